@@ -0,0 +1,91 @@
+// benches/pattern_matcher.rs
+//
+// Throughput of `PatternMatcher` over a synthetic log with a large rule set.
+// Run with `cargo bench --bench pattern_matcher`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rust_hound::analyzer::pattern_matcher::PatternMatcher;
+use rust_hound::config::rules::{MatchMode, PatternConfig, RegexRule, Rules, StringPattern};
+use std::hint::black_box;
+
+const LINES: usize = 100_000;
+const STRING_PATTERNS: usize = 200;
+const REGEX_RULES: usize = 200;
+
+fn synthetic_rules(match_mode: MatchMode) -> Rules {
+    let error_patterns = (0..STRING_PATTERNS / 2)
+        .map(|i| StringPattern::Plain(format!("E{i:04}: subsystem failure")))
+        .collect();
+    let warning_patterns = (0..STRING_PATTERNS / 2)
+        .map(|i| StringPattern::Plain(format!("W{i:04}: degraded")))
+        .collect();
+    let regex_rules = (0..REGEX_RULES)
+        .map(|i| RegexRule {
+            name: format!("rule_{i}"),
+            pattern: format!(r"svc{i}\[\d+\]: (?:timeout|refused) after \d+ms"),
+            severity: if i % 2 == 0 { "high" } else { "medium" }.to_string(),
+            ..Default::default()
+        })
+        .collect();
+
+    Rules {
+        match_mode: Some(match_mode),
+        patterns: PatternConfig {
+            error_patterns,
+            warning_patterns,
+            ..Default::default()
+        },
+        regex_rules,
+        ..Default::default()
+    }
+}
+
+/// Mostly benign lines, with roughly one in fifty hitting a string pattern
+/// and one in fifty hitting a regex rule.
+fn synthetic_log() -> Vec<String> {
+    (0..LINES)
+        .map(|i| match i % 50 {
+            0 => format!(
+                "2024-01-01T00:00:{:02} host app[{i}]: E{:04}: subsystem failure",
+                i % 60,
+                i % 100
+            ),
+            25 => format!(
+                "2024-01-01T00:00:{:02} host svc{}[{i}]: timeout after {}ms",
+                i % 60,
+                i % REGEX_RULES,
+                i % 1000
+            ),
+            _ => format!(
+                "2024-01-01T00:00:{:02} host app[{i}]: request id={i} path=/api/v1/items status=200 took={}ms",
+                i % 60,
+                i % 1000
+            ),
+        })
+        .collect()
+}
+
+fn bench_find_matches(c: &mut Criterion) {
+    let log = synthetic_log();
+    let bytes: usize = log.iter().map(|line| line.len() + 1).sum();
+
+    let mut group = c.benchmark_group("find_matches");
+    group.throughput(Throughput::Bytes(bytes as u64));
+    group.sample_size(10);
+
+    for (label, mode) in [("first", MatchMode::First), ("all", MatchMode::All)] {
+        let matcher = PatternMatcher::new(&synthetic_rules(mode)).unwrap();
+        group.bench_function(label, |b| {
+            b.iter(|| {
+                log.iter()
+                    .map(|line| matcher.find_matches(black_box(line)).len())
+                    .sum::<usize>()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_matches);
+criterion_main!(benches);
@@ -0,0 +1,414 @@
+use crate::analyzer::fields::extract_fields;
+use crate::analyzer::geoip::{self, GeoIp};
+use crate::analyzer::indicators::extract_ips;
+use crate::analyzer::network::{Cidr, NetworkSets};
+use crate::config::rules::{CidrCondition, Condition, FieldCondition};
+use regex::Regex;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+
+/// A line plus lazily computed views of it, shared by every leaf of a
+/// condition tree so extraction happens at most once per line.
+///
+/// Field lookups check fields already attached to a detection first, then
+/// `geo.*` fields from the GeoIP databases, then the line itself.
+pub struct LineContext<'a> {
+    line: &'a str,
+    known_fields: Option<&'a BTreeMap<String, String>>,
+    geoip: Option<&'a GeoIp>,
+    lowercase: OnceCell<String>,
+    fields: OnceCell<HashMap<String, String>>,
+    ips: OnceCell<Vec<IpAddr>>,
+    geo_fields: OnceCell<BTreeMap<String, String>>,
+}
+
+impl<'a> LineContext<'a> {
+    pub fn new(line: &'a str) -> Self {
+        LineContext {
+            line,
+            known_fields: None,
+            geoip: None,
+            lowercase: OnceCell::new(),
+            fields: OnceCell::new(),
+            ips: OnceCell::new(),
+            geo_fields: OnceCell::new(),
+        }
+    }
+
+    /// Fields a detection already carries, e.g. `ioc.*` or `geo.*`.
+    pub fn with_known_fields(mut self, fields: &'a BTreeMap<String, String>) -> Self {
+        self.known_fields = Some(fields);
+        self
+    }
+
+    /// Resolve `geo.*` fields by looking the line up in `geoip`.
+    pub fn with_geoip(mut self, geoip: Option<&'a GeoIp>) -> Self {
+        self.geoip = geoip;
+        self
+    }
+
+    pub fn line(&self) -> &'a str {
+        self.line
+    }
+
+    fn lowercase(&self) -> &str {
+        self.lowercase.get_or_init(|| self.line.to_lowercase())
+    }
+
+    pub fn field(&self, name: &str) -> Option<&str> {
+        if let Some(value) = self.known_fields.and_then(|fields| fields.get(name)) {
+            return Some(value);
+        }
+        if let (true, Some(geoip)) = (name.starts_with(geoip::FIELD_PREFIX), self.geoip) {
+            return self
+                .geo_fields
+                .get_or_init(|| {
+                    geoip
+                        .locate(self)
+                        .map(|info| info.fields())
+                        .unwrap_or_default()
+                })
+                .get(name)
+                .map(String::as_str);
+        }
+        self.extracted_field(name)
+    }
+
+    /// A field extracted from the line itself (`key=value` or JSON).
+    pub fn extracted_field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .get_or_init(|| extract_fields(self.line))
+            .get(name)
+            .map(String::as_str)
+    }
+
+    /// Every IPv4 and IPv6 address in the line.
+    pub fn ips(&self) -> &[IpAddr] {
+        self.ips.get_or_init(|| extract_ips(self.line).collect())
+    }
+}
+
+/// A `Condition` with its regexes compiled and case-folded needles prepared.
+#[derive(Debug)]
+pub enum CompiledCondition {
+    All(Vec<CompiledCondition>),
+    Any(Vec<CompiledCondition>),
+    Not(Box<CompiledCondition>),
+    Contains(String),
+    Icontains(String),
+    Regex(Regex),
+    Field {
+        name: String,
+        predicate: FieldPredicate,
+    },
+    Cidr {
+        field: Option<String>,
+        networks: Vec<Cidr>,
+        /// `true` for `in_cidr`, `false` for `not_in_cidr`
+        inside: bool,
+    },
+}
+
+#[derive(Debug)]
+pub enum FieldPredicate {
+    Exists,
+    Equals {
+        value: String,
+        ignore_case: bool,
+    },
+    Contains {
+        value: String,
+        ignore_case: bool,
+    },
+    Regex(Regex),
+    /// `one_of` when `negate` is false, `not_one_of` when true
+    OneOf {
+        values: HashSet<String>,
+        ignore_case: bool,
+        negate: bool,
+    },
+}
+
+impl CompiledCondition {
+    /// Compile `condition`, resolving network set names against `networks`.
+    pub fn compile(condition: &Condition, networks: &NetworkSets) -> anyhow::Result<Self> {
+        Ok(match condition {
+            Condition::All(conditions) => {
+                CompiledCondition::All(compile_all(conditions, networks)?)
+            }
+            Condition::Any(conditions) => {
+                CompiledCondition::Any(compile_all(conditions, networks)?)
+            }
+            Condition::Not(condition) => {
+                CompiledCondition::Not(Box::new(CompiledCondition::compile(condition, networks)?))
+            }
+            Condition::Contains(needle) => CompiledCondition::Contains(needle.clone()),
+            Condition::Icontains(needle) => CompiledCondition::Icontains(needle.to_lowercase()),
+            Condition::Regex(pattern) => CompiledCondition::Regex(Regex::new(pattern)?),
+            Condition::Field(field) => CompiledCondition::Field {
+                name: field.name.clone(),
+                predicate: compile_field(field)?,
+            },
+            Condition::InCidr(cidr) => compile_cidr(cidr, networks, true)?,
+            Condition::NotInCidr(cidr) => compile_cidr(cidr, networks, false)?,
+        })
+    }
+
+    pub fn matches(&self, context: &LineContext<'_>) -> bool {
+        match self {
+            CompiledCondition::All(conditions) => conditions.iter().all(|c| c.matches(context)),
+            CompiledCondition::Any(conditions) => conditions.iter().any(|c| c.matches(context)),
+            CompiledCondition::Not(condition) => !condition.matches(context),
+            CompiledCondition::Contains(needle) => context.line.contains(needle.as_str()),
+            CompiledCondition::Icontains(needle) => context.lowercase().contains(needle.as_str()),
+            CompiledCondition::Regex(regex) => regex.is_match(context.line),
+            CompiledCondition::Field { name, predicate } => context
+                .field(name)
+                .is_some_and(|value| predicate.matches(value)),
+            CompiledCondition::Cidr {
+                field,
+                networks,
+                inside,
+            } => {
+                let in_networks = |ip: &IpAddr| networks.iter().any(|cidr| cidr.contains(*ip));
+                match field {
+                    Some(name) => context.field(name).is_some_and(|value| {
+                        extract_ips(value).any(|ip| in_networks(&ip) == *inside)
+                    }),
+                    None => context.ips().iter().any(|ip| in_networks(ip) == *inside),
+                }
+            }
+        }
+    }
+}
+
+impl FieldPredicate {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            FieldPredicate::Exists => true,
+            FieldPredicate::Equals {
+                value: expected,
+                ignore_case,
+            } => {
+                if *ignore_case {
+                    value.to_lowercase() == *expected
+                } else {
+                    value == expected
+                }
+            }
+            FieldPredicate::Contains {
+                value: needle,
+                ignore_case,
+            } => {
+                if *ignore_case {
+                    value.to_lowercase().contains(needle.as_str())
+                } else {
+                    value.contains(needle.as_str())
+                }
+            }
+            FieldPredicate::Regex(regex) => regex.is_match(value),
+            FieldPredicate::OneOf {
+                values,
+                ignore_case,
+                negate,
+            } => {
+                let found = if *ignore_case {
+                    values.contains(&value.to_lowercase())
+                } else {
+                    values.contains(value)
+                };
+                found != *negate
+            }
+        }
+    }
+}
+
+fn compile_all(
+    conditions: &[Condition],
+    networks: &NetworkSets,
+) -> anyhow::Result<Vec<CompiledCondition>> {
+    conditions
+        .iter()
+        .map(|condition| CompiledCondition::compile(condition, networks))
+        .collect()
+}
+
+fn compile_cidr(
+    cidr: &CidrCondition,
+    networks: &NetworkSets,
+    inside: bool,
+) -> anyhow::Result<CompiledCondition> {
+    if cidr.networks.is_empty() {
+        return Err(anyhow::anyhow!("CIDR condition needs at least one network"));
+    }
+    Ok(CompiledCondition::Cidr {
+        field: cidr.field.clone(),
+        networks: networks.resolve(&cidr.networks)?,
+        inside,
+    })
+}
+
+fn compile_field(field: &FieldCondition) -> anyhow::Result<FieldPredicate> {
+    let fold = |value: &str| {
+        if field.ignore_case {
+            value.to_lowercase()
+        } else {
+            value.to_string()
+        }
+    };
+    let operators = [
+        field.equals.is_some(),
+        field.contains.is_some(),
+        field.regex.is_some(),
+        !field.one_of.is_empty(),
+        !field.not_one_of.is_empty(),
+    ];
+    if operators.iter().filter(|set| **set).count() > 1 {
+        return Err(anyhow::anyhow!(
+            "field condition on \"{}\" sets more than one of equals, contains, regex, one_of and not_one_of",
+            field.name
+        ));
+    }
+
+    if let Some(value) = &field.equals {
+        return Ok(FieldPredicate::Equals {
+            value: fold(value),
+            ignore_case: field.ignore_case,
+        });
+    }
+    if let Some(value) = &field.contains {
+        return Ok(FieldPredicate::Contains {
+            value: fold(value),
+            ignore_case: field.ignore_case,
+        });
+    }
+    if let Some(pattern) = &field.regex {
+        return Ok(FieldPredicate::Regex(Regex::new(pattern)?));
+    }
+    for (values, negate) in [(&field.one_of, false), (&field.not_one_of, true)] {
+        if !values.is_empty() {
+            return Ok(FieldPredicate::OneOf {
+                values: values.iter().map(|value| fold(value)).collect(),
+                ignore_case: field.ignore_case,
+                negate,
+            });
+        }
+    }
+    Ok(FieldPredicate::Exists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(toml_condition: &str) -> CompiledCondition {
+        #[derive(serde::Deserialize)]
+        struct Wrapper {
+            condition: Condition,
+        }
+        let wrapper: Wrapper = toml::from_str(&format!("condition = {toml_condition}")).unwrap();
+        let networks = NetworkSets::new(&std::collections::BTreeMap::from([(
+            "internal".to_string(),
+            vec!["10.0.0.0/8".to_string(), "fd00::/8".to_string()],
+        )]))
+        .unwrap();
+        CompiledCondition::compile(&wrapper.condition, &networks).unwrap()
+    }
+
+    fn matches(condition: &CompiledCondition, line: &str) -> bool {
+        condition.matches(&LineContext::new(line))
+    }
+
+    #[test]
+    fn combines_all_any_and_not() {
+        let condition = compile(
+            r#"{ all = [
+                { contains = "sshd" },
+                { regex = "Failed password" },
+                { not = { contains = "from 10." } },
+            ] }"#,
+        );
+        assert!(matches(
+            &condition,
+            "sshd[1]: Failed password for root from 203.0.113.5"
+        ));
+        assert!(!matches(
+            &condition,
+            "sshd[1]: Failed password for root from 10.0.0.5"
+        ));
+        assert!(!matches(&condition, "sshd[1]: Accepted password for root"));
+
+        let any = compile(r#"{ any = [{ icontains = "timeout" }, { contains = "FATAL" }] }"#);
+        assert!(matches(&any, "Connection TimeOut"));
+        assert!(!matches(&any, "fatal"));
+    }
+
+    #[test]
+    fn evaluates_field_predicates() {
+        let root = compile(r#"{ field = { name = "user", equals = "ROOT", ignore_case = true } }"#);
+        assert!(matches(&root, "sudo: auth failure; user=root"));
+        assert!(!matches(&root, "sudo: auth failure; user=rooted"));
+        assert!(!matches(&root, "sudo: auth failure"));
+
+        let exists = compile(r#"{ field = { name = "http.status" } }"#);
+        assert!(matches(&exists, r#"{"http":{"status":500}}"#));
+
+        let status = compile(r#"{ field = { name = "status", regex = "^5\\d\\d$" } }"#);
+        assert!(matches(&status, "GET /admin status=503"));
+        assert!(!matches(&status, "GET /admin status=200"));
+    }
+
+    #[test]
+    fn matches_field_value_lists() {
+        let allowed = compile(
+            r#"{ field = { name = "geo.country", one_of = ["tr", "de"], ignore_case = true } }"#,
+        );
+        let context = |country: &str| format!(r#"{{"geo":{{"country":"{country}"}}}}"#);
+        assert!(matches(&allowed, &context("TR")));
+        assert!(!matches(&allowed, &context("BR")));
+
+        let outside = compile(r#"{ field = { name = "geo.country", not_one_of = ["TR", "DE"] } }"#);
+        assert!(matches(&outside, &context("BR")));
+        assert!(!matches(&outside, &context("DE")));
+        assert!(!matches(&outside, "no country here"));
+
+        let fields = BTreeMap::from([("geo.country".to_string(), "US".to_string())]);
+        assert!(outside.matches(&LineContext::new("line").with_known_fields(&fields)));
+    }
+
+    #[test]
+    fn rejects_multiple_field_operators() {
+        let condition = Condition::Field(FieldCondition {
+            name: "user".to_string(),
+            equals: Some("a".to_string()),
+            contains: Some("b".to_string()),
+            ..Default::default()
+        });
+        assert!(CompiledCondition::compile(&condition, &NetworkSets::default()).is_err());
+    }
+
+    #[test]
+    fn tests_ip_addresses_against_networks() {
+        let outside = compile(r#"{ not_in_cidr = { networks = ["internal"] } }"#);
+        assert!(matches(
+            &outside,
+            "Failed password from 203.0.113.5 port 22"
+        ));
+        assert!(!matches(&outside, "Failed password from 10.1.2.3 port 22"));
+        assert!(matches(&outside, "relay 10.1.2.3 -> 2001:db8::7"));
+        assert!(!matches(&outside, "Failed password for root"));
+
+        let metadata = compile(r#"{ in_cidr = { networks = ["169.254.169.254"] } }"#);
+        assert!(matches(
+            &metadata,
+            "GET http://169.254.169.254/latest/meta-data"
+        ));
+        assert!(!matches(&metadata, "GET http://169.254.169.253/"));
+
+        let source = compile(r#"{ in_cidr = { field = "src", networks = ["internal"] } }"#);
+        assert!(matches(&source, "conn src=fd00::1 dst=203.0.113.5"));
+        assert!(!matches(&source, "conn src=203.0.113.5 dst=10.0.0.1"));
+        assert!(!matches(&source, "conn dst=10.0.0.1"));
+    }
+}
@@ -1,31 +1,117 @@
-use crate::config::rules::CorrelatedRule;
-use crate::output::{Detection, Severity};
-use std::collections::VecDeque;
+use crate::analyzer::condition::LineContext;
+use crate::analyzer::indicators::extract_ips;
+use crate::analyzer::network::{mask, Cidr, NetworkSets};
+use crate::config::rules::{CorrelatedRule, JoinKey};
+use crate::output::{Detection, DetectionKind, Severity};
+use std::collections::{BTreeMap, VecDeque};
+use std::net::IpAddr;
 use std::time::{Duration, Instant};
 
 struct Event {
     timestamp: Instant,
     detection: Detection,
+    /// Join key of this event for each rule, `None` when the rule has no
+    /// join or the line has no key
+    keys: Vec<Option<String>>,
 }
 
 pub struct CorrelationEngine {
     rules: Vec<CorrelatedRule>,
+    join_keys: Vec<Option<CompiledJoinKey>>,
     recent_events: VecDeque<Event>,
 }
 
+/// A `JoinKey` with its network set names resolved.
+#[derive(Debug)]
+pub struct CompiledJoinKey {
+    field: Option<String>,
+    is_ip: bool,
+    in_cidr: Vec<Cidr>,
+    not_in_cidr: Vec<Cidr>,
+    ipv4_prefix: Option<u8>,
+    ipv6_prefix: Option<u8>,
+}
+
+impl CompiledJoinKey {
+    pub fn compile(join: &JoinKey, networks: &NetworkSets) -> anyhow::Result<Self> {
+        if join.ipv4_prefix.is_some_and(|prefix| prefix > 32) {
+            return Err(anyhow::anyhow!("ipv4_prefix must be at most 32"));
+        }
+        if join.ipv6_prefix.is_some_and(|prefix| prefix > 128) {
+            return Err(anyhow::anyhow!("ipv6_prefix must be at most 128"));
+        }
+        Ok(CompiledJoinKey {
+            field: join.field.clone(),
+            is_ip: join.is_ip(),
+            in_cidr: networks.resolve(&join.in_cidr)?,
+            not_in_cidr: networks.resolve(&join.not_in_cidr)?,
+            ipv4_prefix: join.ipv4_prefix,
+            ipv6_prefix: join.ipv6_prefix,
+        })
+    }
+
+    /// The key of `line`, if it has one.
+    pub fn key(&self, line: &str) -> Option<String> {
+        let context = LineContext::new(line);
+        if !self.is_ip {
+            return context.field(self.field.as_deref()?).map(str::to_string);
+        }
+
+        let ip = match &self.field {
+            Some(name) => extract_ips(context.field(name)?).find(|ip| self.accepts(ip)),
+            None => context.ips().iter().copied().find(|ip| self.accepts(ip)),
+        }?;
+        let prefix = match ip {
+            IpAddr::V4(_) => self.ipv4_prefix,
+            IpAddr::V6(_) => self.ipv6_prefix,
+        };
+        Some(match prefix {
+            Some(prefix) => format!("{}/{prefix}", mask(ip, prefix)),
+            None => ip.to_string(),
+        })
+    }
+
+    fn accepts(&self, ip: &IpAddr) -> bool {
+        (self.in_cidr.is_empty() || self.in_cidr.iter().any(|cidr| cidr.contains(*ip)))
+            && !self.not_in_cidr.iter().any(|cidr| cidr.contains(*ip))
+    }
+}
+
 impl CorrelationEngine {
-    pub fn new(rules: Vec<CorrelatedRule>) -> Self {
-        Self {
+    /// Build an engine for `rules`, resolving network set names in their
+    /// join keys against `networks`.
+    pub fn new(rules: Vec<CorrelatedRule>, networks: &NetworkSets) -> anyhow::Result<Self> {
+        let join_keys = rules
+            .iter()
+            .map(|rule| {
+                rule.join
+                    .as_ref()
+                    .map(|join| CompiledJoinKey::compile(join, networks))
+                    .transpose()
+                    .map_err(|e| anyhow::anyhow!("join of correlated rule \"{}\": {e}", rule.name))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
             rules,
+            join_keys,
             recent_events: VecDeque::new(),
-        }
+        })
     }
 
     pub fn add_detection(&mut self, detection: Detection) -> Option<Detection> {
         let now = Instant::now();
+        let keys = self
+            .join_keys
+            .iter()
+            .map(|join| {
+                join.as_ref()
+                    .and_then(|join| join.key(&detection.matched_line))
+            })
+            .collect();
         self.recent_events.push_back(Event {
             timestamp: now,
             detection: detection.clone(),
+            keys,
         });
 
         // Remove old events
@@ -54,35 +140,45 @@ impl CorrelationEngine {
     }
 
     fn check_rules(&self) -> Option<Detection> {
-        for rule in &self.rules {
+        for (index, rule) in self.rules.iter().enumerate() {
             let window_start = Instant::now() - Duration::from_secs(rule.time_window_seconds);
+            let joined = self.join_keys[index].is_some();
 
             // Find the 'followed_by' event first
-            if let Some(followed_by_event_pos) = self
-                .recent_events
-                .iter()
-                .rposition(|e| e.detection.pattern_name == rule.followed_by)
-            {
+            if let Some(followed_by_event_pos) = self.recent_events.iter().rposition(|e| {
+                e.detection.pattern_name == rule.followed_by && (!joined || e.keys[index].is_some())
+            }) {
                 let followed_by_event = &self.recent_events[followed_by_event_pos];
+                let key = &followed_by_event.keys[index];
 
-                // Count trigger events that occurred *before* the 'followed_by' event
-                let trigger_count = self
+                // Collect trigger events that occurred *before* the 'followed_by' event
+                let trigger_lines: Vec<usize> = self
                     .recent_events
                     .iter()
                     .take(followed_by_event_pos)
                     .filter(|e| {
                         e.timestamp >= window_start
                             && e.detection.pattern_name == rule.trigger_on_rule.name
+                            && (!joined || e.keys[index] == *key)
                     })
-                    .count();
+                    .map(|e| e.detection.line_number)
+                    .collect();
 
-                if trigger_count >= rule.trigger_on_rule.count {
+                if trigger_lines.len() >= rule.trigger_on_rule.count {
+                    let fields = key
+                        .iter()
+                        .map(|key| ("correlation.key".to_string(), key.clone()))
+                        .collect::<BTreeMap<_, _>>();
                     return Some(Detection {
+                        kind: DetectionKind::Correlation,
                         severity: Severity::from(rule.severity.as_str()),
                         file_path: followed_by_event.detection.file_path.clone(),
                         line_number: followed_by_event.detection.line_number,
                         matched_line: followed_by_event.detection.matched_line.clone(),
                         pattern_name: rule.name.clone(),
+                        description: rule.description.clone(),
+                        related_lines: trigger_lines,
+                        fields,
                     });
                 }
             }
@@ -95,15 +191,19 @@ impl CorrelationEngine {
 mod tests {
     use super::*;
     use crate::config::rules::{CorrelatedRule, TriggerRule};
-    use crate::output::{Detection, Severity};
+    use crate::output::{Detection, DetectionKind, Severity};
 
     fn sample_detection(pattern_name: &str, line: usize) -> Detection {
         Detection {
+            kind: DetectionKind::Pattern,
             severity: Severity::High,
             file_path: "/var/log/auth.log".to_string(),
             line_number: line,
             pattern_name: pattern_name.to_string(),
             matched_line: "sample line".to_string(),
+            description: String::new(),
+            related_lines: Vec::new(),
+            fields: Default::default(),
         }
     }
 
@@ -118,12 +218,14 @@ mod tests {
                 count: 3,
             },
             followed_by: "Successful Login".to_string(),
+            ..Default::default()
         }]
     }
 
     #[test]
     fn triggers_after_enough_failures_then_success() {
-        let mut engine = CorrelationEngine::new(brute_force_rules());
+        let mut engine =
+            CorrelationEngine::new(brute_force_rules(), &NetworkSets::default()).unwrap();
         assert!(engine
             .add_detection(sample_detection("authentication_failure", 1))
             .is_none());
@@ -138,11 +240,54 @@ mod tests {
             .expect("correlation should fire");
         assert_eq!(correlated.pattern_name, "Potential Brute-Force Attack");
         assert_eq!(correlated.severity, Severity::Critical);
+        assert_eq!(correlated.related_lines, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn joins_trigger_and_follow_up_on_ip_network() {
+        let mut rules = brute_force_rules();
+        rules[0].join = Some(JoinKey {
+            not_in_cidr: vec!["10.0.0.0/8".to_string()],
+            ipv4_prefix: Some(24),
+            ..Default::default()
+        });
+        let mut engine = CorrelationEngine::new(rules, &NetworkSets::default()).unwrap();
+        let event = |name: &str, line: usize, text: &str| {
+            let mut detection = sample_detection(name, line);
+            detection.matched_line = text.to_string();
+            detection
+        };
+
+        for (line, source) in [(1, "203.0.113.5"), (2, "203.0.113.9"), (3, "198.51.100.1")] {
+            let text = format!("relay 10.0.0.1 failed login from {source}");
+            assert!(engine
+                .add_detection(event("authentication_failure", line, &text))
+                .is_none());
+        }
+        // Only two failures share 203.0.113.0/24 so far
+        assert!(engine
+            .add_detection(event("Successful Login", 4, "login from 203.0.113.7"))
+            .is_none());
+        engine.add_detection(event(
+            "authentication_failure",
+            5,
+            "failed from 203.0.113.20",
+        ));
+        let correlated = engine
+            .add_detection(event("Successful Login", 6, "login from 203.0.113.7"))
+            .expect("correlation should fire");
+        assert_eq!(
+            correlated.fields.get("correlation.key").map(String::as_str),
+            Some("203.0.113.0/24")
+        );
+        // The failure from 198.51.100.1 is in another network
+        assert_eq!(correlated.related_lines, vec![1, 2, 5]);
     }
 
     #[test]
     fn no_trigger_without_followed_by_event() {
-        let mut engine = CorrelationEngine::new(brute_force_rules());
+        let mut engine =
+            CorrelationEngine::new(brute_force_rules(), &NetworkSets::default()).unwrap();
         for i in 1..=5 {
             assert!(engine
                 .add_detection(sample_detection("authentication_failure", i))
@@ -0,0 +1,88 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+fn key_value_regex() -> &'static Regex {
+    static KEY_VALUE: OnceLock<Regex> = OnceLock::new();
+    KEY_VALUE.get_or_init(|| {
+        Regex::new(r#"(?:^|[\s,;(\[])([A-Za-z_][\w.-]*)=(?:"([^"]*)"|([^\s,;)\]]*))"#).unwrap()
+    })
+}
+
+/// Extract named fields from a log line.
+///
+/// A line holding a JSON object yields its keys (nested objects flattened
+/// with `.`); any other line yields its `key=value` / `key="quoted value"`
+/// pairs. Later duplicates of a key are ignored.
+pub fn extract_fields(line: &str) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+
+    let trimmed = line.trim();
+    if trimmed.starts_with('{') {
+        if let Ok(serde_json::Value::Object(object)) = serde_json::from_str(trimmed) {
+            flatten_json("", &object, &mut fields);
+            return fields;
+        }
+    }
+
+    for captures in key_value_regex().captures_iter(line) {
+        let value = captures
+            .get(2)
+            .or_else(|| captures.get(3))
+            .map_or("", |m| m.as_str());
+        fields
+            .entry(captures[1].to_string())
+            .or_insert_with(|| value.to_string());
+    }
+    fields
+}
+
+fn flatten_json(
+    prefix: &str,
+    object: &serde_json::Map<String, serde_json::Value>,
+    fields: &mut HashMap<String, String>,
+) {
+    for (key, value) in object {
+        let name = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match value {
+            serde_json::Value::Object(nested) => flatten_json(&name, nested, fields),
+            serde_json::Value::String(s) => {
+                fields.insert(name, s.clone());
+            }
+            other => {
+                fields.insert(name, other.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_key_value_pairs() {
+        let fields = extract_fields(
+            "pam_unix(sudo:auth): authentication failure; logname=user uid=1000 rhost=  user=root msg=\"a b\"",
+        );
+        assert_eq!(fields["logname"], "user");
+        assert_eq!(fields["uid"], "1000");
+        assert_eq!(fields["rhost"], "");
+        assert_eq!(fields["user"], "root");
+        assert_eq!(fields["msg"], "a b");
+    }
+
+    #[test]
+    fn extracts_flattened_json_keys() {
+        let fields =
+            extract_fields(r#"{"level":"error","http":{"status":500,"path":"/admin"},"ok":false}"#);
+        assert_eq!(fields["level"], "error");
+        assert_eq!(fields["http.status"], "500");
+        assert_eq!(fields["http.path"], "/admin");
+        assert_eq!(fields["ok"], "false");
+    }
+}
@@ -0,0 +1,416 @@
+use crate::analyzer::condition::LineContext;
+use crate::config::rules::GeoIpConfig;
+use crate::output::Detection;
+use anyhow::Context;
+use maxminddb::Reader;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// Field name prefix of everything the GeoIP stage attaches.
+pub const FIELD_PREFIX: &str = "geo.";
+
+/// Offline GeoIP / ASN lookups against MaxMind-format (mmdb) databases.
+///
+/// Every database is consulted for each address and the results merged, so
+/// a City and an ASN database can be combined.
+pub struct GeoIp {
+    databases: Vec<(PathBuf, Reader<Vec<u8>>)>,
+    fields: Vec<String>,
+}
+
+/// What the databases know about one address.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeoInfo {
+    pub ip: Option<IpAddr>,
+    /// ISO 3166-1 alpha-2 code
+    pub country: Option<String>,
+    pub country_name: Option<String>,
+    pub city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+/// The subset of the GeoIP2 / GeoLite2 City, Country and ASN schemas used
+/// for enrichment.
+#[derive(Deserialize, Default)]
+struct Record<'a> {
+    #[serde(borrow, default)]
+    country: Option<Place<'a>>,
+    #[serde(borrow, default)]
+    city: Option<Place<'a>>,
+    #[serde(default)]
+    location: Option<Location>,
+    #[serde(default)]
+    autonomous_system_number: Option<u32>,
+    #[serde(borrow, default)]
+    autonomous_system_organization: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct Place<'a> {
+    #[serde(borrow, default)]
+    iso_code: Option<&'a str>,
+    #[serde(borrow, default)]
+    names: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(Deserialize)]
+struct Location {
+    #[serde(default)]
+    latitude: Option<f64>,
+    #[serde(default)]
+    longitude: Option<f64>,
+}
+
+impl GeoIp {
+    pub fn open(config: &GeoIpConfig) -> anyhow::Result<Self> {
+        let mut databases = Vec::with_capacity(config.databases.len());
+        for path in &config.databases {
+            let reader = Reader::open_readfile(path)
+                .with_context(|| format!("Failed to open GeoIP database {}", path.display()))?;
+            databases.push((path.clone(), reader));
+        }
+        Ok(GeoIp {
+            databases,
+            fields: config.fields.clone(),
+        })
+    }
+
+    /// Everything the databases know about `ip`, or `None` if none of them
+    /// has a record for it.
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let mut info = GeoInfo {
+            ip: Some(ip),
+            ..Default::default()
+        };
+        let mut found = false;
+        for (path, reader) in &self.databases {
+            // IPv6 lookups fail on IPv4-only databases; that is just a miss
+            let Ok(result) = reader.lookup(ip) else {
+                continue;
+            };
+            let record = match result.decode::<Record>() {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("GeoIP lookup of {ip} in {} failed: {e}", path.display());
+                    continue;
+                }
+            };
+            found = true;
+            if let Some(country) = record.country {
+                info.country = info.country.or(country.iso_code.map(str::to_string));
+                info.country_name = info
+                    .country_name
+                    .or(country.names.get("en").map(|name| name.to_string()));
+            }
+            if let Some(city) = record.city {
+                info.city = info
+                    .city
+                    .or(city.names.get("en").map(|name| name.to_string()));
+            }
+            if let Some(location) = record.location {
+                info.latitude = info.latitude.or(location.latitude);
+                info.longitude = info.longitude.or(location.longitude);
+            }
+            info.asn = info.asn.or(record.autonomous_system_number);
+            info.as_org = info
+                .as_org
+                .or(record.autonomous_system_organization.map(str::to_string));
+        }
+        found.then_some(info)
+    }
+
+    /// Location of the line: the first address in the configured fields, or
+    /// in the whole line when none are configured, that the databases know.
+    pub fn locate(&self, context: &LineContext<'_>) -> Option<GeoInfo> {
+        if self.fields.is_empty() {
+            return context.ips().iter().find_map(|ip| self.lookup(*ip));
+        }
+        self.fields
+            .iter()
+            .filter_map(|name| context.extracted_field(name))
+            .flat_map(crate::analyzer::indicators::extract_ips)
+            .find_map(|ip| self.lookup(ip))
+    }
+
+    /// Attach `geo.*` fields describing the detection's line.
+    pub fn enrich(&self, detection: &mut Detection) {
+        if detection
+            .fields
+            .keys()
+            .any(|key| key.starts_with(FIELD_PREFIX))
+        {
+            return;
+        }
+        let context = LineContext::new(&detection.matched_line);
+        if let Some(info) = self.locate(&context) {
+            detection.fields.extend(info.fields());
+        }
+    }
+}
+
+impl GeoInfo {
+    /// The `geo.*` fields for this location.
+    pub fn fields(&self) -> BTreeMap<String, String> {
+        let mut fields = BTreeMap::new();
+        let mut put = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                fields.insert(format!("{FIELD_PREFIX}{name}"), value);
+            }
+        };
+        put("ip", self.ip.map(|ip| ip.to_string()));
+        put("country", self.country.clone());
+        put("country_name", self.country_name.clone());
+        put("city", self.city.clone());
+        put("latitude", self.latitude.map(|v| v.to_string()));
+        put("longitude", self.longitude.map(|v| v.to_string()));
+        put("asn", self.asn.map(|v| v.to_string()));
+        put("as_org", self.as_org.clone());
+        fields
+    }
+}
+
+/// Builds tiny MaxMind-format databases for tests.
+#[cfg(test)]
+pub(crate) mod test_db {
+    use crate::analyzer::network::Cidr;
+    use std::net::IpAddr;
+    use std::path::Path;
+
+    pub enum Value {
+        Str(&'static str),
+        U32(u32),
+        F64(f64),
+        Map(Vec<(&'static str, Value)>),
+    }
+
+    /// A GeoIP2 City style record.
+    pub fn city(country: &'static str, name: &'static str, lat: f64, lon: f64) -> Value {
+        Value::Map(vec![
+            (
+                "country",
+                Value::Map(vec![
+                    ("iso_code", Value::Str(country)),
+                    ("names", Value::Map(vec![("en", Value::Str(country))])),
+                ]),
+            ),
+            (
+                "city",
+                Value::Map(vec![("names", Value::Map(vec![("en", Value::Str(name))]))]),
+            ),
+            (
+                "location",
+                Value::Map(vec![
+                    ("latitude", Value::F64(lat)),
+                    ("longitude", Value::F64(lon)),
+                ]),
+            ),
+        ])
+    }
+
+    /// A GeoLite2 ASN style record.
+    pub fn asn(number: u32, organization: &'static str) -> Value {
+        Value::Map(vec![
+            ("autonomous_system_number", Value::U32(number)),
+            ("autonomous_system_organization", Value::Str(organization)),
+        ])
+    }
+
+    fn control(out: &mut Vec<u8>, kind: u8, size: usize) {
+        assert!(size < 285, "test values are small");
+        let size_bits = size.min(29) as u8;
+        if kind <= 7 {
+            out.push((kind << 5) | size_bits);
+        } else {
+            out.push(size_bits);
+            out.push(kind - 7);
+        }
+        if size >= 29 {
+            out.push((size - 29) as u8);
+        }
+    }
+
+    fn encode(value: &Value, out: &mut Vec<u8>) {
+        match value {
+            Value::Str(s) => {
+                control(out, 2, s.len());
+                out.extend_from_slice(s.as_bytes());
+            }
+            Value::F64(v) => {
+                control(out, 3, 8);
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+            Value::U32(v) => {
+                control(out, 6, 4);
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+            Value::Map(entries) => {
+                control(out, 7, entries.len());
+                for (key, value) in entries {
+                    encode(&Value::Str(key), out);
+                    encode(value, out);
+                }
+            }
+        }
+    }
+
+    /// The 128 key bits of `ip`, with IPv4 placed under `::/96`.
+    fn bits(ip: IpAddr) -> u128 {
+        match ip {
+            IpAddr::V4(v4) => u128::from(u32::from(v4)),
+            IpAddr::V6(v6) => u128::from(v6),
+        }
+    }
+
+    /// Write an IPv6 database (24-bit records) mapping each network to its
+    /// record.
+    pub fn write(path: &Path, entries: Vec<(&str, Value)>) {
+        const EMPTY: u32 = u32::MAX;
+        // Records: EMPTY, node index, or DATA_FLAG | data offset
+        const DATA_FLAG: u32 = 1 << 31;
+        let mut nodes: Vec<[u32; 2]> = vec![[EMPTY, EMPTY]];
+        let mut data = Vec::new();
+
+        for (network, value) in entries {
+            let cidr: Cidr = network.parse().unwrap();
+            let (ip, prefix) = (cidr.network(), cidr.prefix());
+            let depth = if ip.is_ipv4() { prefix + 96 } else { prefix } as usize;
+            let key = bits(ip);
+            let offset = data.len() as u32;
+            encode(&value, &mut data);
+
+            let mut node = 0;
+            for bit_index in 0..depth {
+                let bit = ((key >> (127 - bit_index)) & 1) as usize;
+                if bit_index + 1 == depth {
+                    nodes[node][bit] = DATA_FLAG | offset;
+                } else {
+                    if nodes[node][bit] == EMPTY {
+                        nodes.push([EMPTY, EMPTY]);
+                        nodes[node][bit] = (nodes.len() - 1) as u32;
+                    }
+                    node = nodes[node][bit] as usize;
+                }
+            }
+        }
+
+        let node_count = nodes.len() as u32;
+        let mut out = Vec::new();
+        for node in &nodes {
+            for record in node {
+                let value = match *record {
+                    EMPTY => node_count,
+                    r if r & DATA_FLAG != 0 => node_count + 16 + (r & !DATA_FLAG),
+                    r => r,
+                };
+                out.extend_from_slice(&value.to_be_bytes()[1..]);
+            }
+        }
+        out.extend_from_slice(&[0; 16]);
+        out.extend_from_slice(&data);
+        out.extend_from_slice(b"\xAB\xCD\xEFMaxMind.com");
+
+        let mut metadata = Vec::new();
+        control(&mut metadata, 7, 9);
+        let uint = |out: &mut Vec<u8>, key: &'static str, kind: u8, bytes: &[u8]| {
+            encode(&Value::Str(key), out);
+            control(out, kind, bytes.len());
+            out.extend_from_slice(bytes);
+        };
+        uint(
+            &mut metadata,
+            "binary_format_major_version",
+            5,
+            &2u16.to_be_bytes(),
+        );
+        uint(
+            &mut metadata,
+            "binary_format_minor_version",
+            5,
+            &0u16.to_be_bytes(),
+        );
+        uint(&mut metadata, "build_epoch", 9, &0u64.to_be_bytes());
+        uint(&mut metadata, "ip_version", 5, &6u16.to_be_bytes());
+        uint(&mut metadata, "node_count", 6, &node_count.to_be_bytes());
+        uint(&mut metadata, "record_size", 5, &24u16.to_be_bytes());
+        encode(&Value::Str("database_type"), &mut metadata);
+        encode(&Value::Str("RustHound-Test"), &mut metadata);
+        encode(&Value::Str("description"), &mut metadata);
+        encode(&Value::Map(vec![]), &mut metadata);
+        encode(&Value::Str("languages"), &mut metadata);
+        control(&mut metadata, 11, 0);
+        out.extend_from_slice(&metadata);
+
+        std::fs::write(path, out).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::console::create_detection;
+    use std::path::Path;
+
+    fn open_test_databases() -> (tempfile::TempDir, GeoIp) {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        test_db::write(
+            &dir.join("city.mmdb"),
+            vec![
+                (
+                    "203.0.113.0/24",
+                    test_db::city("TR", "Istanbul", 41.0, 29.0),
+                ),
+                ("2001:db8::/32", test_db::city("DE", "Berlin", 52.5, 13.4)),
+            ],
+        );
+        test_db::write(
+            &dir.join("asn.mmdb"),
+            vec![("203.0.113.0/25", test_db::asn(64500, "Example Net"))],
+        );
+        let geoip = GeoIp::open(&GeoIpConfig {
+            databases: vec![dir.join("city.mmdb"), dir.join("asn.mmdb")],
+            fields: Vec::new(),
+        })
+        .unwrap();
+        (scratch, geoip)
+    }
+
+    #[test]
+    fn merges_city_and_asn_databases() {
+        let (_scratch, geoip) = open_test_databases();
+        let info = geoip.lookup("203.0.113.7".parse().unwrap()).unwrap();
+        assert_eq!(info.country.as_deref(), Some("TR"));
+        assert_eq!(info.city.as_deref(), Some("Istanbul"));
+        assert_eq!(info.latitude, Some(41.0));
+        assert_eq!(info.asn, Some(64500));
+        assert_eq!(info.as_org.as_deref(), Some("Example Net"));
+
+        let outside_asn = geoip.lookup("203.0.113.200".parse().unwrap()).unwrap();
+        assert_eq!(outside_asn.asn, None);
+        let v6 = geoip.lookup("2001:db8::1".parse().unwrap()).unwrap();
+        assert_eq!(v6.country.as_deref(), Some("DE"));
+        assert!(geoip.lookup("10.0.0.1".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn enriches_detections_from_the_first_known_address() {
+        let (_scratch, geoip) = open_test_databases();
+        let mut detection = create_detection(
+            "high",
+            Path::new("auth.log"),
+            1,
+            "Accepted password for root from 10.0.0.1 via 203.0.113.7",
+            "successful_login",
+        );
+        geoip.enrich(&mut detection);
+        assert_eq!(detection.fields["geo.ip"], "203.0.113.7");
+        assert_eq!(detection.fields["geo.country"], "TR");
+        assert_eq!(detection.fields["geo.asn"], "64500");
+    }
+}
@@ -0,0 +1,212 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::OnceLock;
+
+/// The kinds of indicator that can be pulled out of a log line.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum IndicatorKind {
+    Ipv4,
+    Ipv6,
+    Domain,
+    /// MD5, SHA-1 or SHA-256 hex digest
+    Hash,
+}
+
+impl IndicatorKind {
+    pub const ALL: [IndicatorKind; 4] = [
+        IndicatorKind::Ipv4,
+        IndicatorKind::Ipv6,
+        IndicatorKind::Domain,
+        IndicatorKind::Hash,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IndicatorKind::Ipv4 => "ipv4",
+            IndicatorKind::Ipv6 => "ipv6",
+            IndicatorKind::Domain => "domain",
+            IndicatorKind::Hash => "hash",
+        }
+    }
+}
+
+impl fmt::Display for IndicatorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An indicator in normalized form: IPs parsed, domains and hashes
+/// lowercased, so equal indicators compare equal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Indicator {
+    Ip(IpAddr),
+    Domain(String),
+    Hash(String),
+}
+
+impl Indicator {
+    pub fn kind(&self) -> IndicatorKind {
+        match self {
+            Indicator::Ip(IpAddr::V4(_)) => IndicatorKind::Ipv4,
+            Indicator::Ip(IpAddr::V6(_)) => IndicatorKind::Ipv6,
+            Indicator::Domain(_) => IndicatorKind::Domain,
+            Indicator::Hash(_) => IndicatorKind::Hash,
+        }
+    }
+
+    /// Classify a single indicator as written in an IOC list.
+    pub fn parse(value: &str) -> Option<Indicator> {
+        let value = value.trim();
+        if let Ok(ip) = value
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+        {
+            return Some(Indicator::Ip(ip));
+        }
+        if hash_regex().is_match(value) && is_hash_length(value.len()) {
+            return Some(Indicator::Hash(value.to_ascii_lowercase()));
+        }
+        let domain = value.trim_end_matches('.').to_ascii_lowercase();
+        domain_regex()
+            .find(&domain)
+            .filter(|m| m.len() == domain.len())
+            .map(|_| Indicator::Domain(domain.clone()))
+    }
+}
+
+impl fmt::Display for Indicator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Indicator::Ip(ip) => write!(f, "{ip}"),
+            Indicator::Domain(value) | Indicator::Hash(value) => f.write_str(value),
+        }
+    }
+}
+
+fn ipv4_regex() -> &'static Regex {
+    static IPV4: OnceLock<Regex> = OnceLock::new();
+    IPV4.get_or_init(|| Regex::new(r"\b(?:\d{1,3}\.){3}\d{1,3}\b").unwrap())
+}
+
+fn domain_regex() -> &'static Regex {
+    static DOMAIN: OnceLock<Regex> = OnceLock::new();
+    DOMAIN.get_or_init(|| {
+        Regex::new(
+            r"(?i)\b(?:[a-z0-9](?:[a-z0-9-]{0,61}[a-z0-9])?\.)+[a-z][a-z0-9-]{0,61}[a-z0-9]\b",
+        )
+        .unwrap()
+    })
+}
+
+fn hash_regex() -> &'static Regex {
+    static HASH: OnceLock<Regex> = OnceLock::new();
+    HASH.get_or_init(|| Regex::new(r"(?i)\b[0-9a-f]{32,64}\b").unwrap())
+}
+
+fn is_hash_length(len: usize) -> bool {
+    matches!(len, 32 | 40 | 64)
+}
+
+/// IPv4 addresses in `text`.
+pub fn extract_ipv4(text: &str) -> impl Iterator<Item = Ipv4Addr> + '_ {
+    ipv4_regex()
+        .find_iter(text)
+        .filter_map(|m| m.as_str().parse().ok())
+}
+
+/// IPv6 addresses in `text`, found by parsing every run of hex digits,
+/// colons and dots that contains at least two colons.
+pub fn extract_ipv6(text: &str) -> impl Iterator<Item = Ipv6Addr> + '_ {
+    text.split(|c: char| !(c.is_ascii_hexdigit() || c == ':' || c == '.'))
+        .filter(|token| token.matches(':').count() >= 2)
+        .filter_map(|token| {
+            token
+                .trim_end_matches('.')
+                .parse()
+                .or_else(|_| token.trim_end_matches([':', '.']).parse())
+                .ok()
+        })
+}
+
+/// Every IPv4 and IPv6 address in `text`, in that order.
+pub fn extract_ips(text: &str) -> impl Iterator<Item = IpAddr> + '_ {
+    extract_ipv4(text)
+        .map(IpAddr::V4)
+        .chain(extract_ipv6(text).map(IpAddr::V6))
+}
+
+/// Indicators of the requested kinds found in `text`.
+pub fn extract_indicators(text: &str, kinds: &[IndicatorKind]) -> Vec<Indicator> {
+    let mut indicators = Vec::new();
+    if kinds.contains(&IndicatorKind::Ipv4) {
+        indicators.extend(extract_ipv4(text).map(|ip| Indicator::Ip(ip.into())));
+    }
+    if kinds.contains(&IndicatorKind::Ipv6) {
+        indicators.extend(extract_ipv6(text).map(|ip| Indicator::Ip(ip.into())));
+    }
+    if kinds.contains(&IndicatorKind::Domain) {
+        indicators.extend(
+            domain_regex()
+                .find_iter(text)
+                .map(|m| Indicator::Domain(m.as_str().to_ascii_lowercase())),
+        );
+    }
+    if kinds.contains(&IndicatorKind::Hash) {
+        indicators.extend(
+            hash_regex()
+                .find_iter(text)
+                .filter(|m| is_hash_length(m.len()))
+                .map(|m| Indicator::Hash(m.as_str().to_ascii_lowercase())),
+        );
+    }
+    indicators
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_each_indicator_kind() {
+        let line = "2024-01-01T10:00:00 conn from 203.0.113.5 to [2001:db8::1]:443 \
+                    host=Evil.Example.COM sha256=E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855 \
+                    mac=aa:bb:cc:dd:ee:ff bogus=999.1.1.1";
+        let found: Vec<String> = extract_indicators(line, &IndicatorKind::ALL)
+            .iter()
+            .map(|i| format!("{}:{i}", i.kind()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                "ipv4:203.0.113.5",
+                "ipv6:2001:db8::1",
+                "domain:evil.example.com",
+                "hash:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_list_entries() {
+        assert_eq!(
+            Indicator::parse(" 10.0.0.1 "),
+            Some(Indicator::Ip("10.0.0.1".parse().unwrap()))
+        );
+        assert_eq!(
+            Indicator::parse("Bad-Domain.example."),
+            Some(Indicator::Domain("bad-domain.example".to_string()))
+        );
+        assert_eq!(
+            Indicator::parse("D41D8CD98F00B204E9800998ECF8427E"),
+            Some(Indicator::Hash(
+                "d41d8cd98f00b204e9800998ecf8427e".to_string()
+            ))
+        );
+        assert_eq!(Indicator::parse("not an indicator"), None);
+    }
+}
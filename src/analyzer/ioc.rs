@@ -0,0 +1,311 @@
+use crate::analyzer::fields::extract_fields;
+use crate::analyzer::indicators::{extract_indicators, Indicator, IndicatorKind};
+use crate::config::rules::{IocFormat, IocList};
+use anyhow::Context;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often a list file's modification time is checked during matching.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Every `[[ioc_lists]]` entry with its indicators loaded into a lookup set.
+/// List files are reloaded when their modification time changes.
+#[derive(Default)]
+pub struct IocMatcher {
+    lists: Vec<LoadedIocList>,
+}
+
+/// An indicator from `line` found in one of the lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IocMatch<'a> {
+    pub list: &'a str,
+    pub severity: &'a str,
+    pub indicator: Indicator,
+}
+
+struct LoadedIocList {
+    config: IocList,
+    kinds: Vec<IndicatorKind>,
+    indicators: RwLock<HashSet<Indicator>>,
+    reload: Mutex<ReloadState>,
+    reload_interval: Duration,
+}
+
+struct ReloadState {
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl IocMatcher {
+    pub fn new(lists: &[IocList]) -> anyhow::Result<Self> {
+        let mut loaded = Vec::with_capacity(lists.len());
+        for list in lists {
+            let indicators = load_indicators(list)
+                .with_context(|| format!("Failed to load IOC list \"{}\"", list.name))?;
+            let kinds = if list.types.is_empty() {
+                IndicatorKind::ALL.to_vec()
+            } else {
+                list.types.clone()
+            };
+            loaded.push(LoadedIocList {
+                config: list.clone(),
+                kinds,
+                indicators: RwLock::new(indicators),
+                reload: Mutex::new(ReloadState {
+                    modified: modified_time(&list.path),
+                    last_check: Instant::now(),
+                }),
+                reload_interval: RELOAD_CHECK_INTERVAL,
+            });
+        }
+        Ok(IocMatcher { lists: loaded })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lists.is_empty()
+    }
+
+    /// Listed indicators found in `line`, one match per list and indicator.
+    pub fn find_matches(&self, line: &str) -> Vec<IocMatch<'_>> {
+        let mut matches = Vec::new();
+        if self.lists.is_empty() {
+            return matches;
+        }
+
+        let fields = if self.lists.iter().any(|list| !list.config.fields.is_empty()) {
+            extract_fields(line)
+        } else {
+            Default::default()
+        };
+
+        for list in &self.lists {
+            list.reload_if_changed();
+
+            let candidates = if list.config.fields.is_empty() {
+                extract_indicators(line, &list.kinds)
+            } else {
+                list.config
+                    .fields
+                    .iter()
+                    .filter_map(|name| fields.get(name))
+                    .flat_map(|value| extract_indicators(value, &list.kinds))
+                    .collect()
+            };
+
+            let indicators = list.indicators.read().unwrap();
+            let mut seen = HashSet::new();
+            for candidate in candidates {
+                if let Some(hit) = lookup(&indicators, &candidate) {
+                    if seen.insert(hit.clone()) {
+                        matches.push(IocMatch {
+                            list: &list.config.name,
+                            severity: &list.config.severity,
+                            indicator: hit,
+                        });
+                    }
+                }
+            }
+        }
+        matches
+    }
+}
+
+impl LoadedIocList {
+    /// Reload the list if its file changed since the last check. A list that
+    /// fails to reload keeps its previous contents.
+    fn reload_if_changed(&self) {
+        let mut state = self.reload.lock().unwrap();
+        if state.last_check.elapsed() < self.reload_interval {
+            return;
+        }
+        state.last_check = Instant::now();
+
+        let modified = modified_time(&self.config.path);
+        if modified == state.modified {
+            return;
+        }
+        state.modified = modified;
+        match load_indicators(&self.config) {
+            Ok(indicators) => *self.indicators.write().unwrap() = indicators,
+            Err(e) => eprintln!(
+                "Failed to reload IOC list \"{}\", keeping previous entries: {e:#}",
+                self.config.name
+            ),
+        }
+    }
+}
+
+/// The listed indicator matching `candidate`. Domains also match when a
+/// parent domain is listed, so `evil.example` covers `cdn.evil.example`.
+fn lookup(indicators: &HashSet<Indicator>, candidate: &Indicator) -> Option<Indicator> {
+    if indicators.contains(candidate) {
+        return Some(candidate.clone());
+    }
+    let Indicator::Domain(domain) = candidate else {
+        return None;
+    };
+    domain
+        .match_indices('.')
+        .map(|(dot, _)| &domain[dot + 1..])
+        .filter(|parent| parent.contains('.'))
+        .map(|parent| Indicator::Domain(parent.to_string()))
+        .find(|parent| indicators.contains(parent))
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub(crate) fn load_indicators(list: &IocList) -> anyhow::Result<HashSet<Indicator>> {
+    let format =
+        list.format
+            .unwrap_or_else(|| match list.path.extension().and_then(|e| e.to_str()) {
+                Some(extension) if extension.eq_ignore_ascii_case("csv") => IocFormat::Csv,
+                _ => IocFormat::Text,
+            });
+    let content = std::fs::read_to_string(&list.path)
+        .with_context(|| format!("Cannot read {}", list.path.display()))?;
+
+    let values: Vec<String> = match format {
+        IocFormat::Text => content
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim())
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        IocFormat::Csv => csv_column(&content, list.column.as_deref())
+            .with_context(|| format!("Invalid CSV in {}", list.path.display()))?,
+    };
+
+    Ok(values
+        .iter()
+        .filter_map(|value| Indicator::parse(value))
+        .collect())
+}
+
+/// Values of one column of a CSV document with a header row; `#` starts a
+/// comment line.
+fn csv_column(content: &str, column: Option<&str>) -> anyhow::Result<Vec<String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+
+    let index = match column {
+        None => 0,
+        Some(column) => reader
+            .headers()?
+            .iter()
+            .position(|header| header == column)
+            .ok_or_else(|| anyhow::anyhow!("no column named \"{column}\""))?,
+    };
+
+    let mut values = Vec::new();
+    for record in reader.records() {
+        if let Some(value) = record?.get(index) {
+            values.push(value.to_string());
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(matcher: &IocMatcher, line: &str) -> Vec<String> {
+        matcher
+            .find_matches(line)
+            .iter()
+            .map(|m| format!("{}:{}", m.list, m.indicator))
+            .collect()
+    }
+
+    #[test]
+    fn matches_text_and_csv_lists() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        std::fs::write(
+            dir.join("ips.txt"),
+            "# known scanners\n203.0.113.5\n2001:db8::bad  # v6\n\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("feed.csv"),
+            "first_seen,indicator,source\n2024-01-01,Evil.Example,osint\n2024-01-02,d41d8cd98f00b204e9800998ecf8427e,sandbox\n",
+        )
+        .unwrap();
+
+        let matcher = IocMatcher::new(&[
+            IocList {
+                name: "scanners".to_string(),
+                path: dir.join("ips.txt"),
+                severity: "high".to_string(),
+                ..Default::default()
+            },
+            IocList {
+                name: "feed".to_string(),
+                path: dir.join("feed.csv"),
+                column: Some("indicator".to_string()),
+                fields: vec!["query".to_string(), "md5".to_string()],
+                severity: "critical".to_string(),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(
+            found(
+                &matcher,
+                "sshd: Failed password from 203.0.113.5 and 203.0.113.5"
+            ),
+            vec!["scanners:203.0.113.5"]
+        );
+        assert_eq!(
+            found(&matcher, "conn src=2001:db8:0:0:0:0:0:bad"),
+            vec!["scanners:2001:db8::bad"]
+        );
+        assert_eq!(
+            found(
+                &matcher,
+                "dns query=cdn.evil.example md5=D41D8CD98F00B204E9800998ECF8427E"
+            ),
+            vec!["feed:evil.example", "feed:d41d8cd98f00b204e9800998ecf8427e"]
+        );
+        // Only the configured fields are searched
+        assert!(found(&matcher, "dns answer=evil.example").is_empty());
+    }
+
+    #[test]
+    fn reloads_changed_list_files() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let path = dir.join("ips.txt");
+        std::fs::write(&path, "203.0.113.5\n").unwrap();
+        let mut matcher = IocMatcher::new(&[IocList {
+            name: "scanners".to_string(),
+            path: path.clone(),
+            severity: "high".to_string(),
+            ..Default::default()
+        }])
+        .unwrap();
+        matcher.lists[0].reload_interval = Duration::ZERO;
+
+        assert_eq!(found(&matcher, "from 198.51.100.7").len(), 0);
+        std::fs::write(&path, "198.51.100.7\n").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            found(&matcher, "from 198.51.100.7"),
+            vec!["scanners:198.51.100.7"]
+        );
+        assert!(found(&matcher, "from 203.0.113.5").is_empty());
+    }
+}
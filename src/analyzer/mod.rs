@@ -1,4 +1,16 @@
 // src/analyzer/mod.rs
+pub mod condition;
 pub mod correlation_engine;
+pub mod fields;
 pub mod frequency_tracker;
+pub mod geoip;
+pub mod indicators;
+pub mod ioc;
+pub mod network;
+pub mod new_values;
 pub mod pattern_matcher;
+pub mod rule_tests;
+pub mod state_file;
+pub mod suppression;
+pub mod timestamp;
+pub mod travel;
@@ -0,0 +1,190 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network in CIDR notation. A bare address is a /32 or
+/// /128 network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn new(addr: IpAddr, prefix: u8) -> anyhow::Result<Self> {
+        if prefix > max_prefix(&addr) {
+            return Err(anyhow::anyhow!(
+                "prefix /{prefix} is too long for {addr} (max /{})",
+                max_prefix(&addr)
+            ));
+        }
+        Ok(Cidr {
+            network: mask(addr, prefix),
+            prefix,
+        })
+    }
+
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether `ip` lies in this network. IPv4-mapped IPv6 addresses
+    /// (`::ffff:10.0.0.1`) are compared as IPv4.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) if self.network.is_ipv4() => match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => return false,
+            },
+            ip => ip,
+        };
+        ip.is_ipv4() == self.network.is_ipv4() && mask(ip, self.prefix) == self.network
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid IP address in \"{s}\""))?;
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid prefix length in \"{s}\""))?,
+            None => max_prefix(&addr),
+        };
+        Cidr::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    if addr.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+/// `addr` with every bit after the first `prefix` cleared. Prefixes longer
+/// than the address keep it unchanged.
+pub fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let bits = u32::from(v4);
+            let kept = u32::MAX
+                .checked_shl(32 - u32::from(prefix.min(32)))
+                .unwrap_or(0);
+            IpAddr::V4((bits & kept).into())
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::from(v6);
+            let kept = u128::MAX
+                .checked_shl(128 - u32::from(prefix.min(128)))
+                .unwrap_or(0);
+            IpAddr::V6((bits & kept).into())
+        }
+    }
+}
+
+/// The `[networks]` table: named lists of networks that `in_cidr` and
+/// `not_in_cidr` conditions can refer to by name.
+#[derive(Debug, Default)]
+pub struct NetworkSets {
+    sets: HashMap<String, Vec<Cidr>>,
+}
+
+impl NetworkSets {
+    pub fn new(networks: &BTreeMap<String, Vec<String>>) -> anyhow::Result<Self> {
+        let mut sets = HashMap::with_capacity(networks.len());
+        for (name, entries) in networks {
+            let cidrs = entries
+                .iter()
+                .map(|entry| entry.parse())
+                .collect::<anyhow::Result<Vec<Cidr>>>()
+                .map_err(|e| anyhow::anyhow!("network set \"{name}\": {e}"))?;
+            sets.insert(name.clone(), cidrs);
+        }
+        Ok(NetworkSets { sets })
+    }
+
+    /// Expand a list of CIDRs, bare addresses and set names into networks.
+    pub fn resolve(&self, entries: &[String]) -> anyhow::Result<Vec<Cidr>> {
+        let mut cidrs = Vec::new();
+        for entry in entries {
+            match self.sets.get(entry) {
+                Some(set) => cidrs.extend_from_slice(set),
+                None => cidrs.push(entry.parse().map_err(|e| {
+                    anyhow::anyhow!("\"{entry}\" is neither a network set nor a CIDR: {e}")
+                })?),
+            }
+        }
+        Ok(cidrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn contains_ipv4_and_ipv6() {
+        let private = cidr("10.0.0.0/8");
+        assert!(private.contains(ip("10.200.3.4")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        assert!(private.contains(ip("::ffff:10.1.2.3")));
+        assert!(!private.contains(ip("fd00::1")));
+
+        let metadata = cidr("169.254.169.254");
+        assert!(metadata.contains(ip("169.254.169.254")));
+        assert!(!metadata.contains(ip("169.254.169.253")));
+
+        let ula = cidr("fd00::/8");
+        assert!(ula.contains(ip("fd12:3456::1")));
+        assert!(!ula.contains(ip("fe80::1")));
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.5")));
+
+        assert_eq!(cidr("192.168.1.77/24").to_string(), "192.168.1.0/24");
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn resolves_named_sets() {
+        let sets = NetworkSets::new(&BTreeMap::from([(
+            "internal".to_string(),
+            vec!["10.0.0.0/8".to_string(), "fd00::/8".to_string()],
+        )]))
+        .unwrap();
+        let cidrs = sets
+            .resolve(&["internal".to_string(), "192.168.0.0/16".to_string()])
+            .unwrap();
+        assert_eq!(cidrs.len(), 3);
+        assert!(sets.resolve(&["external".to_string()]).is_err());
+    }
+}
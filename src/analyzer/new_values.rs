@@ -0,0 +1,481 @@
+use crate::analyzer::condition::{CompiledCondition, LineContext};
+use crate::analyzer::geoip::GeoIp;
+use crate::analyzer::network::NetworkSets;
+use crate::analyzer::state_file::StateFile;
+use crate::analyzer::timestamp::event_time;
+use crate::config::rules::NewValueRule;
+use crate::output::{Detection, DetectionKind, Severity};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Values kept per key; the least recently seen are forgotten first, and
+/// count as new again.
+const MAX_VALUES_PER_KEY: usize = 10_000;
+const KNOWN_VALUES_VERSION: u32 = 2;
+
+/// The compiled `[[new_value_rules]]`. Picking values out of a line is
+/// stateless, so `parallel_scan` workers run `find` on any thread.
+#[derive(Default)]
+pub struct NewValueMatcher {
+    rules: Vec<CompiledRule>,
+}
+
+struct CompiledRule {
+    rule: NewValueRule,
+    condition: Option<CompiledCondition>,
+    regex: Option<Regex>,
+}
+
+/// The values every `[[new_value_rules]]` entry has learned.
+///
+/// `observe` updates the known values and must see lines in order. Rules
+/// with a `history` path keep their values in a `StateFile`, saved once
+/// more when the tracker is dropped.
+#[derive(Default)]
+pub struct NewValueTracker {
+    rules: Vec<TrackedRule>,
+}
+
+struct TrackedRule {
+    rule: NewValueRule,
+    known: Mutex<StateFile<KnownValues>>,
+}
+
+/// A value one of the rules picked out of a line, not yet checked against
+/// the known values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeenValue<'a> {
+    pub rule: &'a str,
+    /// `field=value` pairs of the rule's `key_fields`, space separated
+    pub key: String,
+    pub value: String,
+    index: usize,
+}
+
+/// On-disk form of one rule's known values.
+#[derive(Serialize, Deserialize)]
+struct KnownValues {
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    keys: BTreeMap<String, KeyValues>,
+}
+
+impl Default for KnownValues {
+    fn default() -> Self {
+        KnownValues {
+            version: KNOWN_VALUES_VERSION,
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(from = "StoredKeyValues")]
+struct KeyValues {
+    /// Event time of the key's earliest line; learning runs from here
+    first_seen: DateTime<Utc>,
+    /// The known values, with the time of the latest line showing each
+    values: BTreeMap<String, DateTime<Utc>>,
+}
+
+/// A `KeyValues` as saved by any version; version 1 kept the values
+/// without times.
+#[derive(Deserialize)]
+struct StoredKeyValues {
+    first_seen: DateTime<Utc>,
+    #[serde(default)]
+    values: StoredValues,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredValues {
+    Seen(BTreeMap<String, DateTime<Utc>>),
+    Listed(BTreeSet<String>),
+}
+
+impl Default for StoredValues {
+    fn default() -> Self {
+        StoredValues::Seen(BTreeMap::new())
+    }
+}
+
+impl From<StoredKeyValues> for KeyValues {
+    fn from(stored: StoredKeyValues) -> Self {
+        let values = match stored.values {
+            StoredValues::Seen(values) => values,
+            // Count them as seen when the key was first seen
+            StoredValues::Listed(values) => values
+                .into_iter()
+                .map(|value| (value, stored.first_seen))
+                .collect(),
+        };
+        KeyValues {
+            first_seen: stored.first_seen,
+            values,
+        }
+    }
+}
+
+impl NewValueMatcher {
+    pub fn new(rules: &[NewValueRule], networks: &NetworkSets) -> anyhow::Result<Self> {
+        let mut compiled = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let condition = rule
+                .condition
+                .as_ref()
+                .map(|condition| CompiledCondition::compile(condition, networks))
+                .transpose()
+                .with_context(|| {
+                    format!("Invalid condition in new value rule \"{}\"", rule.name)
+                })?;
+            let regex = match (&rule.field, &rule.regex) {
+                (Some(_), None) => None,
+                (None, Some(regex)) => Some(compile_value_regex(regex).with_context(|| {
+                    format!("Invalid regex in new value rule \"{}\"", rule.name)
+                })?),
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "New value rule \"{}\" needs exactly one of field and regex",
+                        rule.name
+                    ))
+                }
+            };
+            compiled.push(CompiledRule {
+                rule: rule.clone(),
+                condition,
+                regex,
+            });
+        }
+        Ok(NewValueMatcher { rules: compiled })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The value each rule picks out of `line`, for rules whose condition
+    /// the line satisfies and whose key fields are all present.
+    pub fn find(&self, line: &str, geoip: Option<&GeoIp>) -> Vec<SeenValue<'_>> {
+        let mut seen = Vec::new();
+        if self.rules.is_empty() {
+            return seen;
+        }
+        let context = LineContext::new(line).with_geoip(geoip);
+        for (index, compiled) in self.rules.iter().enumerate() {
+            if let Some(condition) = &compiled.condition {
+                if !condition.matches(&context) {
+                    continue;
+                }
+            }
+            let value = match (&compiled.rule.field, &compiled.regex) {
+                (Some(field), _) => context.field(field),
+                (None, Some(regex)) => regex.captures(line).and_then(|captures| {
+                    captures
+                        .name("value")
+                        .or_else(|| captures.get(1))
+                        .map(|m| m.as_str())
+                }),
+                (None, None) => None,
+            };
+            let Some(value) = value.filter(|value| !value.is_empty()) else {
+                continue;
+            };
+            let key: Option<Vec<String>> = compiled
+                .rule
+                .key_fields
+                .iter()
+                .map(|name| context.field(name).map(|value| format!("{name}={value}")))
+                .collect();
+            let Some(key) = key else {
+                continue;
+            };
+            seen.push(SeenValue {
+                rule: &compiled.rule.name,
+                key: key.join(" "),
+                value: value.to_string(),
+                index,
+            });
+        }
+        seen
+    }
+}
+
+impl NewValueTracker {
+    /// Load the known values of `rules`, which must be the rules the
+    /// `NewValueMatcher` was built from, in the same order.
+    pub fn new(rules: &[NewValueRule]) -> anyhow::Result<Self> {
+        let mut tracked = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let known = StateFile::open(rule.history.as_deref())
+                .with_context(|| format!("Failed to load known values of \"{}\"", rule.name))?;
+            tracked.push(TrackedRule {
+                rule: rule.clone(),
+                known: Mutex::new(known),
+            });
+        }
+        Ok(NewValueTracker { rules: tracked })
+    }
+
+    /// Learn `seen`, returning a detection if its value is new for its key
+    /// and the key's learning period is over.
+    pub fn observe(
+        &self,
+        seen: &SeenValue<'_>,
+        file_path: &Path,
+        line_number: usize,
+        line: &str,
+    ) -> Option<Detection> {
+        let tracked = self.rules.get(seen.index)?;
+        let rule = &tracked.rule;
+        // Unlike travel, nothing is measured between two lines, so a line
+        // without an event time counts as happening when it is read
+        let time = event_time(&LineContext::new(line), rule.time_field.as_deref())
+            .unwrap_or_else(Utc::now);
+
+        let mut known = tracked.known.lock().unwrap();
+        let entry = known
+            .data
+            .keys
+            .entry(seen.key.clone())
+            .or_insert_with(|| KeyValues {
+                first_seen: time,
+                values: BTreeMap::new(),
+            });
+        entry.first_seen = entry.first_seen.min(time);
+        let known_count = entry.values.len();
+        if let Some(last_seen) = entry.values.get_mut(&seen.value) {
+            if time > *last_seen {
+                *last_seen = time;
+                known.changed();
+            }
+            return None;
+        }
+        entry.values.insert(seen.value.clone(), time);
+        entry.forget_values();
+        let learning_ends = i64::try_from(rule.learning_period_seconds)
+            .ok()
+            .and_then(chrono::Duration::try_seconds)
+            .and_then(|period| entry.first_seen.checked_add_signed(period));
+        known.changed();
+        if learning_ends.is_none_or(|end| time < end) {
+            return None;
+        }
+
+        let description = if seen.key.is_empty() {
+            format!("\"{}\" seen for the first time", seen.value)
+        } else {
+            format!(
+                "\"{}\" seen for the first time for {}",
+                seen.value, seen.key
+            )
+        };
+        let mut fields = BTreeMap::from([
+            ("new_value.value".to_string(), seen.value.clone()),
+            ("new_value.known".to_string(), known_count.to_string()),
+        ]);
+        if !seen.key.is_empty() {
+            fields.insert("new_value.key".to_string(), seen.key.clone());
+        }
+        Some(Detection {
+            kind: DetectionKind::NewValue,
+            severity: Severity::from(rule.severity.as_str()),
+            file_path: file_path.to_string_lossy().to_string(),
+            line_number,
+            matched_line: line.to_string(),
+            pattern_name: rule.name.clone(),
+            description,
+            related_lines: Vec::new(),
+            fields,
+        })
+    }
+
+    /// Write every changed set of known values to disk.
+    pub fn flush(&self) {
+        for tracked in &self.rules {
+            tracked.known.lock().unwrap().flush();
+        }
+    }
+}
+
+impl Drop for NewValueTracker {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl KeyValues {
+    /// Drop the least recently seen values beyond `MAX_VALUES_PER_KEY`.
+    fn forget_values(&mut self) {
+        while self.values.len() > MAX_VALUES_PER_KEY {
+            let Some(oldest) = self
+                .values
+                .iter()
+                .min_by_key(|(_, last_seen)| **last_seen)
+                .map(|(value, _)| value.clone())
+            else {
+                break;
+            };
+            self.values.remove(&oldest);
+        }
+    }
+}
+
+/// Compile a value regex, which must have a `value` group or at least one
+/// group.
+pub(crate) fn compile_value_regex(pattern: &str) -> anyhow::Result<Regex> {
+    let regex = Regex::new(pattern)?;
+    if regex.captures_len() < 2 {
+        return Err(anyhow::anyhow!(
+            "the regex needs a capture group for the value"
+        ));
+    }
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_values(
+        rules: &[NewValueRule],
+        tracker: &NewValueTracker,
+        lines: &[&str],
+    ) -> Vec<(usize, String, BTreeMap<String, String>)> {
+        let matcher = NewValueMatcher::new(rules, &NetworkSets::default()).unwrap();
+        let mut found = Vec::new();
+        for (index, line) in lines.iter().enumerate() {
+            for seen in matcher.find(line, None) {
+                if let Some(detection) =
+                    tracker.observe(&seen, Path::new("app.log"), index + 1, line)
+                {
+                    found.push((index + 1, detection.pattern_name, detection.fields));
+                }
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn reports_values_first_seen_after_learning() {
+        let rules = [NewValueRule {
+            name: "new_ssh_user".to_string(),
+            condition: Some(crate::config::rules::Condition::Contains(
+                "Accepted".to_string(),
+            )),
+            field: Some("user".to_string()),
+            key_fields: vec!["host".to_string()],
+            learning_period_seconds: 3600,
+            severity: "warning".to_string(),
+            ..Default::default()
+        }];
+        let tracker = NewValueTracker::new(&rules).unwrap();
+
+        let found = new_values(
+            &rules,
+            &tracker,
+            &[
+                "2024-05-01T08:00:00Z Accepted host=web1 user=alice",
+                "2024-05-01T08:30:00Z Accepted host=web1 user=bob",
+                "2024-05-01T09:10:00Z Accepted host=web1 user=alice",
+                "2024-05-01T09:20:00Z Failed host=web1 user=mallory",
+                "2024-05-01T09:30:00Z Accepted host=web1 user=carol",
+                "2024-05-01T09:40:00Z Accepted host=web1 user=carol",
+                // A new host starts its own learning period
+                "2024-05-01T09:50:00Z Accepted host=web2 user=dave",
+                "2024-05-01T09:55:00Z Accepted user=erin",
+            ],
+        );
+        assert_eq!(found.len(), 1, "{found:?}");
+        let (line, name, fields) = &found[0];
+        assert_eq!((*line, name.as_str()), (5, "new_ssh_user"));
+        assert_eq!(fields["new_value.value"], "carol");
+        assert_eq!(fields["new_value.key"], "host=web1");
+        assert_eq!(fields["new_value.known"], "2");
+    }
+
+    #[test]
+    fn persists_known_values_and_learning_start() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let rules = [NewValueRule {
+            name: "new_kernel_process".to_string(),
+            regex: Some(r"kernel: (?P<value>[\w-]+)\[\d+\]".to_string()),
+            learning_period_seconds: 60,
+            severity: "info".to_string(),
+            history: Some(dir.join("kernel.json")),
+            ..Default::default()
+        }];
+
+        let tracker = NewValueTracker::new(&rules).unwrap();
+        let found = new_values(
+            &rules,
+            &tracker,
+            &["2024-05-01T08:00:00Z kernel: systemd[1]: started"],
+        );
+        assert!(found.is_empty());
+        drop(tracker);
+
+        let tracker = NewValueTracker::new(&rules).unwrap();
+        let found = new_values(
+            &rules,
+            &tracker,
+            &[
+                "2024-05-01T09:00:00Z kernel: systemd[1]: reloaded",
+                "2024-05-01T09:00:01Z kernel: xmrig[4242]: segfault",
+            ],
+        );
+        let values: Vec<_> = found
+            .iter()
+            .map(|(_, _, fields)| fields["new_value.value"].as_str())
+            .collect();
+        assert_eq!(values, vec!["xmrig"]);
+    }
+
+    #[test]
+    fn forgets_the_least_recently_seen_values_beyond_the_cap() {
+        let start = DateTime::UNIX_EPOCH;
+        let mut key = KeyValues {
+            first_seen: start,
+            values: BTreeMap::new(),
+        };
+        for i in 0..MAX_VALUES_PER_KEY + 5 {
+            let time = start + chrono::Duration::seconds(i as i64);
+            key.values.insert(format!("value{i}"), time);
+            key.forget_values();
+        }
+        assert_eq!(key.values.len(), MAX_VALUES_PER_KEY);
+        assert!(!key.values.contains_key("value4"));
+        assert!(key.values.contains_key("value5"));
+    }
+
+    #[test]
+    fn reads_version_1_known_values() {
+        let known: KnownValues = serde_json::from_str(
+            r#"{"version":1,"keys":{"host=web1":{"first_seen":"2024-05-01T08:00:00Z","values":["alice"]}}}"#,
+        )
+        .unwrap();
+        let values = &known.keys["host=web1"].values;
+        assert_eq!(values["alice"].to_rfc3339(), "2024-05-01T08:00:00+00:00");
+    }
+
+    #[test]
+    fn rejects_rules_without_a_value_source() {
+        let rule = NewValueRule {
+            name: "broken".to_string(),
+            regex: Some("no group".to_string()),
+            severity: "info".to_string(),
+            ..Default::default()
+        };
+        let error = NewValueMatcher::new(&[rule], &NetworkSets::default())
+            .err()
+            .unwrap();
+        assert!(format!("{error:#}").contains("capture group"));
+    }
+}
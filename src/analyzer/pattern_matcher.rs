@@ -1,68 +1,325 @@
-use crate::config::rules::{RegexRule, Rules};
-use regex::Regex;
+use crate::analyzer::condition::{CompiledCondition, LineContext};
+use crate::analyzer::geoip::GeoIp;
+use crate::analyzer::ioc::{IocMatch, IocMatcher};
+use crate::analyzer::network::NetworkSets;
+use crate::analyzer::new_values::{NewValueMatcher, SeenValue};
+use crate::analyzer::suppression::Suppressor;
+use crate::config::rules::{Anchor, MatchMode, PatternOptions, RegexRule, Rules};
+use crate::output::{Detection, DetectionKind};
+use aho_corasick::AhoCorasick;
+use regex::{Regex, RegexSet, SetMatches};
+use std::collections::HashMap;
 
+/// How a `[[regex_rules]]` entry decides whether a line matches.
+enum RuleMatcher {
+    /// Index of the rule's pattern in the shared `RegexSet`.
+    Regex(usize),
+    Condition(CompiledCondition),
+}
+
+impl RuleMatcher {
+    fn new(
+        rule: &RegexRule,
+        networks: &NetworkSets,
+        set_patterns: &mut Vec<String>,
+    ) -> anyhow::Result<Self> {
+        match (&rule.condition, rule.pattern.is_empty()) {
+            (None, false) => {
+                set_patterns.push(rule.pattern.clone());
+                Ok(RuleMatcher::Regex(set_patterns.len() - 1))
+            }
+            (Some(condition), true) => Ok(RuleMatcher::Condition(CompiledCondition::compile(
+                condition, networks,
+            )?)),
+            (Some(_), false) => Err(anyhow::anyhow!(
+                "rule \"{}\" sets both pattern and condition",
+                rule.name
+            )),
+            (None, true) => Err(anyhow::anyhow!(
+                "rule \"{}\" needs a pattern or a condition",
+                rule.name
+            )),
+        }
+    }
+
+    fn is_match(&self, context: &LineContext<'_>, set_matches: &SetMatches) -> bool {
+        match self {
+            RuleMatcher::Regex(index) => set_matches.matched(*index),
+            RuleMatcher::Condition(condition) => condition.matches(context),
+        }
+    }
+}
+
+/// What a string pattern reports when it matches.
+struct StringRule {
+    severity: String,
+    name: String,
+    continue_matching: bool,
+}
+
+/// Matches lines against every configured rule in a single pass per rule
+/// kind: plain string patterns share one Aho-Corasick automaton, string
+/// patterns with options and the regex rules each share one `RegexSet`.
+/// Priority is still decided by list order, not by where in the line a
+/// match starts.
 pub struct PatternMatcher {
-    error_patterns: Vec<String>,
-    warning_patterns: Vec<String>,
-    regex_rules: Vec<(RegexRule, Regex)>,
+    match_mode: MatchMode,
+    /// The error patterns followed by the warning patterns, in priority
+    /// order.
+    string_patterns: Vec<StringRule>,
+    literals: AhoCorasick,
+    /// Automaton pattern id → index into `string_patterns`.
+    literal_ids: Vec<usize>,
+    /// String patterns with case, word or anchor options, as regexes.
+    option_set: RegexSet,
+    /// Set index → index into `string_patterns`.
+    option_ids: Vec<usize>,
+    regex_set: RegexSet,
+    regex_rules: Vec<(RegexRule, RuleMatcher)>,
+    /// Rule name → pattern, for regex rules with named capture groups.
+    capture_rules: HashMap<String, Regex>,
+    ioc_matcher: IocMatcher,
+    suppressor: Suppressor,
+    networks: NetworkSets,
+    geoip: Option<GeoIp>,
+    new_values: NewValueMatcher,
 }
 
 impl PatternMatcher {
     pub fn new(rules: &Rules) -> anyhow::Result<Self> {
+        let lists = [
+            (
+                "ERROR",
+                &rules.patterns.error_patterns,
+                rules.patterns.error_options.unwrap_or_default(),
+            ),
+            (
+                "WARNING",
+                &rules.patterns.warning_patterns,
+                rules.patterns.warning_options.unwrap_or_default(),
+            ),
+        ];
+
+        let mut string_patterns = Vec::new();
+        let mut literal_patterns = Vec::new();
+        let mut literal_ids = Vec::new();
+        let mut option_patterns = Vec::new();
+        let mut option_ids = Vec::new();
+        for (list_severity, patterns, list_options) in lists {
+            for pattern in patterns {
+                let index = string_patterns.len();
+                string_patterns.push(StringRule {
+                    severity: pattern.severity().unwrap_or(list_severity).to_string(),
+                    name: pattern.name().to_string(),
+                    continue_matching: pattern.continue_matching(),
+                });
+                let options = pattern.options(&list_options);
+                if options.is_default() {
+                    literal_patterns.push(pattern.pattern());
+                    literal_ids.push(index);
+                } else {
+                    option_patterns.push(option_regex(pattern.pattern(), &options));
+                    option_ids.push(index);
+                }
+            }
+        }
+
+        let networks = NetworkSets::new(&rules.networks)?;
+        let mut set_patterns = Vec::new();
         let mut compiled_regex_rules = Vec::new();
+        let mut capture_rules = HashMap::new();
         for rule in &rules.regex_rules {
-            let regex = Regex::new(&rule.pattern)?;
-            compiled_regex_rules.push((rule.clone(), regex));
+            let matcher = RuleMatcher::new(rule, &networks, &mut set_patterns)?;
+            if let RuleMatcher::Regex(_) = matcher {
+                let regex = Regex::new(&rule.pattern)?;
+                if regex.capture_names().flatten().next().is_some() {
+                    capture_rules.insert(rule.name.clone(), regex);
+                }
+            }
+            compiled_regex_rules.push((rule.clone(), matcher));
         }
 
         Ok(PatternMatcher {
-            error_patterns: rules.patterns.error_patterns.clone(),
-            warning_patterns: rules.patterns.warning_patterns.clone(),
+            match_mode: rules.match_mode.unwrap_or_default(),
+            string_patterns,
+            literals: AhoCorasick::new(literal_patterns)?,
+            literal_ids,
+            option_set: RegexSet::new(&option_patterns)?,
+            option_ids,
+            regex_set: RegexSet::new(&set_patterns)?,
             regex_rules: compiled_regex_rules,
+            capture_rules,
+            ioc_matcher: IocMatcher::new(&rules.ioc_lists)?,
+            suppressor: Suppressor::new(&rules.suppress_rules, &networks)?,
+            new_values: NewValueMatcher::new(&rules.new_value_rules, &networks)?,
+            networks,
+            geoip: rules.geoip.as_ref().map(GeoIp::open).transpose()?,
         })
     }
 
-    pub fn check_for_patterns(&self, line: &str) -> Option<(&str, &str)> {
-        for pattern in &self.error_patterns {
-            if line.contains(pattern) {
-                return Some(("ERROR", pattern));
+    /// Indicators in `line` found in the `[[ioc_lists]]`. These are checked
+    /// on every line regardless of match mode.
+    pub fn find_ioc_matches(&self, line: &str) -> Vec<IocMatch<'_>> {
+        self.ioc_matcher.find_matches(line)
+    }
+
+    /// Values the `[[new_value_rules]]` pick out of `line`, to be checked
+    /// against the known values with `NewValueTracker::observe`.
+    pub fn find_new_values(&self, line: &str) -> Vec<SeenValue<'_>> {
+        self.new_values.find(line, self.geoip.as_ref())
+    }
+
+    /// The `[networks]` sets, also used by correlation join keys.
+    pub fn networks(&self) -> &NetworkSets {
+        &self.networks
+    }
+
+    /// The `[geoip]` databases, if configured. Conditions can test `geo.*`
+    /// fields and detections are enriched with them.
+    pub fn geoip(&self) -> Option<&GeoIp> {
+        self.geoip.as_ref()
+    }
+
+    /// Attach the named capture groups of the regex rule that produced
+    /// `detection` as fields, e.g. `(?P<user>\S+)` as `user`. Groups that
+    /// did not take part in the match are left out.
+    pub fn add_captured_fields(&self, detection: &mut Detection) {
+        if detection.kind != DetectionKind::Pattern {
+            return;
+        }
+        let Some(regex) = self.capture_rules.get(&detection.pattern_name) else {
+            return;
+        };
+        let Some(captures) = regex.captures(&detection.matched_line) else {
+            return;
+        };
+        for name in regex.capture_names().flatten() {
+            if let Some(value) = captures.name(name) {
+                detection
+                    .fields
+                    .entry(name.to_string())
+                    .or_insert_with(|| value.as_str().to_string());
             }
         }
+    }
+
+    /// The compiled `[[suppress_rules]]`, applied to detections after matching.
+    pub fn suppressor(&self) -> &Suppressor {
+        &self.suppressor
+    }
+
+    /// Every rule that matches `line`, in priority order, as
+    /// `(severity, rule name)` pairs.
+    ///
+    /// In `first` mode matching stops after the first hit unless that rule
+    /// sets `continue = true`; in `all` mode every rule is evaluated.
+    pub fn find_matches(&self, line: &str) -> Vec<(&str, &str)> {
+        self.collect_matches(line, self.match_mode == MatchMode::All, true)
+    }
 
-        for pattern in &self.warning_patterns {
-            if line.contains(pattern) {
-                return Some(("WARNING", pattern));
+    /// The highest-priority rule matching `line`, regardless of match mode.
+    pub fn check_for_patterns(&self, line: &str) -> Option<(&str, &str)> {
+        self.collect_matches(line, false, false).into_iter().next()
+    }
+
+    fn collect_matches(
+        &self,
+        line: &str,
+        match_all: bool,
+        honor_continue: bool,
+    ) -> Vec<(&str, &str)> {
+        let mut matches = Vec::new();
+
+        if !self.string_patterns.is_empty() {
+            let mut hits: Vec<usize> = self
+                .literals
+                .find_overlapping_iter(line)
+                .map(|hit| self.literal_ids[hit.pattern().as_usize()])
+                .collect();
+            if !self.option_ids.is_empty() {
+                hits.extend(
+                    self.option_set
+                        .matches(line)
+                        .into_iter()
+                        .map(|index| self.option_ids[index]),
+                );
+            }
+            hits.sort_unstable();
+            hits.dedup();
+            for index in hits {
+                let rule = &self.string_patterns[index];
+                matches.push((rule.severity.as_str(), rule.name.as_str()));
+                let keep_going = match_all || (honor_continue && rule.continue_matching);
+                if !keep_going {
+                    return matches;
+                }
             }
         }
 
-        for (rule, regex) in &self.regex_rules {
-            if regex.is_match(line) {
-                return Some((&rule.severity, &rule.name));
+        if self.regex_rules.is_empty() {
+            return matches;
+        }
+        let set_matches = self.regex_set.matches(line);
+        let context = LineContext::new(line).with_geoip(self.geoip.as_ref());
+        for (rule, matcher) in &self.regex_rules {
+            if matcher.is_match(&context, &set_matches) {
+                matches.push((rule.severity.as_str(), rule.name.as_str()));
+                let keep_going = match_all || (honor_continue && rule.continue_matching);
+                if !keep_going {
+                    break;
+                }
             }
         }
 
-        None
+        matches
+    }
+}
+
+/// Regex equivalent of a string pattern with matching options.
+fn option_regex(pattern: &str, options: &PatternOptions) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut regex = regex::escape(pattern);
+    if options.whole_word {
+        if pattern.starts_with(is_word) {
+            regex.insert_str(0, r"\b");
+        }
+        if pattern.ends_with(is_word) {
+            regex.push_str(r"\b");
+        }
+    }
+    match options.anchor {
+        Some(Anchor::Start) => regex.insert(0, '^'),
+        Some(Anchor::End) => regex.push('$'),
+        Some(Anchor::Line) => regex = format!("^{regex}$"),
+        None => {}
+    }
+    if options.ignore_case {
+        regex.insert_str(0, "(?i)");
     }
+    regex
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::rules::{PatternConfig, RegexRule, Rules};
+    use crate::config::rules::{Condition, MatchMode, PatternConfig, RegexRule, Rules};
+    use crate::output::console::create_detection;
+    use std::path::Path;
 
     fn test_rules() -> Rules {
         Rules {
             patterns: PatternConfig {
-                error_patterns: vec!["ERROR".to_string()],
-                warning_patterns: vec!["WARN".to_string()],
+                error_patterns: vec!["ERROR".into()],
+                warning_patterns: vec!["WARN".into()],
+                ..Default::default()
             },
             regex_rules: vec![RegexRule {
                 name: "custom_rule".to_string(),
                 pattern: r"disk.*error".to_string(),
                 severity: "critical".to_string(),
+                ..Default::default()
             }],
-            frequency_rules: None,
-            correlated_rules: vec![],
+            ..Default::default()
         }
     }
 
@@ -87,10 +344,269 @@ mod tests {
         assert_eq!(result, Some(("critical", "custom_rule")));
     }
 
+    #[test]
+    fn conditions_see_geoip_fields() {
+        use crate::analyzer::geoip::test_db;
+        use crate::config::rules::{FieldCondition, GeoIpConfig};
+
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let database = dir.join("city.mmdb");
+        test_db::write(
+            &database,
+            vec![
+                (
+                    "203.0.113.0/24",
+                    test_db::city("TR", "Istanbul", 41.0, 29.0),
+                ),
+                (
+                    "198.51.100.0/24",
+                    test_db::city("BR", "Sao Paulo", -23.5, -46.6),
+                ),
+            ],
+        );
+
+        let mut rules = test_rules();
+        rules.geoip = Some(GeoIpConfig {
+            databases: vec![database],
+            fields: Vec::new(),
+        });
+        rules.regex_rules.push(RegexRule {
+            name: "login_outside_allowlist".to_string(),
+            condition: Some(Condition::All(vec![
+                Condition::Contains("Accepted password".to_string()),
+                Condition::Field(FieldCondition {
+                    name: "geo.country".to_string(),
+                    not_one_of: vec!["TR".to_string(), "DE".to_string()],
+                    ..Default::default()
+                }),
+            ])),
+            severity: "high".to_string(),
+            ..Default::default()
+        });
+        let matcher = PatternMatcher::new(&rules).unwrap();
+
+        let login = |ip: &str| format!("sshd: Accepted password for deploy from {ip}");
+        assert_eq!(
+            matcher.check_for_patterns(&login("198.51.100.4")),
+            Some(("high", "login_outside_allowlist"))
+        );
+        assert_eq!(matcher.check_for_patterns(&login("203.0.113.4")), None);
+        // No GeoIP data, so no country to compare
+        assert_eq!(matcher.check_for_patterns(&login("10.0.0.4")), None);
+    }
+
+    #[test]
+    fn matches_condition_rules() {
+        let mut rules = test_rules();
+        rules.regex_rules.push(RegexRule {
+            name: "external_ssh_failure".to_string(),
+            condition: Some(Condition::All(vec![
+                Condition::Contains("sshd".to_string()),
+                Condition::Not(Box::new(Condition::Contains("from 10.".to_string()))),
+            ])),
+            severity: "high".to_string(),
+            ..Default::default()
+        });
+        let matcher = PatternMatcher::new(&rules).unwrap();
+        assert_eq!(
+            matcher.check_for_patterns("sshd: Failed password from 203.0.113.5"),
+            Some(("high", "external_ssh_failure"))
+        );
+        assert_eq!(
+            matcher.check_for_patterns("sshd: Failed password from 10.0.0.5"),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_rules_with_both_pattern_and_condition() {
+        let mut rules = test_rules();
+        rules.regex_rules[0].condition = Some(Condition::Contains("x".to_string()));
+        assert!(PatternMatcher::new(&rules).is_err());
+    }
+
+    #[test]
+    fn first_mode_stops_unless_rule_continues() {
+        let mut rules = test_rules();
+        rules.regex_rules.insert(
+            0,
+            RegexRule {
+                name: "disk".to_string(),
+                pattern: "disk".to_string(),
+                severity: "info".to_string(),
+                continue_matching: true,
+                ..Default::default()
+            },
+        );
+        let matcher = PatternMatcher::new(&rules).unwrap();
+        assert_eq!(
+            matcher.find_matches("ERROR disk error"),
+            vec![("ERROR", "ERROR")]
+        );
+        assert_eq!(
+            matcher.find_matches("disk io error"),
+            vec![("info", "disk"), ("critical", "custom_rule")]
+        );
+    }
+
+    #[test]
+    fn string_entries_continue_too() {
+        let rules: Rules = toml::from_str(
+            r#"
+[rules]
+error_patterns = [{ pattern = "disk", name = "disk", continue = true }, "error"]
+
+[[regex_rules]]
+name = "io_error"
+pattern = "io error"
+severity = "critical"
+"#,
+        )
+        .unwrap();
+        let matcher = PatternMatcher::new(&rules).unwrap();
+        assert_eq!(
+            matcher.find_matches("disk io error"),
+            vec![("ERROR", "disk"), ("ERROR", "error")]
+        );
+        assert_eq!(
+            matcher.check_for_patterns("disk io error"),
+            Some(("ERROR", "disk"))
+        );
+    }
+
+    #[test]
+    fn all_mode_reports_every_matching_rule() {
+        let mut rules = test_rules();
+        rules.match_mode = Some(MatchMode::All);
+        let matcher = PatternMatcher::new(&rules).unwrap();
+        assert_eq!(
+            matcher.find_matches("ERROR WARN disk error"),
+            vec![
+                ("ERROR", "ERROR"),
+                ("WARNING", "WARN"),
+                ("critical", "custom_rule")
+            ]
+        );
+        assert_eq!(
+            matcher.check_for_patterns("ERROR WARN disk error"),
+            Some(("ERROR", "ERROR"))
+        );
+        assert!(matcher.find_matches("all good").is_empty());
+    }
+
+    #[test]
+    fn string_patterns_keep_list_order_when_overlapping() {
+        let mut rules = test_rules();
+        rules.patterns.error_patterns = vec!["timeout".into(), "out of memory".into()];
+        rules.patterns.warning_patterns = vec!["time".into()];
+        let matcher = PatternMatcher::new(&rules).unwrap();
+        assert_eq!(
+            matcher.check_for_patterns("out of memory after timeout"),
+            Some(("ERROR", "timeout"))
+        );
+
+        rules.match_mode = Some(MatchMode::All);
+        let matcher = PatternMatcher::new(&rules).unwrap();
+        assert_eq!(
+            matcher.find_matches("timeout: out of memory"),
+            vec![
+                ("ERROR", "timeout"),
+                ("ERROR", "out of memory"),
+                ("WARNING", "time")
+            ]
+        );
+    }
+
+    #[test]
+    fn string_pattern_options_and_named_entries() {
+        let rules: Rules = toml::from_str(
+            r#"
+            [rules]
+            error_patterns = [
+                "ERROR",
+                { pattern = "out of memory", name = "oom", severity = "critical", ignore_case = true },
+                { pattern = "panic", whole_word = true },
+            ]
+            warning_patterns = ["WARN", { pattern = "[deprecated]", anchor = "end", ignore_case = false }]
+
+            [rules.warning_options]
+            ignore_case = true
+            whole_word = true
+            "#,
+        )
+        .unwrap();
+        let matcher = PatternMatcher::new(&rules).unwrap();
+        let check = |line| matcher.check_for_patterns(line);
+
+        assert_eq!(check("kernel: Out Of Memory"), Some(("critical", "oom")));
+        assert_eq!(check("kernel panic!"), Some(("ERROR", "panic")));
+        assert_eq!(check("thread panicked"), None);
+        assert_eq!(check("error: lowercase"), None);
+        assert_eq!(check("warn: disk almost full"), Some(("WARNING", "WARN")));
+        assert_eq!(check("warning: not a whole word"), None);
+        assert_eq!(
+            check("GET /v1/api [deprecated]"),
+            Some(("WARNING", "[deprecated]"))
+        );
+        assert_eq!(check("GET /v1/api [DEPRECATED]"), None);
+        assert_eq!(check("[deprecated] GET /v1/api"), None);
+    }
+
+    #[test]
+    fn option_regex_escapes_and_anchors() {
+        let options = PatternOptions {
+            ignore_case: true,
+            whole_word: true,
+            anchor: Some(Anchor::Line),
+        };
+        assert_eq!(option_regex("a.b", &options), r"(?i)^\ba\.b\b$");
+        assert_eq!(
+            option_regex(
+                "(x)",
+                &PatternOptions {
+                    anchor: Some(Anchor::Start),
+                    ..options
+                }
+            ),
+            r"(?i)^\(x\)"
+        );
+    }
+
     #[test]
     fn error_takes_priority_over_regex() {
         let matcher = PatternMatcher::new(&test_rules()).unwrap();
         let result = matcher.check_for_patterns("ERROR disk error");
         assert_eq!(result, Some(("ERROR", "ERROR")));
     }
+
+    #[test]
+    fn named_captures_become_detection_fields() {
+        let mut rules = test_rules();
+        rules.regex_rules.push(RegexRule {
+            name: "successful_login".to_string(),
+            pattern: r"Accepted \w+ for (?P<user>\S+) from (?P<ip>\S+)(?: port (?P<port>\d+))?"
+                .to_string(),
+            severity: "info".to_string(),
+            ..Default::default()
+        });
+        let matcher = PatternMatcher::new(&rules).unwrap();
+        let line = "sshd: Accepted password for alice from 203.0.113.7";
+        let mut detection =
+            create_detection("info", Path::new("auth.log"), 1, line, "successful_login");
+        matcher.add_captured_fields(&mut detection);
+        assert_eq!(detection.fields["user"], "alice");
+        assert_eq!(detection.fields["ip"], "203.0.113.7");
+        assert!(!detection.fields.contains_key("port"));
+
+        let mut detection = create_detection(
+            "critical",
+            Path::new("a.log"),
+            1,
+            "disk error",
+            "custom_rule",
+        );
+        matcher.add_captured_fields(&mut detection);
+        assert!(detection.fields.is_empty());
+    }
 }
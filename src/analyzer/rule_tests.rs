@@ -0,0 +1,198 @@
+use crate::analyzer::correlation_engine::CorrelationEngine;
+use crate::analyzer::pattern_matcher::PatternMatcher;
+use crate::config::rules::{CorrelatedRule, Rules};
+use crate::output::console::{create_detection, create_ioc_detection};
+use std::path::Path;
+
+/// Outcome of a single inline rule test case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleTestResult {
+    pub rule_name: String,
+    pub case: String,
+    pub passed: bool,
+    pub detail: Option<String>,
+}
+
+/// Run the `should_match` / `should_not_match` examples of every regex rule
+/// and the log snippets of every correlated rule.
+///
+/// Example lines go through the full `PatternMatcher` with the configured
+/// match mode, so in `first` mode a line claimed by an earlier pattern does
+/// not count as a match for the rule.
+pub fn run_rule_tests(rules: &Rules) -> anyhow::Result<Vec<RuleTestResult>> {
+    let pattern_matcher = PatternMatcher::new(rules)?;
+    let mut results = Vec::new();
+
+    for rule in &rules.regex_rules {
+        for line in &rule.should_match {
+            let matches = pattern_matcher.find_matches(line);
+            let passed = matches.iter().any(|(_, name)| *name == rule.name);
+            results.push(RuleTestResult {
+                rule_name: rule.name.clone(),
+                case: format!("should match: {line}"),
+                passed,
+                detail: match matches.first() {
+                    _ if passed => None,
+                    Some((_, name)) => Some(format!("matched by \"{name}\" instead")),
+                    None => Some("no rule matched".to_string()),
+                },
+            });
+        }
+
+        for line in &rule.should_not_match {
+            let passed = !pattern_matcher
+                .find_matches(line)
+                .iter()
+                .any(|(_, name)| *name == rule.name);
+            results.push(RuleTestResult {
+                rule_name: rule.name.clone(),
+                case: format!("should not match: {line}"),
+                passed,
+                detail: (!passed).then(|| "rule matched".to_string()),
+            });
+        }
+    }
+
+    for rule in &rules.correlated_rules {
+        for (index, test) in rule.tests.iter().enumerate() {
+            let detections = count_correlations(&pattern_matcher, rule, &test.log)?;
+            let passed = detections == test.expect_detections;
+            results.push(RuleTestResult {
+                rule_name: rule.name.clone(),
+                case: format!("log snippet #{}", index + 1),
+                passed,
+                detail: (!passed).then(|| {
+                    format!(
+                        "expected {} detection(s), got {detections}",
+                        test.expect_detections
+                    )
+                }),
+            });
+        }
+    }
+
+    Ok(results)
+}
+
+fn count_correlations(
+    pattern_matcher: &PatternMatcher,
+    rule: &CorrelatedRule,
+    log: &str,
+) -> anyhow::Result<usize> {
+    let mut engine = CorrelationEngine::new(vec![rule.clone()], pattern_matcher.networks())?;
+    let snippet_path = Path::new("<test>");
+
+    Ok(log
+        .lines()
+        .enumerate()
+        .flat_map(|(index, line)| {
+            let rule_detections = pattern_matcher.find_matches(line).into_iter().map(
+                move |(severity, pattern_name)| {
+                    create_detection(severity, snippet_path, index + 1, line, pattern_name)
+                },
+            );
+            let ioc_detections =
+                pattern_matcher
+                    .find_ioc_matches(line)
+                    .into_iter()
+                    .map(move |ioc_match| {
+                        create_ioc_detection(&ioc_match, snippet_path, index + 1, line)
+                    });
+            rule_detections.chain(ioc_detections).collect::<Vec<_>>()
+        })
+        .filter_map(|detection| engine.add_detection(detection))
+        .filter(|detection| detection.pattern_name == rule.name)
+        .count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::rules::{CorrelationTest, MatchMode, PatternConfig, RegexRule, TriggerRule};
+
+    fn test_rules() -> Rules {
+        Rules {
+            patterns: PatternConfig {
+                error_patterns: vec!["ERROR".into()],
+                ..Default::default()
+            },
+            regex_rules: vec![
+                RegexRule {
+                    name: "auth_failure".to_string(),
+                    pattern: "Failed password".to_string(),
+                    severity: "high".to_string(),
+                    should_match: vec![
+                        "sshd: Failed password for root".to_string(),
+                        "ERROR Failed password for root".to_string(),
+                    ],
+                    should_not_match: vec!["sshd: Accepted password for root".to_string()],
+                    ..Default::default()
+                },
+                RegexRule {
+                    name: "login".to_string(),
+                    pattern: "Accepted password".to_string(),
+                    severity: "info".to_string(),
+                    ..Default::default()
+                },
+            ],
+            correlated_rules: vec![CorrelatedRule {
+                name: "brute_force".to_string(),
+                severity: "critical".to_string(),
+                description: "test".to_string(),
+                time_window_seconds: 60,
+                trigger_on_rule: TriggerRule {
+                    name: "auth_failure".to_string(),
+                    count: 2,
+                },
+                followed_by: "login".to_string(),
+                tests: vec![
+                    CorrelationTest {
+                        log: "Failed password\nFailed password\nAccepted password\n".to_string(),
+                        expect_detections: 1,
+                    },
+                    CorrelationTest {
+                        log: "Failed password\nAccepted password\n".to_string(),
+                        expect_detections: 1,
+                    },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reports_pass_and_fail_per_case() {
+        let results = run_rule_tests(&test_rules()).unwrap();
+        let summary: Vec<_> = results
+            .iter()
+            .map(|r| (r.case.as_str(), r.passed))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("should match: sshd: Failed password for root", true),
+                ("should match: ERROR Failed password for root", false),
+                ("should not match: sshd: Accepted password for root", true),
+                ("log snippet #1", true),
+                ("log snippet #2", false),
+            ]
+        );
+        assert_eq!(
+            results[1].detail.as_deref(),
+            Some("matched by \"ERROR\" instead")
+        );
+        assert_eq!(
+            results[4].detail.as_deref(),
+            Some("expected 1 detection(s), got 0")
+        );
+    }
+
+    #[test]
+    fn all_match_mode_lets_shadowed_rules_pass() {
+        let mut rules = test_rules();
+        rules.match_mode = Some(MatchMode::All);
+        let results = run_rule_tests(&rules).unwrap();
+        assert!(results[1].passed, "{:?}", results[1]);
+    }
+}
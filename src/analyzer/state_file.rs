@@ -0,0 +1,79 @@
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Minimum time between two writes of a state file.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Learned analyzer state kept in a JSON file across runs, such as login
+/// histories or known field values. Without a path it lives in memory only.
+///
+/// Changes are written back at most every few seconds and on `flush`,
+/// through a temporary file so a crash never leaves a truncated file.
+pub struct StateFile<T> {
+    path: Option<PathBuf>,
+    pub data: T,
+    dirty: bool,
+    last_save: Instant,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> StateFile<T> {
+    /// Load `path`, starting from `T::default()` if it does not exist yet.
+    pub fn open(path: Option<&Path>) -> anyhow::Result<Self> {
+        let data = match path {
+            Some(path) if path.exists() => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("Cannot read {}", path.display()))?;
+                serde_json::from_str(&content)
+                    .with_context(|| format!("Invalid JSON in {}", path.display()))?
+            }
+            _ => T::default(),
+        };
+        Ok(StateFile {
+            path: path.map(Path::to_path_buf),
+            data,
+            dirty: false,
+            last_save: Instant::now(),
+        })
+    }
+
+    /// Record that `data` changed, saving it if the last save is old enough.
+    pub fn changed(&mut self) {
+        self.dirty = true;
+        if self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.save();
+        }
+    }
+
+    /// Save pending changes now.
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.save();
+        }
+    }
+
+    /// Write the file, staying dirty on failure so the next save retries.
+    fn save(&mut self) {
+        self.last_save = Instant::now();
+        let Some(path) = &self.path else {
+            self.dirty = false;
+            return;
+        };
+        match write_atomically(path, &self.data) {
+            Ok(()) => self.dirty = false,
+            Err(e) => eprintln!("Failed to save state to {}: {e:#}", path.display()),
+        }
+    }
+}
+
+fn write_atomically<T: Serialize>(path: &Path, data: &T) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, serde_json::to_vec(data)?)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
@@ -0,0 +1,260 @@
+use crate::analyzer::condition::{CompiledCondition, LineContext};
+use crate::analyzer::network::NetworkSets;
+use crate::config::rules::SuppressRule;
+use crate::output::Detection;
+use chrono::{DateTime, Days, FixedOffset, Local, NaiveDate, NaiveTime, TimeZone};
+use regex::Regex;
+use std::collections::HashSet;
+use std::path::Path;
+use toml::value::{Datetime, Offset};
+
+/// The compiled `[[suppress_rules]]` of a rule set.
+#[derive(Default)]
+pub struct Suppressor {
+    rules: Vec<CompiledSuppressRule>,
+}
+
+struct CompiledSuppressRule {
+    name: String,
+    pattern: Option<Regex>,
+    condition: Option<CompiledCondition>,
+    rules: HashSet<String>,
+    files: Vec<glob::Pattern>,
+    expires_at: Option<DateTime<Local>>,
+}
+
+impl Suppressor {
+    pub fn new(rules: &[SuppressRule], networks: &NetworkSets) -> anyhow::Result<Self> {
+        let mut compiled = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let context =
+                |e: anyhow::Error| anyhow::anyhow!("suppress rule \"{}\": {e}", rule.name);
+            compiled.push(CompiledSuppressRule {
+                name: rule.name.clone(),
+                pattern: (!rule.pattern.is_empty())
+                    .then(|| Regex::new(&rule.pattern))
+                    .transpose()
+                    .map_err(|e| context(e.into()))?,
+                condition: rule
+                    .condition
+                    .as_ref()
+                    .map(|condition| CompiledCondition::compile(condition, networks))
+                    .transpose()
+                    .map_err(context)?,
+                rules: rule.rules.iter().cloned().collect(),
+                files: rule
+                    .files
+                    .iter()
+                    .map(|file| glob::Pattern::new(file))
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| context(e.into()))?,
+                expires_at: rule
+                    .expires
+                    .as_ref()
+                    .map(expiry_deadline)
+                    .transpose()
+                    .map_err(context)?,
+            });
+        }
+        Ok(Suppressor { rules: compiled })
+    }
+
+    /// Name of the first unexpired suppress rule covering `detection`.
+    /// Conditions also see the fields attached to the detection.
+    pub fn suppressed_by(&self, detection: &Detection) -> Option<&str> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        self.suppressed_by_at(detection, Local::now())
+    }
+
+    fn suppressed_by_at(&self, detection: &Detection, now: DateTime<Local>) -> Option<&str> {
+        let context =
+            LineContext::new(&detection.matched_line).with_known_fields(&detection.fields);
+        self.rules
+            .iter()
+            .find(|rule| rule.covers(detection, &context, now))
+            .map(|rule| rule.name.as_str())
+    }
+}
+
+impl CompiledSuppressRule {
+    fn covers(
+        &self,
+        detection: &Detection,
+        context: &LineContext<'_>,
+        now: DateTime<Local>,
+    ) -> bool {
+        if self.expires_at.is_some_and(|deadline| now >= deadline) {
+            return false;
+        }
+        if !self.rules.is_empty() && !self.rules.contains(&detection.pattern_name) {
+            return false;
+        }
+        if !self.files.is_empty() {
+            let path = Path::new(&detection.file_path);
+            let name = path.file_name().map(|n| n.to_string_lossy());
+            let file_matches = self.files.iter().any(|glob| {
+                glob.matches_path(path) || name.as_deref().is_some_and(|n| glob.matches(n))
+            });
+            if !file_matches {
+                return false;
+            }
+        }
+        self.pattern
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(&detection.matched_line))
+            && self
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.matches(context))
+    }
+}
+
+/// The instant an `expires` value stops applying. A bare date covers that
+/// whole day in local time; a date-time without an offset is local time.
+pub fn expiry_deadline(expires: &Datetime) -> anyhow::Result<DateTime<Local>> {
+    let date = expires
+        .date
+        .ok_or_else(|| anyhow::anyhow!("expires needs a date, got \"{expires}\""))?;
+    let day = NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())
+        .ok_or_else(|| anyhow::anyhow!("invalid expiry date \"{expires}\""))?;
+
+    let Some(time) = expires.time else {
+        let next_day = day
+            .checked_add_days(Days::new(1))
+            .ok_or_else(|| anyhow::anyhow!("invalid expiry date \"{expires}\""))?;
+        return local_deadline(next_day.and_time(NaiveTime::MIN), expires);
+    };
+    let time = NaiveTime::from_hms_nano_opt(
+        time.hour.into(),
+        time.minute.into(),
+        time.second.into(),
+        time.nanosecond,
+    )
+    .ok_or_else(|| anyhow::anyhow!("invalid expiry time \"{expires}\""))?;
+    let naive = day.and_time(time);
+
+    let offset_minutes = match expires.offset {
+        None => return local_deadline(naive, expires),
+        Some(Offset::Z) => 0,
+        Some(Offset::Custom { minutes }) => i32::from(minutes),
+    };
+    let offset = FixedOffset::east_opt(offset_minutes * 60)
+        .ok_or_else(|| anyhow::anyhow!("invalid expiry offset \"{expires}\""))?;
+    offset
+        .from_local_datetime(&naive)
+        .single()
+        .map(|deadline| deadline.with_timezone(&Local))
+        .ok_or_else(|| anyhow::anyhow!("invalid expiry \"{expires}\""))
+}
+
+fn local_deadline(
+    naive: chrono::NaiveDateTime,
+    expires: &Datetime,
+) -> anyhow::Result<DateTime<Local>> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| anyhow::anyhow!("expiry \"{expires}\" does not exist in local time"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::console::create_detection;
+
+    fn suppressor(toml_rules: &str) -> Suppressor {
+        #[derive(serde::Deserialize)]
+        struct Wrapper {
+            suppress_rules: Vec<SuppressRule>,
+        }
+        let wrapper: Wrapper = toml::from_str(toml_rules).unwrap();
+        Suppressor::new(&wrapper.suppress_rules, &NetworkSets::default()).unwrap()
+    }
+
+    fn detection(rule: &str, file: &str, line: &str) -> Detection {
+        create_detection("high", Path::new(file), 1, line, rule)
+    }
+
+    #[test]
+    fn scopes_by_rule_file_and_line() {
+        let suppressor = suppressor(
+            r#"
+            [[suppress_rules]]
+            name = "backup_logins"
+            rules = ["auth_failure"]
+            files = ["auth*.log"]
+            condition = { field = { name = "user", equals = "backup" } }
+
+            [[suppress_rules]]
+            name = "scanner"
+            pattern = "from 10\\.0\\.0\\.9\\b"
+            "#,
+        );
+        let backup = "auth failure user=backup";
+        assert_eq!(
+            suppressor.suppressed_by(&detection("auth_failure", "/var/log/auth.log", backup)),
+            Some("backup_logins")
+        );
+        assert_eq!(
+            suppressor.suppressed_by(&detection("sudo_failure", "/var/log/auth.log", backup)),
+            None
+        );
+        assert_eq!(
+            suppressor.suppressed_by(&detection("auth_failure", "/var/log/syslog.log", backup)),
+            None
+        );
+        assert_eq!(
+            suppressor.suppressed_by(&detection(
+                "auth_failure",
+                "/var/log/auth.log",
+                "auth failure user=root"
+            )),
+            None
+        );
+        assert_eq!(
+            suppressor.suppressed_by(&detection("any", "x.log", "probe from 10.0.0.9 port 22")),
+            Some("scanner")
+        );
+    }
+
+    #[test]
+    fn expired_rules_stop_applying() {
+        let suppressor = suppressor(
+            r#"
+            [[suppress_rules]]
+            name = "temporary"
+            pattern = "noise"
+            expires = 2024-03-31
+            "#,
+        );
+        let noise = detection("any", "x.log", "noise");
+        let at = |s: &str| {
+            Local
+                .from_local_datetime(&s.parse::<chrono::NaiveDateTime>().unwrap())
+                .unwrap()
+        };
+        assert_eq!(
+            suppressor.suppressed_by_at(&noise, at("2024-03-31T23:59:59")),
+            Some("temporary")
+        );
+        assert_eq!(
+            suppressor.suppressed_by_at(&noise, at("2024-04-01T00:00:00")),
+            None
+        );
+    }
+
+    #[test]
+    fn expiry_with_offset_is_an_instant() {
+        let expires: Datetime = "2024-03-31T12:00:00Z".parse().unwrap();
+        assert_eq!(
+            expiry_deadline(&expires)
+                .unwrap()
+                .with_timezone(&chrono::Utc)
+                .to_rfc3339(),
+            "2024-03-31T12:00:00+00:00"
+        );
+        assert!(expiry_deadline(&"12:00:00".parse().unwrap()).is_err());
+    }
+}
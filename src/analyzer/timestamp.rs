@@ -0,0 +1,126 @@
+use crate::analyzer::condition::LineContext;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Utc};
+
+/// Fields checked for the event time when no field is configured.
+const TIME_FIELDS: [&str; 5] = ["timestamp", "@timestamp", "time", "ts", "date"];
+
+/// When the line says the event happened.
+///
+/// Uses `field` when given; otherwise the first of the usual time fields
+/// (`timestamp`, `@timestamp`, `time`, ...) and then a timestamp at the
+/// start of the line. Returns `None` if nothing parses.
+pub fn event_time(context: &LineContext<'_>, field: Option<&str>) -> Option<DateTime<Utc>> {
+    if let Some(field) = field {
+        return context.field(field).and_then(parse_timestamp);
+    }
+    TIME_FIELDS
+        .iter()
+        .filter_map(|name| context.extracted_field(name))
+        .find_map(parse_timestamp)
+        .or_else(|| leading_timestamp(context.line()))
+}
+
+/// A timestamp made of the first one to three words of `line`, e.g.
+/// `2024-05-01T10:00:00Z`, `2024-05-01 10:00:00,123` or `May  1 10:00:00`.
+fn leading_timestamp(line: &str) -> Option<DateTime<Utc>> {
+    let words: Vec<&str> = line
+        .split_whitespace()
+        .take(3)
+        .map(|word| word.trim_start_matches('[').trim_end_matches([']', ':']))
+        .collect();
+    (1..=words.len())
+        .rev()
+        .find_map(|count| parse_timestamp(&words[..count].join(" ")))
+}
+
+/// Parse one timestamp: RFC 3339 / ISO 8601 with or without an offset
+/// (local time when missing), Unix seconds or milliseconds, or a syslog
+/// `Mmm dd hh:mm:ss` stamp, which is assumed to be from the last year.
+pub fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.to_utc());
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f%:z", "%Y-%m-%dT%H:%M:%S%.f%z"] {
+        if let Ok(time) = DateTime::parse_from_str(value, format) {
+            return Some(time.to_utc());
+        }
+    }
+    let decimal_comma = value.replacen(',', ".", 1);
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(&decimal_comma, format) {
+            return local(time);
+        }
+    }
+    if value.len() >= 9 && value.bytes().all(|b| b.is_ascii_digit()) {
+        let number: i64 = value.parse().ok()?;
+        return match value.len() {
+            13 => DateTime::from_timestamp_millis(number),
+            _ => DateTime::from_timestamp(number, 0),
+        };
+    }
+    syslog_timestamp(value)
+}
+
+fn syslog_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    let now = Local::now();
+    let with_year = format!("{} {value}", now.year());
+    let time = NaiveDateTime::parse_from_str(&with_year, "%Y %b %d %H:%M:%S%.f").ok()?;
+    // A stamp later than tomorrow was written last year
+    let time = if time > now.naive_local() + chrono::Duration::days(1) {
+        time.with_year(now.year() - 1)?
+    } else {
+        time
+    };
+    local(time)
+}
+
+fn local(time: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map(|time| time.to_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().to_utc()
+    }
+
+    #[test]
+    fn parses_common_formats() {
+        let expected = utc("2024-05-01T10:00:00Z");
+        assert_eq!(parse_timestamp("2024-05-01T10:00:00Z"), Some(expected));
+        assert_eq!(parse_timestamp("2024-05-01T12:00:00+02:00"), Some(expected));
+        assert_eq!(parse_timestamp("2024-05-01 10:00:00+00:00"), Some(expected));
+        assert_eq!(parse_timestamp("1714557600"), Some(expected));
+        assert_eq!(parse_timestamp("1714557600000"), Some(expected));
+        assert!(parse_timestamp("2024-05-01 10:00:00,250").is_some());
+        assert!(parse_timestamp("May  1 10:00:00").is_some());
+        assert_eq!(parse_timestamp("sshd[42]"), None);
+    }
+
+    #[test]
+    fn finds_the_time_of_a_line() {
+        let time = |line: &str, field: Option<&str>| event_time(&LineContext::new(line), field);
+        assert_eq!(
+            time("[2024-05-01T10:00:00Z] sshd: Accepted password", None),
+            Some(utc("2024-05-01T10:00:00Z"))
+        );
+        assert_eq!(
+            time(
+                r#"{"@timestamp":"2024-05-01T10:00:00Z","msg":"login"}"#,
+                None
+            ),
+            Some(utc("2024-05-01T10:00:00Z"))
+        );
+        assert_eq!(
+            time("login at=1714557600 user=alice", Some("at")),
+            Some(utc("2024-05-01T10:00:00Z"))
+        );
+        assert_eq!(time("no time here", None), None);
+    }
+}
@@ -0,0 +1,694 @@
+use crate::analyzer::condition::LineContext;
+use crate::analyzer::geoip::GeoIp;
+use crate::analyzer::indicators::extract_ips;
+use crate::analyzer::network::{mask, Cidr, NetworkSets};
+use crate::analyzer::state_file::StateFile;
+use crate::analyzer::timestamp::event_time;
+use crate::config::rules::{Site, TravelRule};
+use crate::output::{Detection, DetectionKind, Severity};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+/// Logins kept per user; travel is measured against the one nearest in time.
+const RECENT_LOGINS: usize = 10;
+/// Networks kept per user; the least recently used are forgotten first.
+const MAX_NETWORKS: usize = 50;
+/// Networks a user has not logged in from for this long before their
+/// latest login are forgotten, and count as new again.
+const NETWORK_RETENTION_DAYS: i64 = 90;
+const HISTORY_VERSION: u32 = 2;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+/// Without a site or an ASN, logins from the same /24 or /48 share a network.
+const IPV4_NETWORK_PREFIX: u8 = 24;
+const IPV6_NETWORK_PREFIX: u8 = 48;
+
+/// Every `[[travel_rules]]` entry with the login history it has learned.
+///
+/// Logins from several files update the same history, so one tracker is
+/// shared by every scan; histories with a `history` path are saved through
+/// `StateFile`, and once more when the tracker is dropped.
+#[derive(Default)]
+pub struct LoginTracker {
+    rules: Vec<TrackedRule>,
+    sites: Vec<CompiledSite>,
+}
+
+struct TrackedRule {
+    rule: TravelRule,
+    history: Mutex<StateFile<LoginHistory>>,
+}
+
+struct CompiledSite {
+    name: String,
+    networks: Vec<Cidr>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+/// On-disk form of one rule's history.
+#[derive(Serialize, Deserialize)]
+struct LoginHistory {
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    users: BTreeMap<String, UserHistory>,
+}
+
+impl Default for LoginHistory {
+    fn default() -> Self {
+        LoginHistory {
+            version: HISTORY_VERSION,
+            users: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(from = "StoredUserHistory")]
+struct UserHistory {
+    /// The networks the user has logged in from, with the time of the
+    /// latest login from each
+    networks: BTreeMap<String, DateTime<Utc>>,
+    /// The latest logins, oldest first
+    recent: Vec<Login>,
+}
+
+/// A `UserHistory` as saved by any version; version 1 kept the networks
+/// without times.
+#[derive(Deserialize)]
+struct StoredUserHistory {
+    #[serde(default)]
+    networks: StoredNetworks,
+    #[serde(default)]
+    recent: Vec<Login>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredNetworks {
+    Seen(BTreeMap<String, DateTime<Utc>>),
+    Listed(BTreeSet<String>),
+}
+
+impl Default for StoredNetworks {
+    fn default() -> Self {
+        StoredNetworks::Seen(BTreeMap::new())
+    }
+}
+
+impl From<StoredUserHistory> for UserHistory {
+    fn from(stored: StoredUserHistory) -> Self {
+        let networks = match stored.networks {
+            StoredNetworks::Seen(networks) => networks,
+            // Count them as seen at the user's latest login
+            StoredNetworks::Listed(networks) => {
+                let time = stored
+                    .recent
+                    .last()
+                    .map_or(DateTime::UNIX_EPOCH, |login| login.time);
+                networks
+                    .into_iter()
+                    .map(|network| (network, time))
+                    .collect()
+            }
+        };
+        UserHistory {
+            networks,
+            recent: stored.recent,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Login {
+    time: DateTime<Utc>,
+    ip: IpAddr,
+    /// `site:<name>`, `AS<number>` or the enclosing /24 or /48
+    network: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    longitude: Option<f64>,
+}
+
+impl LoginTracker {
+    pub fn new(
+        rules: &[TravelRule],
+        sites: &BTreeMap<String, Site>,
+        networks: &NetworkSets,
+    ) -> anyhow::Result<Self> {
+        let mut compiled_sites = Vec::with_capacity(sites.len());
+        for (name, site) in sites {
+            compiled_sites.push(CompiledSite {
+                name: name.clone(),
+                networks: networks
+                    .resolve(&site.networks)
+                    .with_context(|| format!("Invalid networks for site \"{name}\""))?,
+                latitude: site.latitude,
+                longitude: site.longitude,
+            });
+        }
+
+        let mut tracked = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let history = StateFile::open(rule.history.as_deref())
+                .with_context(|| format!("Failed to load login history of \"{}\"", rule.name))?;
+            tracked.push(TrackedRule {
+                rule: rule.clone(),
+                history: Mutex::new(history),
+            });
+        }
+        Ok(LoginTracker {
+            rules: tracked,
+            sites: compiled_sites,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Record `detection` as a login for every travel rule that lists its
+    /// rule, returning impossible-travel and new-network detections.
+    pub fn observe(&self, detection: &Detection, geoip: Option<&GeoIp>) -> Vec<Detection> {
+        let mut found = Vec::new();
+        for tracked in &self.rules {
+            let rule = &tracked.rule;
+            if !rule.rules.contains(&detection.pattern_name) {
+                continue;
+            }
+            let context =
+                LineContext::new(&detection.matched_line).with_known_fields(&detection.fields);
+            let Some(user) = context.field(&rule.user_field).filter(|u| !u.is_empty()) else {
+                continue;
+            };
+            let Some(ip) = source_ip(rule, &context) else {
+                continue;
+            };
+            let time = event_time(&context, rule.time_field.as_deref());
+
+            let mut state = tracked.history.lock().unwrap();
+            let history = state.data.users.entry(user.to_string()).or_default();
+            // An untimed login counts as seen at the user's latest login, so
+            // the scan's own clock never mixes with the event times
+            let login = self.login(
+                ip,
+                time.or_else(|| history.latest()).unwrap_or_else(Utc::now),
+                geoip,
+            );
+            if rule.new_network
+                && !history.networks.is_empty()
+                && !history.networks.contains_key(&login.network)
+            {
+                found.push(tracked.detection(detection, user, &login, BTreeMap::new()));
+            }
+            // Without an event time the speed between logins is unknown
+            let Some(time) = time else {
+                history.record_network(&login);
+                state.changed();
+                continue;
+            };
+            if let Some(fields) = history
+                .nearest(time)
+                .and_then(|previous| impossible_travel(rule, previous, &login))
+            {
+                found.push(tracked.detection(detection, user, &login, fields));
+            }
+            history.record(login);
+            state.changed();
+        }
+        found
+    }
+
+    /// Write every changed history to disk.
+    pub fn flush(&self) {
+        for tracked in &self.rules {
+            tracked.history.lock().unwrap().flush();
+        }
+    }
+
+    fn login(&self, ip: IpAddr, time: DateTime<Utc>, geoip: Option<&GeoIp>) -> Login {
+        let mut login = Login {
+            time,
+            ip,
+            network: mask(ip, network_prefix(ip)).to_string(),
+            location: None,
+            latitude: None,
+            longitude: None,
+        };
+        if let Some(site) = self
+            .sites
+            .iter()
+            .find(|site| site.networks.iter().any(|cidr| cidr.contains(ip)))
+        {
+            login.network = format!("site:{}", site.name);
+            login.location = Some(site.name.clone());
+            login.latitude = site.latitude;
+            login.longitude = site.longitude;
+        } else if let Some(info) = geoip.and_then(|geoip| geoip.lookup(ip)) {
+            if let Some(asn) = info.asn {
+                login.network = format!("AS{asn}");
+            }
+            login.location = match (&info.city, &info.country) {
+                (Some(city), Some(country)) => Some(format!("{city}, {country}")),
+                (city, country) => city.clone().or(country.clone()),
+            };
+            login.latitude = info.latitude;
+            login.longitude = info.longitude;
+        }
+        login
+    }
+}
+
+impl Drop for LoginTracker {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl TrackedRule {
+    /// A detection for `login`; without travel `fields` it reports a new
+    /// network.
+    fn detection(
+        &self,
+        login_detection: &Detection,
+        user: &str,
+        login: &Login,
+        travel_fields: BTreeMap<String, String>,
+    ) -> Detection {
+        let (reason, description) = match (
+            travel_fields.get("travel.distance_km"),
+            travel_fields.get("travel.speed_kmh"),
+        ) {
+            (Some(distance), Some(speed)) => (
+                "impossible_travel",
+                format!("{user} logged in {distance} km from the previous login, at {speed} km/h"),
+            ),
+            _ => (
+                "new_network",
+                format!("{user} logged in from new network {}", login.network),
+            ),
+        };
+        let mut fields = BTreeMap::from([
+            ("travel.reason".to_string(), reason.to_string()),
+            ("travel.user".to_string(), user.to_string()),
+            ("travel.ip".to_string(), login.ip.to_string()),
+            ("travel.network".to_string(), login.network.clone()),
+        ]);
+        if let Some(location) = &login.location {
+            fields.insert("travel.location".to_string(), location.clone());
+        }
+        fields.extend(travel_fields);
+        Detection {
+            kind: DetectionKind::Travel,
+            severity: Severity::from(self.rule.severity.as_str()),
+            file_path: login_detection.file_path.clone(),
+            line_number: login_detection.line_number,
+            matched_line: login_detection.matched_line.clone(),
+            pattern_name: self.rule.name.clone(),
+            description,
+            related_lines: Vec::new(),
+            fields,
+        }
+    }
+}
+
+impl UserHistory {
+    /// The recorded login with coordinates closest in time to `time`.
+    fn nearest(&self, time: DateTime<Utc>) -> Option<&Login> {
+        self.recent
+            .iter()
+            .filter(|login| login.latitude.is_some() && login.longitude.is_some())
+            .min_by_key(|login| (login.time - time).abs())
+    }
+
+    /// The time of the latest login from any network.
+    fn latest(&self) -> Option<DateTime<Utc>> {
+        self.networks.values().max().copied()
+    }
+
+    fn record(&mut self, login: Login) {
+        self.record_network(&login);
+        let position = self.recent.partition_point(|l| l.time <= login.time);
+        self.recent.insert(position, login);
+        if self.recent.len() > RECENT_LOGINS {
+            self.recent.remove(0);
+        }
+    }
+
+    /// Remember the login's network without keeping the login itself.
+    fn record_network(&mut self, login: &Login) {
+        let last_seen = self
+            .networks
+            .entry(login.network.clone())
+            .or_insert(login.time);
+        *last_seen = (*last_seen).max(login.time);
+        self.forget_networks();
+    }
+
+    /// Drop networks unused for `NETWORK_RETENTION_DAYS`, then the least
+    /// recently used ones beyond `MAX_NETWORKS`.
+    fn forget_networks(&mut self) {
+        let Some(latest) = self.latest() else {
+            return;
+        };
+        let cutoff = latest - chrono::Duration::days(NETWORK_RETENTION_DAYS);
+        self.networks.retain(|_, last_seen| *last_seen >= cutoff);
+        while self.networks.len() > MAX_NETWORKS {
+            let Some(oldest) = self
+                .networks
+                .iter()
+                .min_by_key(|(_, last_seen)| **last_seen)
+                .map(|(network, _)| network.clone())
+            else {
+                break;
+            };
+            self.networks.remove(&oldest);
+        }
+    }
+}
+
+/// Fields describing the trip from `previous` to `login` if it is longer
+/// than `min_distance_km` and faster than `max_speed_kmh`.
+fn impossible_travel(
+    rule: &TravelRule,
+    previous: &Login,
+    login: &Login,
+) -> Option<BTreeMap<String, String>> {
+    let distance = distance_km(
+        (previous.latitude?, previous.longitude?),
+        (login.latitude?, login.longitude?),
+    );
+    if distance < rule.min_distance_km {
+        return None;
+    }
+    let elapsed = (login.time - previous.time).num_seconds().unsigned_abs();
+    let speed = distance / (elapsed as f64 / 3600.0);
+    if speed <= rule.max_speed_kmh {
+        return None;
+    }
+
+    let mut fields = BTreeMap::from([
+        ("travel.previous_ip".to_string(), previous.ip.to_string()),
+        (
+            "travel.previous_time".to_string(),
+            previous.time.to_rfc3339(),
+        ),
+        ("travel.distance_km".to_string(), format!("{distance:.0}")),
+        ("travel.elapsed_seconds".to_string(), elapsed.to_string()),
+        ("travel.speed_kmh".to_string(), format!("{speed:.0}")),
+    ]);
+    if let Some(location) = &previous.location {
+        fields.insert("travel.previous_location".to_string(), location.clone());
+    }
+    Some(fields)
+}
+
+/// Great-circle distance between two `(latitude, longitude)` points.
+fn distance_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lon2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+fn network_prefix(ip: IpAddr) -> u8 {
+    if ip.is_ipv4() {
+        IPV4_NETWORK_PREFIX
+    } else {
+        IPV6_NETWORK_PREFIX
+    }
+}
+
+/// The login's source address: the configured field, else the address
+/// GeoIP enrichment picked, else the first address in the line.
+fn source_ip(rule: &TravelRule, context: &LineContext<'_>) -> Option<IpAddr> {
+    if let Some(field) = &rule.ip_field {
+        return context
+            .field(field)
+            .and_then(|value| extract_ips(value).next());
+    }
+    context
+        .field("geo.ip")
+        .and_then(|value| value.parse().ok())
+        .or_else(|| context.ips().first().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::geoip::test_db;
+    use crate::config::rules::GeoIpConfig;
+    use crate::output::console::create_detection;
+    use std::path::{Path, PathBuf};
+
+    fn travel_rule(history: Option<PathBuf>) -> TravelRule {
+        TravelRule {
+            name: "impossible_travel".to_string(),
+            rules: vec!["successful_login".to_string()],
+            user_field: "user".to_string(),
+            ip_field: None,
+            time_field: None,
+            max_speed_kmh: 1000.0,
+            min_distance_km: 100.0,
+            new_network: true,
+            severity: "high".to_string(),
+            history,
+            source: None,
+        }
+    }
+
+    fn login(tracker: &LoginTracker, geoip: Option<&GeoIp>, line: &str) -> Vec<String> {
+        let detection =
+            create_detection("info", Path::new("auth.log"), 1, line, "successful_login");
+        tracker
+            .observe(&detection, geoip)
+            .iter()
+            .map(|d| d.fields["travel.reason"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn reports_impossible_travel_between_geoip_locations() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        test_db::write(
+            &dir.join("city.mmdb"),
+            vec![
+                (
+                    "203.0.113.0/24",
+                    test_db::city("TR", "Istanbul", 41.0, 29.0),
+                ),
+                (
+                    "198.51.100.0/24",
+                    test_db::city("US", "New York", 40.7, -74.0),
+                ),
+                ("192.0.2.0/24", test_db::city("TR", "Izmit", 40.8, 29.9)),
+            ],
+        );
+        let geoip = GeoIp::open(&GeoIpConfig {
+            databases: vec![dir.join("city.mmdb")],
+            fields: Vec::new(),
+        })
+        .unwrap();
+        let tracker = LoginTracker::new(
+            &[travel_rule(None)],
+            &BTreeMap::new(),
+            &NetworkSets::default(),
+        )
+        .unwrap();
+        let geoip = Some(&geoip);
+
+        assert!(login(
+            &tracker,
+            geoip,
+            "2024-05-01T08:00:00Z user=alice src=203.0.113.7"
+        )
+        .is_empty());
+        // 80 km in two hours, from a new /24
+        assert_eq!(
+            login(
+                &tracker,
+                geoip,
+                "2024-05-01T10:00:00Z user=alice src=192.0.2.9"
+            ),
+            vec!["new_network"]
+        );
+        // 8000 km in one hour
+        assert_eq!(
+            login(
+                &tracker,
+                geoip,
+                "2024-05-01T11:00:00Z user=alice src=198.51.100.3"
+            ),
+            vec!["new_network", "impossible_travel"]
+        );
+        // Other users have their own history
+        assert!(login(
+            &tracker,
+            geoip,
+            "2024-05-01T11:00:00Z user=bob src=203.0.113.7"
+        )
+        .is_empty());
+
+        let detection = create_detection(
+            "info",
+            Path::new("auth.log"),
+            7,
+            "2024-05-01T11:30:00Z user=alice src=203.0.113.8",
+            "successful_login",
+        );
+        let found = tracker.observe(&detection, geoip);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, DetectionKind::Travel);
+        assert_eq!(found[0].line_number, 7);
+        assert_eq!(found[0].fields["travel.location"], "Istanbul, TR");
+        assert_eq!(found[0].fields["travel.previous_location"], "New York, US");
+        assert_eq!(found[0].fields["travel.elapsed_seconds"], "1800");
+
+        // Lines without a time only teach networks; the scan's clock would
+        // make these two logins seconds apart
+        assert!(login(&tracker, geoip, "user=carol src=198.51.100.3").is_empty());
+        assert_eq!(
+            login(&tracker, geoip, "user=carol src=203.0.113.7"),
+            vec!["new_network"]
+        );
+    }
+
+    #[test]
+    fn persists_networks_learned_from_sites() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let history = dir.join("state").join("logins.json");
+        let sites = BTreeMap::from([
+            (
+                "office".to_string(),
+                Site {
+                    networks: vec!["internal".to_string()],
+                    latitude: Some(41.0),
+                    longitude: Some(29.0),
+                },
+            ),
+            (
+                "vpn".to_string(),
+                Site {
+                    networks: vec!["198.51.100.0/24".to_string()],
+                    latitude: Some(50.1),
+                    longitude: Some(8.7),
+                },
+            ),
+        ]);
+        let networks = NetworkSets::new(&BTreeMap::from([(
+            "internal".to_string(),
+            vec!["10.0.0.0/8".to_string()],
+        )]))
+        .unwrap();
+        let rules = [travel_rule(Some(history.clone()))];
+
+        let tracker = LoginTracker::new(&rules, &sites, &networks).unwrap();
+        assert!(login(
+            &tracker,
+            None,
+            "2024-05-01T08:00:00Z user=alice src=10.1.2.3"
+        )
+        .is_empty());
+        drop(tracker);
+        assert!(history.exists());
+
+        let tracker = LoginTracker::new(&rules, &sites, &networks).unwrap();
+        // Same site, different address
+        assert!(login(
+            &tracker,
+            None,
+            "2024-05-01T09:00:00Z user=alice src=10.9.9.9"
+        )
+        .is_empty());
+        // Frankfurt, 1860 km away, 20 minutes later
+        assert_eq!(
+            login(
+                &tracker,
+                None,
+                "2024-05-01T09:20:00Z user=alice src=198.51.100.3"
+            ),
+            vec!["new_network", "impossible_travel"]
+        );
+    }
+
+    #[test]
+    fn forgets_networks_unused_for_the_retention_period() {
+        let tracker = LoginTracker::new(
+            &[travel_rule(None)],
+            &BTreeMap::new(),
+            &NetworkSets::default(),
+        )
+        .unwrap();
+
+        assert!(login(
+            &tracker,
+            None,
+            "2024-01-01T08:00:00Z user=alice src=10.0.0.1"
+        )
+        .is_empty());
+        assert_eq!(
+            login(
+                &tracker,
+                None,
+                "2024-06-01T08:00:00Z user=alice src=10.0.1.1"
+            ),
+            vec!["new_network"]
+        );
+        // The first network was last used five months earlier
+        assert_eq!(
+            login(
+                &tracker,
+                None,
+                "2024-06-02T08:00:00Z user=alice src=10.0.0.2"
+            ),
+            vec!["new_network"]
+        );
+
+        let mut history = UserHistory::default();
+        let start = DateTime::UNIX_EPOCH;
+        for i in 0..MAX_NETWORKS + 5 {
+            history.record(Login {
+                time: start + chrono::Duration::minutes(i as i64),
+                ip: IpAddr::from([10, 0, i as u8, 1]),
+                network: format!("10.0.{i}.0/24"),
+                location: None,
+                latitude: None,
+                longitude: None,
+            });
+        }
+        assert_eq!(history.networks.len(), MAX_NETWORKS);
+        assert!(!history.networks.contains_key("10.0.4.0/24"));
+        assert!(history.networks.contains_key("10.0.5.0/24"));
+    }
+
+    #[test]
+    fn reads_version_1_histories() {
+        let history: LoginHistory = serde_json::from_str(
+            r#"{"version":1,"users":{"alice":{"networks":["10.0.0.0/24"],"recent":[
+                {"time":"2024-05-01T08:00:00Z","ip":"10.0.0.1","network":"10.0.0.0/24"}]}}}"#,
+        )
+        .unwrap();
+        let networks = &history.users["alice"].networks;
+        assert_eq!(
+            networks["10.0.0.0/24"].to_rfc3339(),
+            "2024-05-01T08:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn measures_great_circle_distance() {
+        let istanbul_to_new_york = distance_km((41.0, 29.0), (40.7, -74.0));
+        assert!((istanbul_to_new_york - 8070.0).abs() < 30.0);
+        assert_eq!(distance_km((41.0, 29.0), (41.0, 29.0)), 0.0);
+    }
+}
@@ -1,2 +1,4 @@
 // src/config/mod.rs
 pub mod rules;
+pub mod sigma;
+pub mod validate;
@@ -1,30 +1,933 @@
+use crate::analyzer::indicators::IndicatorKind;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct PatternConfig {
     #[serde(default)]
-    pub error_patterns: Vec<String>,
+    pub error_patterns: Vec<StringPattern>,
     #[serde(default)]
-    pub warning_patterns: Vec<String>,
+    pub warning_patterns: Vec<StringPattern>,
+    /// Matching options for every entry of `error_patterns`; an empty
+    /// table in a later layer resets them to the defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_options: Option<PatternOptions>,
+    /// Matching options for every entry of `warning_patterns`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning_options: Option<PatternOptions>,
+}
+
+/// An `error_patterns` / `warning_patterns` entry: either a bare substring
+/// or a table with its own name, severity and matching options.
+///
+/// ```toml
+/// error_patterns = [
+///     "ERROR",
+///     { pattern = "out of memory", name = "oom", severity = "critical", ignore_case = true },
+/// ]
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum StringPattern {
+    Plain(String),
+    Entry(PatternEntry),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct PatternEntry {
+    pub pattern: String,
+    /// Rule name reported in detections (default: the pattern text)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Severity override (default: ERROR or WARNING, from the list)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+    /// Per-entry overrides of the list options
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore_case: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whole_word: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<Anchor>,
+    /// Keep evaluating later rules after this one matches
+    #[serde(
+        rename = "continue",
+        default,
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub continue_matching: bool,
+}
+
+/// How a string pattern is compared with the line.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PatternOptions {
+    #[serde(default)]
+    pub ignore_case: bool,
+    /// Word characters may not directly precede or follow the match
+    #[serde(default)]
+    pub whole_word: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<Anchor>,
+}
+
+/// Where in the line a string pattern has to sit.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    /// The line starts with the pattern
+    Start,
+    /// The line ends with the pattern
+    End,
+    /// The line is exactly the pattern
+    Line,
+}
+
+impl PatternOptions {
+    pub fn is_default(&self) -> bool {
+        *self == PatternOptions::default()
+    }
+}
+
+impl StringPattern {
+    /// The substring to look for.
+    pub fn pattern(&self) -> &str {
+        match self {
+            StringPattern::Plain(pattern) => pattern,
+            StringPattern::Entry(entry) => &entry.pattern,
+        }
+    }
+
+    /// Rule name reported in detections.
+    pub fn name(&self) -> &str {
+        match self {
+            StringPattern::Entry(PatternEntry {
+                name: Some(name), ..
+            }) => name,
+            _ => self.pattern(),
+        }
+    }
+
+    pub fn severity(&self) -> Option<&str> {
+        match self {
+            StringPattern::Plain(_) => None,
+            StringPattern::Entry(entry) => entry.severity.as_deref(),
+        }
+    }
+
+    /// Whether later rules are still evaluated after this one matches.
+    pub fn continue_matching(&self) -> bool {
+        match self {
+            StringPattern::Plain(_) => false,
+            StringPattern::Entry(entry) => entry.continue_matching,
+        }
+    }
+
+    /// The list options with this entry's overrides applied.
+    pub fn options(&self, list_options: &PatternOptions) -> PatternOptions {
+        match self {
+            StringPattern::Plain(_) => *list_options,
+            StringPattern::Entry(entry) => PatternOptions {
+                ignore_case: entry.ignore_case.unwrap_or(list_options.ignore_case),
+                whole_word: entry.whole_word.unwrap_or(list_options.whole_word),
+                anchor: entry.anchor.or(list_options.anchor),
+            },
+        }
+    }
+}
+
+impl From<&str> for StringPattern {
+    fn from(pattern: &str) -> Self {
+        StringPattern::Plain(pattern.to_string())
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Rules {
+    /// Rule files merged in before this one, relative to this file; globs allowed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// `first` (default) stops at the first matching rule for a line unless
+    /// it sets `continue = true`; `all` reports every matching rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_mode: Option<MatchMode>,
     #[serde(rename = "rules", default)]
     pub patterns: PatternConfig,
+    /// Named network sets for `in_cidr` / `not_in_cidr` conditions
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub networks: BTreeMap<String, Vec<String>>,
     #[serde(default)]
     pub regex_rules: Vec<RegexRule>,
     #[serde(default)]
     pub frequency_rules: Option<FrequencyRules>,
     #[serde(default)]
     pub correlated_rules: Vec<CorrelatedRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suppress_rules: Vec<SuppressRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ioc_lists: Vec<IocList>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geoip: Option<GeoIpConfig>,
+    /// Named locations for `travel_rules`, keyed by site name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sites: BTreeMap<String, Site>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub travel_rules: Vec<TravelRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub new_value_rules: Vec<NewValueRule>,
+    /// Where detections are sent; console only when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<OutputConfig>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct RegexRule {
     pub name: String,
+    /// Regex matched against the line; mutually exclusive with `condition`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub pattern: String,
+    /// Boolean combination of matchers; mutually exclusive with `pattern`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Condition>,
+    pub severity: String,
+    /// Keep evaluating later rules after this one matches
+    #[serde(
+        rename = "continue",
+        default,
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub continue_matching: bool,
+    /// Example lines this rule must detect (run by `rusthound test`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub should_match: Vec<String>,
+    /// Example lines this rule must not detect
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub should_not_match: Vec<String>,
+    /// File the rule was loaded from
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+/// Drops detections known to be noise. Every key that is set must match;
+/// `rules` and `files` narrow the scope, `pattern` and `condition` test the
+/// matched line.
+///
+/// ```toml
+/// [[suppress_rules]]
+/// name = "backup_job_logins"
+/// rules = ["authentication_failure"]
+/// condition = { field = { name = "user", equals = "backup" } }
+/// expires = 2025-06-30
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SuppressRule {
+    pub name: String,
+    /// Regex the matched line must match
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub pattern: String,
+    /// Condition the matched line must satisfy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Condition>,
+    /// Only suppress detections of these rules (default: any rule)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<String>,
+    /// Only suppress detections in files whose path or name matches one of
+    /// these globs (default: any file)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    /// Stop applying after this date (inclusive) or date-time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<toml::value::Datetime>,
+    /// Why the suppression exists, for whoever reads the rules next
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+    /// File the rule was loaded from
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+/// A file of known-bad indicators (IPs, domains, hashes). Lines whose
+/// extracted indicators appear in the list produce a detection named after
+/// the list.
+///
+/// ```toml
+/// [[ioc_lists]]
+/// name = "threat_feed"
+/// path = "iocs/feed.csv"
+/// column = "indicator"
+/// fields = ["src_ip", "query"]
+/// severity = "high"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct IocList {
+    pub name: String,
+    /// List file, relative to the rule file that declares it
+    pub path: PathBuf,
+    /// `text` (one indicator per line) or `csv`; default from the extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<IocFormat>,
+    /// CSV header of the indicator column (default: the first column)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+    /// Indicator kinds to look for (default: all)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub types: Vec<IndicatorKind>,
+    /// Only look inside these extracted fields instead of the whole line
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
     pub severity: String,
+    /// File the list was declared in
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+/// Offline GeoIP / ASN enrichment from MaxMind-format databases. Detections
+/// get `geo.*` fields, which conditions and suppress rules can test.
+///
+/// ```toml
+/// [geoip]
+/// databases = ["GeoLite2-City.mmdb", "GeoLite2-ASN.mmdb"]
+/// fields = ["src_ip"]
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct GeoIpConfig {
+    /// mmdb files, relative to the rule file that declares them
+    pub databases: Vec<PathBuf>,
+    /// Fields holding the address to look up (default: the first address in
+    /// the line that any database knows)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+}
+
+/// A known location such as an office or VPN egress, identified by its
+/// networks. Logins from a site use its coordinates instead of GeoIP.
+///
+/// ```toml
+/// [sites.istanbul_office]
+/// networks = ["10.1.0.0/16", "198.51.100.0/24"]
+/// latitude = 41.01
+/// longitude = 28.97
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Site {
+    /// CIDRs, bare addresses or `[networks]` set names
+    pub networks: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+}
+
+/// Remembers where each user logs in from and reports logins that imply
+/// travelling faster than `max_speed_kmh`, or that come from a network the
+/// user has never logged in from before.
+///
+/// ```toml
+/// [[travel_rules]]
+/// name = "impossible_travel"
+/// rules = ["ssh_login_success"]
+/// user_field = "user"
+/// severity = "high"
+/// history = "state/logins.json"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TravelRule {
+    pub name: String,
+    /// Rules whose detections are logins
+    pub rules: Vec<String>,
+    /// Field holding the user name
+    pub user_field: String,
+    /// Field holding the source address (default: the address GeoIP
+    /// enrichment used, else the first IP address in the line)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_field: Option<String>,
+    /// Field holding the event time (default: a `timestamp`-like field or
+    /// the timestamp at the start of the line)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_field: Option<String>,
+    #[serde(default = "default_max_speed_kmh")]
+    pub max_speed_kmh: f64,
+    /// Ignore jumps shorter than this, which GeoIP accuracy cannot resolve
+    #[serde(default = "default_min_distance_km")]
+    pub min_distance_km: f64,
+    /// Also report a user's first login from a network not seen before
+    #[serde(default = "default_true")]
+    pub new_network: bool,
+    pub severity: String,
+    /// JSON file the learned history is kept in, relative to the rule file
+    /// (default: memory only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<PathBuf>,
+    /// File the rule was loaded from
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+/// Learns which values a field takes and reports values first seen after
+/// the learning period, separately for each key.
+///
+/// ```toml
+/// [[new_value_rules]]
+/// name = "new_admin_user_agent"
+/// condition = { field = { name = "path", contains = "/admin" } }
+/// field = "user_agent"
+/// key_fields = ["host"]
+/// severity = "warning"
+/// history = "state/admin_agents.json"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct NewValueRule {
+    pub name: String,
+    /// Only lines satisfying this condition are considered (default: every
+    /// line holding the value)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Condition>,
+    /// Field holding the value; mutually exclusive with `regex`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Regex whose `value` group, or else first group, is the value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    /// Fields whose values together form the key known values are kept
+    /// under (default: one set for everything)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_fields: Vec<String>,
+    /// How long after a key is first seen its values are learned silently
+    #[serde(default = "default_learning_period_seconds")]
+    pub learning_period_seconds: u64,
+    /// Field holding the event time (default: a `timestamp`-like field or
+    /// the timestamp at the start of the line)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_field: Option<String>,
+    pub severity: String,
+    /// JSON file the known values are kept in, relative to the rule file
+    /// (default: memory only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<PathBuf>,
+    /// File the rule was loaded from
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+fn default_learning_period_seconds() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_max_speed_kmh() -> f64 {
+    1000.0
+}
+
+fn default_min_distance_km() -> f64 {
+    100.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IocFormat {
+    Text,
+    Csv,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    #[default]
+    First,
+    All,
+}
+
+/// A condition tree evaluated against a single log line.
+///
+/// ```toml
+/// condition = { all = [
+///     { contains = "sshd" },
+///     { regex = "Failed password" },
+///     { not = { contains = "from 10." } },
+/// ] }
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// Every sub-condition must match
+    All(Vec<Condition>),
+    /// At least one sub-condition must match
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    /// Case-sensitive substring
+    Contains(String),
+    /// Case-insensitive substring
+    Icontains(String),
+    Regex(String),
+    Field(FieldCondition),
+    /// Some IP address in the line (or in `field`) lies in one of `networks`
+    InCidr(CidrCondition),
+    /// Some IP address in the line (or in `field`) lies outside all of
+    /// `networks`. Unlike `not = { in_cidr = ... }`, a line without any IP
+    /// address does not match.
+    NotInCidr(CidrCondition),
+}
+
+/// A predicate on a field extracted from the line (`key=value` pairs or the
+/// keys of a JSON object). With no operator set, the field only has to exist.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct FieldCondition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contains: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    /// The value is one of these
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub one_of: Vec<String>,
+    /// The field exists and its value is none of these
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub not_one_of: Vec<String>,
+    /// Compare `equals` / `contains` / `one_of` / `not_one_of` case-insensitively
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_case: bool,
+}
+
+/// Networks to test IP addresses against: CIDRs (`10.0.0.0/8`,
+/// `fd00::/8`), bare addresses, or names from the `[networks]` table.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct CidrCondition {
+    pub networks: Vec<String>,
+    /// Only test the address in this extracted field (default: every IP
+    /// address in the line)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+/// One `[[outputs]]` entry: a destination and the detections it receives.
+///
+/// ```toml
+/// [[outputs]]
+/// type = "json"
+/// path = "detections.jsonl"      # relative to the rule file; stdout if unset
+/// min_severity = "high"
+/// exclude_rules = ["noisy_rule"]
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct OutputConfig {
+    /// Shown in messages; a later rule layer replaces an output of the same name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_severity: Option<String>,
+    /// Only detections of these rules (default: every rule)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_rules: Vec<String>,
+    #[serde(flatten)]
+    pub sink: SinkConfig,
+}
+
+/// The destination of an output, selected by its `type` key.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// Colored lines on stdout
+    Console {
+        /// Print every matching rule per line, not only the most severe one
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        all_matches: bool,
+    },
+    /// `DetectionRecord`s as JSON Lines or a JSON array
+    Json {
+        /// Stdout when unset or `-`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<PathBuf>,
+        #[serde(default)]
+        format: JsonFormat,
+    },
+    /// One row per detection with fixed columns, for spreadsheets
+    Csv {
+        /// Stdout when unset or `-`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<PathBuf>,
+        /// Extracted fields that get their own `fields.<name>` column
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<String>,
+        /// Prefix cells that a spreadsheet would run as a formula with `'`
+        #[serde(default = "default_true")]
+        escape_formulas: bool,
+    },
+    /// The CSV columns as a Parquet file, written when the run ends; needs
+    /// the `parquet` build feature
+    Parquet {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<String>,
+    },
+    /// Batches of detections POSTed to an HTTP endpoint
+    Webhook(WebhookConfig),
+    /// A local command run once per detection
+    Exec(ExecConfig),
+    /// RFC 5424 syslog, CEF or LEEF messages to a syslog server or SIEM
+    Syslog(SyslogConfig),
+}
+
+/// How detections are laid out in the JSON output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonFormat {
+    /// One compact JSON object per line (JSON Lines)
+    #[default]
+    #[serde(alias = "jsonl")]
+    Lines,
+    /// A single JSON array, closed when the run ends
+    Array,
+}
+
+impl FromStr for JsonFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "lines" | "jsonl" => Ok(JsonFormat::Lines),
+            "array" => Ok(JsonFormat::Array),
+            _ => Err(anyhow::anyhow!(
+                "unknown JSON format \"{s}\" (expected lines or array)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for JsonFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            JsonFormat::Lines => "lines",
+            JsonFormat::Array => "array",
+        })
+    }
+}
+
+/// Where and how a syslog output sends detections.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SyslogConfig {
+    /// `host:port` of the receiver
+    pub address: String,
+    #[serde(default)]
+    pub protocol: SyslogProtocol,
+    #[serde(default)]
+    pub format: SyslogFormat,
+    /// Message separation on TCP and TLS
+    #[serde(default)]
+    pub framing: SyslogFraming,
+    #[serde(default)]
+    pub facility: Facility,
+    #[serde(default = "default_app_name")]
+    pub app_name: String,
+    /// PEM file of the CA that signed the receiver's certificate, relative
+    /// to the rule file (default: public web roots)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<PathBuf>,
+    /// Name the receiver's certificate is checked against (default: the
+    /// host of `address`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    /// Connect and write timeout of TCP and TLS connections
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+impl SyslogConfig {
+    pub fn new(address: impl Into<String>) -> Self {
+        SyslogConfig {
+            address: address.into(),
+            protocol: SyslogProtocol::default(),
+            format: SyslogFormat::default(),
+            framing: SyslogFraming::default(),
+            facility: Facility::default(),
+            app_name: default_app_name(),
+            ca_file: None,
+            server_name: None,
+            timeout_seconds: default_timeout_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogProtocol {
+    #[default]
+    Udp,
+    Tcp,
+    /// TCP with TLS (RFC 5425)
+    Tls,
+}
+
+/// What follows the RFC 5424 header.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFormat {
+    /// Structured data with the rule, file and line; the message is the
+    /// description and the matched line
+    #[default]
+    Rfc5424,
+    /// ArcSight Common Event Format
+    Cef,
+    /// IBM QRadar Log Event Extended Format 1.0
+    Leef,
+}
+
+/// How messages are separated on TCP and TLS connections (RFC 6587).
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogFraming {
+    /// `<length> <message>`
+    #[default]
+    OctetCounting,
+    /// One message per line
+    Newline,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Facility {
+    Kern = 0,
+    #[default]
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    Authpriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+fn default_app_name() -> String {
+    "rusthound".to_string()
+}
+
+/// The command an exec output runs for each detection.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ExecConfig {
+    /// Program and arguments, each with `{{rule_id}}`, `{{fields.ip}}`, ...
+    /// placeholders; run directly, never through a shell
+    pub command: Vec<String>,
+    /// Write the detection record as JSON to the command's stdin
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stdin: bool,
+    /// Commands running at the same time; further detections wait
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent: usize,
+    /// A command still running after this long is killed
+    #[serde(default = "default_exec_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+impl ExecConfig {
+    pub fn new(command: Vec<String>) -> Self {
+        ExecConfig {
+            command,
+            stdin: false,
+            max_concurrent: default_max_concurrent(),
+            timeout_seconds: default_exec_timeout_seconds(),
+        }
+    }
+}
+
+fn default_max_concurrent() -> usize {
+    4
+}
+
+fn default_exec_timeout_seconds() -> u64 {
+    30
+}
+
+/// Message layout of a webhook that posts to a chat service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatFormat {
+    /// Slack incoming webhook: Block Kit blocks in colored attachments
+    Slack,
+    /// Teams workflow webhook: an Adaptive Card
+    Teams,
+    /// Teams Office 365 connector: a legacy MessageCard
+    TeamsMessageCard,
+    /// Discord webhook: one embed per detection
+    Discord,
+}
+
+/// Where and how a webhook output delivers detections.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Extra request headers, e.g. an `Authorization` token
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Post chat messages instead of detection records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ChatFormat>,
+    /// Body of one detection with `{{rule_id}}`, `{{fields.user}}`, ...
+    /// placeholders; each detection is sent as its own request (default: a
+    /// JSON array of detection records)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// How long detections are collected before a batch is sent; 0 sends
+    /// them as they arrive
+    #[serde(default = "default_batch_seconds")]
+    pub batch_seconds: u64,
+    #[serde(default = "default_max_batch")]
+    pub max_batch: usize,
+    /// Retries of a failed request, waiting `retry_backoff_ms` and doubling
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Requests per minute, retries included (default: unlimited)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_per_minute: Option<u32>,
+    /// JSON Lines file, relative to the rule file, that keeps batches the
+    /// endpoint did not take; they are sent again once it is back (default:
+    /// such batches are dropped)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spool: Option<PathBuf>,
+    #[serde(default = "default_max_spool_bytes")]
+    pub max_spool_bytes: u64,
+}
+
+impl WebhookConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        WebhookConfig {
+            url: url.into(),
+            headers: BTreeMap::new(),
+            format: None,
+            template: None,
+            content_type: default_content_type(),
+            batch_seconds: default_batch_seconds(),
+            max_batch: default_max_batch(),
+            max_retries: default_max_retries(),
+            retry_backoff_ms: default_retry_backoff_ms(),
+            timeout_seconds: default_timeout_seconds(),
+            rate_limit_per_minute: None,
+            spool: None,
+            max_spool_bytes: default_max_spool_bytes(),
+        }
+    }
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+fn default_batch_seconds() -> u64 {
+    5
+}
+
+fn default_max_batch() -> usize {
+    100
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+
+fn default_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_max_spool_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+impl OutputConfig {
+    pub fn console() -> Self {
+        OutputConfig::new(SinkConfig::Console { all_matches: false })
+    }
+
+    pub fn new(sink: SinkConfig) -> Self {
+        OutputConfig {
+            name: None,
+            min_severity: None,
+            rules: Vec::new(),
+            exclude_rules: Vec::new(),
+            sink,
+        }
+    }
+
+    /// `name`, or the output type when unnamed.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(match &self.sink {
+            SinkConfig::Console { .. } => "console",
+            SinkConfig::Json { .. } => "json",
+            SinkConfig::Csv { .. } => "csv",
+            SinkConfig::Parquet { .. } => "parquet",
+            SinkConfig::Webhook(_) => "webhook",
+            SinkConfig::Exec(_) => "exec",
+            SinkConfig::Syslog(_) => "syslog",
+        })
+    }
+
+    /// Make relative file paths relative to `base_dir`.
+    fn resolve_paths(&mut self, base_dir: &Path) {
+        match &mut self.sink {
+            SinkConfig::Json {
+                path: Some(path), ..
+            } if path.as_os_str() != "-" => *path = base_dir.join(&*path),
+            SinkConfig::Csv {
+                path: Some(path), ..
+            } if path.as_os_str() != "-" => *path = base_dir.join(&*path),
+            SinkConfig::Parquet { path, .. } => *path = base_dir.join(&*path),
+            SinkConfig::Webhook(WebhookConfig {
+                spool: Some(path), ..
+            }) => *path = base_dir.join(&*path),
+            SinkConfig::Syslog(SyslogConfig {
+                ca_file: Some(path),
+                ..
+            }) => *path = base_dir.join(&*path),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OutputsFile {
+    #[serde(default)]
+    outputs: Vec<OutputConfig>,
+}
+
+/// Load the `[[outputs]]` of a file that holds only output configuration.
+pub fn load_outputs_file(path: &Path) -> anyhow::Result<Vec<OutputConfig>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read {}: {e}", path.display()))?;
+    let mut file: OutputsFile = toml::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("Invalid output file {}: {e}", path.display()))?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    for output in &mut file.outputs {
+        output.resolve_paths(base_dir);
+    }
+    Ok(file.outputs)
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -33,7 +936,7 @@ pub struct FrequencyRules {
     pub time_window_seconds: u32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct CorrelatedRule {
     pub name: String,
     pub severity: String,
@@ -41,17 +944,324 @@ pub struct CorrelatedRule {
     pub time_window_seconds: u64,
     pub trigger_on_rule: TriggerRule,
     pub followed_by: String,
+    /// Only correlate events that share this key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub join: Option<JoinKey>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<CorrelationTest>,
+    /// File the rule was loaded from
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// The value two events of a correlated rule must share to count together,
+/// e.g. the source address of failed and successful logins.
+///
+/// ```toml
+/// [correlated_rules.join]
+/// field = "src"            # default: the first IP address in the line
+/// not_in_cidr = ["internal"]
+/// ipv4_prefix = 24
+/// ```
+///
+/// A plain `field` joins on its value verbatim. Without `field`, or with
+/// any of the CIDR options set, the key is the first IP address that passes
+/// `in_cidr` / `not_in_cidr`, optionally widened to its network.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct JoinKey {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Only addresses in one of these networks can be the key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub in_cidr: Vec<String>,
+    /// Only addresses outside all of these networks can be the key
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub not_in_cidr: Vec<String>,
+    /// Join IPv4 addresses on their enclosing network of this length
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4_prefix: Option<u8>,
+    /// Join IPv6 addresses on their enclosing network of this length
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6_prefix: Option<u8>,
+}
+
+impl JoinKey {
+    /// Whether the key is an IP address rather than a raw field value.
+    pub fn is_ip(&self) -> bool {
+        self.field.is_none()
+            || !self.in_cidr.is_empty()
+            || !self.not_in_cidr.is_empty()
+            || self.ipv4_prefix.is_some()
+            || self.ipv6_prefix.is_some()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct TriggerRule {
     pub name: String,
     pub count: usize,
 }
 
-pub fn load_rules_from_file(path: &std::path::Path) -> anyhow::Result<Rules> {
+/// A log snippet and the number of times the enclosing correlated rule
+/// is expected to fire while processing it.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CorrelationTest {
+    pub log: String,
+    pub expect_detections: usize,
+}
+
+pub fn load_rules_from_file(path: &Path) -> anyhow::Result<Rules> {
     let content = std::fs::read_to_string(path)?;
-    let rules: Rules = toml::from_str(&content)?;
+    Ok(parse_rules(path, &content)?)
+}
+
+/// Parse the content of the rule file at `path`, making its file paths
+/// relative to that file and recording it as every rule's source.
+pub fn parse_rules(path: &Path, content: &str) -> Result<Rules, toml::de::Error> {
+    let mut rules: Rules = toml::from_str(content)?;
+    for rule in &mut rules.regex_rules {
+        rule.source = Some(path.to_path_buf());
+    }
+    for rule in &mut rules.correlated_rules {
+        rule.source = Some(path.to_path_buf());
+    }
+    for rule in &mut rules.suppress_rules {
+        rule.source = Some(path.to_path_buf());
+    }
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    for list in &mut rules.ioc_lists {
+        list.path = base_dir.join(&list.path);
+        list.source = Some(path.to_path_buf());
+    }
+    for rule in &mut rules.travel_rules {
+        if let Some(history) = &mut rule.history {
+            *history = base_dir.join(&*history);
+        }
+        rule.source = Some(path.to_path_buf());
+    }
+    for rule in &mut rules.new_value_rules {
+        if let Some(history) = &mut rule.history {
+            *history = base_dir.join(&*history);
+        }
+        rule.source = Some(path.to_path_buf());
+    }
+    if let Some(geoip) = &mut rules.geoip {
+        for database in &mut geoip.databases {
+            *database = base_dir.join(&*database);
+        }
+    }
+    for output in &mut rules.outputs {
+        output.resolve_paths(base_dir);
+    }
+    Ok(rules)
+}
+
+impl Rules {
+    /// Layer `other` on top of `self`.
+    ///
+    /// String patterns are appended without duplicates; list options from
+    /// `other` replace the earlier ones whenever `other` sets the table,
+    /// so an empty `[rules.error_options]` resets them to the defaults.
+    /// Regex, correlated and suppress rules, IOC lists, travel and new
+    /// value rules with a name that already exists replace the earlier
+    /// definition in place, keeping its matching priority; new names are
+    /// appended. Network sets and sites are replaced by name. `geoip`,
+    /// `frequency_rules` and `match_mode` from `other` win when present.
+    /// Outputs are appended, except that a named output replaces the
+    /// earlier one of that name.
+    pub fn merge(&mut self, other: Rules) {
+        merge_patterns(
+            &mut self.patterns.error_patterns,
+            other.patterns.error_patterns,
+        );
+        merge_patterns(
+            &mut self.patterns.warning_patterns,
+            other.patterns.warning_patterns,
+        );
+        if other.patterns.error_options.is_some() {
+            self.patterns.error_options = other.patterns.error_options;
+        }
+        if other.patterns.warning_options.is_some() {
+            self.patterns.warning_options = other.patterns.warning_options;
+        }
+
+        for rule in other.regex_rules {
+            match self.regex_rules.iter_mut().find(|r| r.name == rule.name) {
+                Some(existing) => *existing = rule,
+                None => self.regex_rules.push(rule),
+            }
+        }
+
+        for rule in other.correlated_rules {
+            match self
+                .correlated_rules
+                .iter_mut()
+                .find(|r| r.name == rule.name)
+            {
+                Some(existing) => *existing = rule,
+                None => self.correlated_rules.push(rule),
+            }
+        }
+
+        for rule in other.suppress_rules {
+            match self.suppress_rules.iter_mut().find(|r| r.name == rule.name) {
+                Some(existing) => *existing = rule,
+                None => self.suppress_rules.push(rule),
+            }
+        }
+
+        for list in other.ioc_lists {
+            match self.ioc_lists.iter_mut().find(|l| l.name == list.name) {
+                Some(existing) => *existing = list,
+                None => self.ioc_lists.push(list),
+            }
+        }
+
+        for rule in other.travel_rules {
+            match self.travel_rules.iter_mut().find(|r| r.name == rule.name) {
+                Some(existing) => *existing = rule,
+                None => self.travel_rules.push(rule),
+            }
+        }
+
+        for rule in other.new_value_rules {
+            match self
+                .new_value_rules
+                .iter_mut()
+                .find(|r| r.name == rule.name)
+            {
+                Some(existing) => *existing = rule,
+                None => self.new_value_rules.push(rule),
+            }
+        }
+
+        for output in other.outputs {
+            let existing = output.name.as_ref().and_then(|name| {
+                self.outputs
+                    .iter_mut()
+                    .find(|o| o.name.as_ref() == Some(name))
+            });
+            match existing {
+                Some(existing) => *existing = output,
+                None => self.outputs.push(output),
+            }
+        }
+
+        self.networks.extend(other.networks);
+        self.sites.extend(other.sites);
+
+        if other.geoip.is_some() {
+            self.geoip = other.geoip;
+        }
+        if other.frequency_rules.is_some() {
+            self.frequency_rules = other.frequency_rules;
+        }
+        if other.match_mode.is_some() {
+            self.match_mode = other.match_mode;
+        }
+    }
+}
+
+fn merge_patterns(existing: &mut Vec<StringPattern>, incoming: Vec<StringPattern>) {
+    for pattern in incoming {
+        if !existing.contains(&pattern) {
+            existing.push(pattern);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IncludeList {
+    #[serde(default)]
+    include: Vec<String>,
+}
+
+/// Every file that contributes to the rule set rooted at `path`, in merge
+/// order (lowest precedence first).
+///
+/// Each file's `include` entries come before the file itself, and `*.toml`
+/// files in a `rules.d` directory next to `path` come last, in name order.
+pub fn rule_layers(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut collector = LayerCollector::default();
+    collector.collect(path)?;
+
+    let drop_in_dir = path.parent().unwrap_or(Path::new(".")).join("rules.d");
+    if drop_in_dir.is_dir() {
+        let pattern = drop_in_dir.join("*.toml");
+        for drop_in in expand_include(&pattern.to_string_lossy())? {
+            collector.collect(&drop_in)?;
+        }
+    }
+
+    Ok(collector.layers)
+}
+
+#[derive(Default)]
+struct LayerCollector {
+    layers: Vec<PathBuf>,
+    visited: HashSet<PathBuf>,
+    stack: Vec<PathBuf>,
+}
+
+impl LayerCollector {
+    fn collect(&mut self, path: &Path) -> anyhow::Result<()> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Cannot read rule file {}: {e}", path.display()))?;
+        if self.stack.contains(&canonical) {
+            return Err(anyhow::anyhow!(
+                "Include cycle detected at {}",
+                path.display()
+            ));
+        }
+        if !self.visited.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        let content = std::fs::read_to_string(path)?;
+        let includes: IncludeList = toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid rule file {}: {e}", path.display()))?;
+
+        self.stack.push(canonical);
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        for include in &includes.include {
+            let pattern = base_dir.join(include);
+            let matches = expand_include(&pattern.to_string_lossy())?;
+            let is_glob = glob::Pattern::escape(include) != *include;
+            if matches.is_empty() && !is_glob {
+                return Err(anyhow::anyhow!(
+                    "Included rule file not found: {} (from {})",
+                    pattern.display(),
+                    path.display()
+                ));
+            }
+            for included in matches {
+                self.collect(&included)?;
+            }
+        }
+        self.stack.pop();
+
+        self.layers.push(path.to_path_buf());
+        Ok(())
+    }
+}
+
+fn expand_include(pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// Load the rule file at `path` together with its includes and `rules.d`
+/// drop-ins, merged according to [`rule_layers`] order.
+pub fn load_layered_rules(path: &Path) -> anyhow::Result<Rules> {
+    let mut rules = Rules::default();
+    for layer in rule_layers(path)? {
+        let layer_rules = load_rules_from_file(&layer)
+            .map_err(|e| anyhow::anyhow!("Failed to load {}: {e}", layer.display()))?;
+        rules.merge(layer_rules);
+    }
+    rules.include.clear();
     Ok(rules)
 }
 
@@ -59,6 +1269,12 @@ pub fn load_rules_from_file(path: &std::path::Path) -> anyhow::Result<Rules> {
 mod tests {
     use super::*;
 
+    fn regex_rule(name: &str, pattern: &str) -> String {
+        format!(
+            "[[regex_rules]]\nname = \"{name}\"\npattern = \"{pattern}\"\nseverity = \"high\"\n"
+        )
+    }
+
     #[test]
     fn deserializes_rules_toml() {
         let rules = load_rules_from_file(std::path::Path::new("rules.toml")).unwrap();
@@ -75,5 +1291,169 @@ mod tests {
             rules.correlated_rules[0].name,
             "Potential Brute-Force Attack"
         );
+        assert_eq!(rules.correlated_rules[0].tests.len(), 2);
+    }
+
+    #[test]
+    fn includes_merge_with_override_by_name() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        std::fs::create_dir_all(dir.join("packs")).unwrap();
+        std::fs::write(
+            dir.join("base.toml"),
+            format!(
+                "[rules]\nerror_patterns = [\"ERROR\"]\nerror_options = {{ whole_word = true }}\nwarning_options = {{ ignore_case = true }}\n{}{}",
+                regex_rule("a", "base-a"),
+                regex_rule("b", "base-b")
+            ),
+        )
+        .unwrap();
+        std::fs::write(dir.join("packs/extra.toml"), regex_rule("c", "extra-c")).unwrap();
+        std::fs::write(
+            dir.join("rules.toml"),
+            format!(
+                "include = [\"base.toml\", \"packs/*.toml\"]\n[rules]\nerror_patterns = [\"ERROR\", \"FATAL\"]\nerror_options = {{}}\n{}",
+                regex_rule("a", "host-a")
+            ),
+        )
+        .unwrap();
+
+        let rules = load_layered_rules(&dir.join("rules.toml")).unwrap();
+        let error_names: Vec<_> = rules
+            .patterns
+            .error_patterns
+            .iter()
+            .map(StringPattern::name)
+            .collect();
+        assert_eq!(error_names, vec!["ERROR", "FATAL"]);
+        // An empty table resets the list options; an absent one keeps them
+        assert_eq!(
+            rules.patterns.error_options,
+            Some(PatternOptions::default())
+        );
+        assert!(rules.patterns.warning_options.unwrap().ignore_case);
+        let names: Vec<_> = rules
+            .regex_rules
+            .iter()
+            .map(|r| (r.name.as_str(), r.pattern.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("a", "host-a"), ("b", "base-b"), ("c", "extra-c")]
+        );
+        assert_eq!(rules.regex_rules[0].source, Some(dir.join("rules.toml")));
+        assert_eq!(rules.regex_rules[1].source, Some(dir.join("base.toml")));
+        assert_eq!(
+            rules.regex_rules[2].source,
+            Some(dir.join("packs").join("extra.toml"))
+        );
+    }
+
+    #[test]
+    fn drop_in_directory_is_applied_last() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        std::fs::create_dir_all(dir.join("rules.d")).unwrap();
+        std::fs::write(dir.join("rules.toml"), regex_rule("a", "main-a")).unwrap();
+        std::fs::write(dir.join("rules.d/10-host.toml"), regex_rule("a", "host-a")).unwrap();
+        std::fs::write(dir.join("rules.d/notes.txt"), "ignored").unwrap();
+
+        let layers = rule_layers(&dir.join("rules.toml")).unwrap();
+        assert_eq!(
+            layers,
+            vec![
+                dir.join("rules.toml"),
+                dir.join("rules.d").join("10-host.toml")
+            ]
+        );
+        let rules = load_layered_rules(&dir.join("rules.toml")).unwrap();
+        assert_eq!(rules.regex_rules.len(), 1);
+        assert_eq!(rules.regex_rules[0].pattern, "host-a");
+    }
+
+    #[test]
+    fn outputs_resolve_paths_and_replace_by_name() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        std::fs::create_dir_all(dir.join("rules.d")).unwrap();
+        std::fs::write(
+            dir.join("rules.toml"),
+            "[[outputs]]\ntype = \"console\"\n\n[[outputs]]\nname = \"alerts\"\ntype = \"json\"\npath = \"alerts.jsonl\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("rules.d/10-host.toml"),
+            "[[outputs]]\nname = \"alerts\"\ntype = \"json\"\npath = \"-\"\nformat = \"array\"\nmin_severity = \"high\"\n",
+        )
+        .unwrap();
+
+        let rules = load_layered_rules(&dir.join("rules.toml")).unwrap();
+        assert_eq!(rules.outputs.len(), 2);
+        assert_eq!(rules.outputs[0], OutputConfig::console());
+        assert_eq!(rules.outputs[1].min_severity.as_deref(), Some("high"));
+        assert_eq!(
+            rules.outputs[1].sink,
+            SinkConfig::Json {
+                path: Some(PathBuf::from("-")),
+                format: JsonFormat::Array,
+            }
+        );
+
+        std::fs::write(
+            dir.join("outputs.toml"),
+            "[[outputs]]\ntype = \"json\"\npath = \"out/detections.jsonl\"\n\n[[outputs]]\ntype = \"webhook\"\nurl = \"https://hooks.example.com/x\"\nbatch_seconds = 0\nspool = \"spool/hook.jsonl\"\nheaders = { Authorization = \"Bearer t\" }\n\n[[outputs]]\ntype = \"syslog\"\naddress = \"siem:6514\"\nprotocol = \"tls\"\nformat = \"cef\"\nca_file = \"certs/ca.pem\"\n\n[[outputs]]\ntype = \"csv\"\npath = \"out/detections.csv\"\nfields = [\"geo.country\"]\n\n[[outputs]]\ntype = \"parquet\"\npath = \"out/detections.parquet\"\n",
+        )
+        .unwrap();
+        let outputs = load_outputs_file(&dir.join("outputs.toml")).unwrap();
+        assert_eq!(
+            outputs[0].sink,
+            SinkConfig::Json {
+                path: Some(dir.join("out/detections.jsonl")),
+                format: JsonFormat::Lines,
+            }
+        );
+        let mut webhook = WebhookConfig::new("https://hooks.example.com/x");
+        webhook.batch_seconds = 0;
+        webhook.spool = Some(dir.join("spool/hook.jsonl"));
+        webhook
+            .headers
+            .insert("Authorization".to_string(), "Bearer t".to_string());
+        assert_eq!(outputs[1].sink, SinkConfig::Webhook(webhook));
+        assert_eq!(outputs[1].label(), "webhook");
+        let SinkConfig::Syslog(syslog) = &outputs[2].sink else {
+            panic!("expected a syslog output");
+        };
+        assert_eq!(syslog.protocol, SyslogProtocol::Tls);
+        assert_eq!(syslog.format, SyslogFormat::Cef);
+        assert_eq!(syslog.ca_file, Some(dir.join("certs/ca.pem")));
+        assert_eq!(
+            outputs[3].sink,
+            SinkConfig::Csv {
+                path: Some(dir.join("out/detections.csv")),
+                fields: vec!["geo.country".to_string()],
+                escape_formulas: true,
+            }
+        );
+        assert_eq!(
+            outputs[4].sink,
+            SinkConfig::Parquet {
+                path: dir.join("out/detections.parquet"),
+                fields: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_include_cycles_and_missing_files() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        std::fs::write(dir.join("a.toml"), "include = [\"b.toml\"]\n").unwrap();
+        std::fs::write(dir.join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+        std::fs::write(dir.join("c.toml"), "include = [\"missing.toml\"]\n").unwrap();
+
+        let cycle = rule_layers(&dir.join("a.toml")).unwrap_err();
+        assert!(cycle.to_string().contains("Include cycle"));
+        let missing = rule_layers(&dir.join("c.toml")).unwrap_err();
+        assert!(missing.to_string().contains("not found"));
     }
 }
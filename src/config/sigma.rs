@@ -0,0 +1,714 @@
+use crate::config::rules::{Condition, FieldCondition, FrequencyRules, RegexRule};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Result of converting one or more Sigma documents.
+///
+/// `warnings` describe approximations made for rules that were converted;
+/// `unsupported` lists constructs that caused a rule to be skipped.
+#[derive(Debug, Default)]
+pub struct SigmaConversion {
+    pub regex_rules: Vec<RegexRule>,
+    pub frequency_rules: Option<FrequencyRules>,
+    pub warnings: Vec<String>,
+    pub unsupported: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ConvertedRules<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_rules: &'a Option<FrequencyRules>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    regex_rules: &'a [RegexRule],
+}
+
+impl SigmaConversion {
+    /// Render the converted rules as a `rules.toml` fragment, keeping each
+    /// condition tree on its rule as an inline table.
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        let mut document = toml_edit::ser::to_document(&ConvertedRules {
+            frequency_rules: &self.frequency_rules,
+            regex_rules: &self.regex_rules,
+        })?;
+        for (_, item) in document.iter_mut() {
+            let inline = std::mem::take(item);
+            *item = match inline.into_array_of_tables() {
+                Ok(tables) => toml_edit::Item::ArrayOfTables(tables),
+                Err(inline) => inline
+                    .into_table()
+                    .map_or_else(|inline| inline, toml_edit::Item::Table),
+            };
+        }
+        Ok(document.to_string())
+    }
+
+    fn set_frequency_rules(&mut self, frequency: FrequencyRules, title: &str) {
+        match &self.frequency_rules {
+            None => self.frequency_rules = Some(frequency),
+            Some(existing)
+                if existing.max_same_errors_per_minute == frequency.max_same_errors_per_minute
+                    && existing.time_window_seconds == frequency.time_window_seconds => {}
+            Some(existing) => self.warnings.push(format!(
+                "{title}: count aggregation ({} in {}s) conflicts with an earlier one ({} in {}s); keeping the earlier one",
+                frequency.max_same_errors_per_minute,
+                frequency.time_window_seconds,
+                existing.max_same_errors_per_minute,
+                existing.time_window_seconds
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SigmaRule {
+    title: String,
+    #[serde(default)]
+    level: Option<String>,
+    #[serde(default)]
+    action: Option<String>,
+    detection: Mapping,
+}
+
+/// How a single value is compared against the log line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatchKind {
+    Equals,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Regex,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Matcher {
+    field: Option<String>,
+    kind: MatchKind,
+    value: String,
+    case_sensitive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Match(Matcher),
+}
+
+impl SigmaConversion {
+    /// Convert every Sigma rule in a YAML file (multiple documents allowed)
+    /// and add the results to this conversion.
+    pub fn add_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let content = std::fs::read_to_string(path)?;
+        self.add_str(&content)
+    }
+
+    pub fn add_str(&mut self, content: &str) -> anyhow::Result<()> {
+        for document in serde_yaml::Deserializer::from_str(content) {
+            let rule = SigmaRule::deserialize(document)?;
+            convert_rule(&rule, self);
+        }
+        Ok(())
+    }
+}
+
+fn convert_rule(rule: &SigmaRule, conversion: &mut SigmaConversion) {
+    let title = rule.title.as_str();
+
+    if let Some(action) = &rule.action {
+        conversion
+            .unsupported
+            .push(format!("{title}: rule collections (action: {action})"));
+        return;
+    }
+
+    let converted = convert_detection(&rule.detection).and_then(|(expr, aggregation)| {
+        let frequency = aggregation
+            .map(|aggregation| parse_aggregation(&aggregation, &rule.detection))
+            .transpose()?;
+        Ok((lower_to_condition(&expr), frequency))
+    });
+
+    match converted {
+        Ok((condition, frequency)) => {
+            if let Some(frequency) = frequency {
+                conversion.warnings.push(format!(
+                    "{title}: count aggregation mapped to [frequency_rules], which applies to every rule"
+                ));
+                conversion.set_frequency_rules(frequency, title);
+            }
+            conversion.regex_rules.push(RegexRule {
+                name: title.to_string(),
+                condition: Some(condition),
+                severity: map_level(rule.level.as_deref()).to_string(),
+                ..Default::default()
+            });
+        }
+        Err(problem) => conversion.unsupported.push(format!("{title}: {problem}")),
+    }
+}
+
+fn map_level(level: Option<&str>) -> &'static str {
+    match level.map(str::to_lowercase).as_deref() {
+        Some("critical") => "critical",
+        Some("high") => "high",
+        Some("medium") => "error",
+        Some("low") => "warning",
+        _ => "info",
+    }
+}
+
+/// Parse the `detection` block into a single expression plus the optional
+/// aggregation part of the condition (the text after `|`).
+fn convert_detection(detection: &Mapping) -> Result<(Expr, Option<String>), String> {
+    let mut selections = BTreeMap::new();
+    let mut conditions = Vec::new();
+
+    for (key, value) in detection {
+        let key = key.as_str().ok_or("non-string detection key")?;
+        match key {
+            "condition" => match value {
+                Value::String(condition) => conditions.push(condition.clone()),
+                Value::Sequence(items) => {
+                    for item in items {
+                        let condition = item.as_str().ok_or("non-string condition")?;
+                        conditions.push(condition.to_string());
+                    }
+                }
+                _ => return Err("condition must be a string or list".to_string()),
+            },
+            "timeframe" => {}
+            name => {
+                selections.insert(name.to_string(), convert_selection(value)?);
+            }
+        }
+    }
+
+    if conditions.is_empty() {
+        return Err("missing condition".to_string());
+    }
+
+    let mut exprs = Vec::new();
+    let mut aggregation = None;
+    for condition in &conditions {
+        let (expression, aggregate) = match condition.split_once('|') {
+            Some((expression, aggregate)) => (expression, Some(aggregate.trim().to_string())),
+            None => (condition.as_str(), None),
+        };
+        if aggregate.is_some() && conditions.len() > 1 {
+            return Err("aggregations in a list of conditions".to_string());
+        }
+        aggregation = aggregate;
+        exprs.push(ConditionParser::new(expression, &selections).parse()?);
+    }
+
+    let expr = if exprs.len() == 1 {
+        exprs.remove(0)
+    } else {
+        Expr::Or(exprs)
+    };
+    Ok((expr, aggregation))
+}
+
+fn convert_selection(value: &Value) -> Result<Expr, String> {
+    match value {
+        Value::Mapping(fields) => {
+            let mut terms = Vec::new();
+            for (key, value) in fields {
+                let key = key.as_str().ok_or("non-string field name")?;
+                terms.push(convert_field(key, value)?);
+            }
+            Ok(Expr::And(terms))
+        }
+        Value::Sequence(items) if items.iter().all(Value::is_mapping) => Ok(Expr::Or(
+            items
+                .iter()
+                .map(convert_selection)
+                .collect::<Result<_, _>>()?,
+        )),
+        Value::Sequence(items) => Ok(Expr::Or(
+            items
+                .iter()
+                .map(|item| keyword_matcher(item).map(Expr::Match))
+                .collect::<Result<_, _>>()?,
+        )),
+        keyword => keyword_matcher(keyword).map(Expr::Match),
+    }
+}
+
+fn keyword_matcher(value: &Value) -> Result<Matcher, String> {
+    Ok(Matcher {
+        field: None,
+        kind: MatchKind::Contains,
+        value: scalar_to_string(value)?,
+        case_sensitive: false,
+    })
+}
+
+fn scalar_to_string(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Null => Err("null field values".to_string()),
+        _ => Err("nested values in a selection".to_string()),
+    }
+}
+
+fn convert_field(key: &str, value: &Value) -> Result<Expr, String> {
+    let mut parts = key.split('|');
+    let field = parts.next().unwrap_or_default();
+    let mut kind = MatchKind::Equals;
+    let mut match_all = false;
+    let mut case_sensitive = false;
+    for modifier in parts {
+        match modifier {
+            "contains" => kind = MatchKind::Contains,
+            "startswith" => kind = MatchKind::StartsWith,
+            "endswith" => kind = MatchKind::EndsWith,
+            "re" => kind = MatchKind::Regex,
+            "all" => match_all = true,
+            "cased" => case_sensitive = true,
+            other => return Err(format!("field modifier \"{other}\"")),
+        }
+    }
+
+    let values = match value {
+        Value::Sequence(items) => items
+            .iter()
+            .map(scalar_to_string)
+            .collect::<Result<_, _>>()?,
+        scalar => vec![scalar_to_string(scalar)?],
+    };
+    let field = (!field.is_empty()).then(|| field.to_string());
+    let matchers: Vec<Expr> = values
+        .into_iter()
+        .map(|value| {
+            Expr::Match(Matcher {
+                field: field.clone(),
+                kind,
+                value,
+                case_sensitive: case_sensitive || kind == MatchKind::Regex,
+            })
+        })
+        .collect();
+
+    Ok(if match_all {
+        Expr::And(matchers)
+    } else {
+        Expr::Or(matchers)
+    })
+}
+
+/// Recursive-descent parser for the boolean part of a Sigma `condition`.
+struct ConditionParser<'a> {
+    tokens: Vec<String>,
+    position: usize,
+    selections: &'a BTreeMap<String, Expr>,
+}
+
+impl<'a> ConditionParser<'a> {
+    fn new(condition: &str, selections: &'a BTreeMap<String, Expr>) -> Self {
+        let tokens = condition
+            .replace('(', " ( ")
+            .replace(')', " ) ")
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        ConditionParser {
+            tokens,
+            position: 0,
+            selections,
+        }
+    }
+
+    fn parse(mut self) -> Result<Expr, String> {
+        let expr = self.parse_or()?;
+        match self.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected \"{token}\" in condition")),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut terms = vec![self.parse_and()?];
+        while self.peek() == Some("or") {
+            self.next();
+            terms.push(self.parse_and()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            Expr::Or(terms)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut terms = vec![self.parse_not()?];
+        while self.peek() == Some("and") {
+            self.next();
+            terms.push(self.parse_not()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            Expr::And(terms)
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some("not") {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let token = self.next().ok_or("unexpected end of condition")?;
+        match token.as_str() {
+            "(" => {
+                let expr = self.parse_or()?;
+                match self.next().as_deref() {
+                    Some(")") => Ok(expr),
+                    _ => Err("unbalanced parentheses in condition".to_string()),
+                }
+            }
+            "1" | "any" | "all" if self.peek() == Some("of") => {
+                self.next();
+                let target = self.next().ok_or("missing selection after \"of\"")?;
+                let matched = self.selections_matching(&target)?;
+                Ok(if token == "all" {
+                    Expr::And(matched)
+                } else {
+                    Expr::Or(matched)
+                })
+            }
+            name => self
+                .selections
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("unknown selection \"{name}\" in condition")),
+        }
+    }
+
+    fn selections_matching(&self, target: &str) -> Result<Vec<Expr>, String> {
+        let matched: Vec<Expr> = self
+            .selections
+            .iter()
+            .filter(|(name, _)| {
+                target == "them"
+                    || glob::Pattern::new(target).is_ok_and(|pattern| pattern.matches(name))
+            })
+            .map(|(_, expr)| expr.clone())
+            .collect();
+        if matched.is_empty() {
+            Err(format!("no selection matches \"{target}\""))
+        } else {
+            Ok(matched)
+        }
+    }
+}
+
+/// Map `count() > N` with the rule's `timeframe` onto frequency rules.
+fn parse_aggregation(aggregation: &str, detection: &Mapping) -> Result<FrequencyRules, String> {
+    let unsupported = || format!("aggregation \"{aggregation}\"");
+    let rest = aggregation
+        .strip_prefix("count()")
+        .ok_or_else(unsupported)?
+        .trim();
+    let (threshold, inclusive) = if let Some(value) = rest.strip_prefix(">=") {
+        (value, true)
+    } else if let Some(value) = rest.strip_prefix('>') {
+        (value, false)
+    } else {
+        return Err(unsupported());
+    };
+    let threshold: u32 = threshold.trim().parse().map_err(|_| unsupported())?;
+    let max_same_errors_per_minute = if inclusive {
+        threshold.saturating_sub(1)
+    } else {
+        threshold
+    };
+
+    let timeframe = detection
+        .get("timeframe")
+        .and_then(Value::as_str)
+        .ok_or("count aggregation without a timeframe")?;
+    Ok(FrequencyRules {
+        max_same_errors_per_minute,
+        time_window_seconds: parse_timeframe(timeframe)
+            .ok_or_else(|| format!("timeframe \"{timeframe}\""))?,
+    })
+}
+
+fn parse_timeframe(timeframe: &str) -> Option<u32> {
+    let split = timeframe.len().checked_sub(1)?;
+    let (amount, unit) = timeframe.split_at(split);
+    let amount: u32 = amount.parse().ok()?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    amount.checked_mul(multiplier)
+}
+
+/// Lower an expression to a RustHound condition tree.
+///
+/// Plain keyword and field values become substring / equality checks;
+/// values with wildcards and `startswith` / `endswith` modifiers become
+/// anchored regexes. Sigma compares case-insensitively unless `cased` is set.
+fn lower_to_condition(expr: &Expr) -> Condition {
+    match expr {
+        Expr::And(terms) | Expr::Or(terms) if terms.len() == 1 => lower_to_condition(&terms[0]),
+        Expr::And(terms) => Condition::All(terms.iter().map(lower_to_condition).collect()),
+        Expr::Or(terms) => Condition::Any(terms.iter().map(lower_to_condition).collect()),
+        Expr::Not(term) => Condition::Not(Box::new(lower_to_condition(term))),
+        Expr::Match(matcher) => lower_matcher(matcher),
+    }
+}
+
+fn lower_matcher(matcher: &Matcher) -> Condition {
+    let plain = matcher.kind != MatchKind::Regex && !has_wildcards(&matcher.value);
+    let ignore_case = !matcher.case_sensitive;
+
+    match (&matcher.field, matcher.kind) {
+        (None, MatchKind::Contains) if plain => {
+            let value = unescape(&matcher.value);
+            if ignore_case {
+                Condition::Icontains(value)
+            } else {
+                Condition::Contains(value)
+            }
+        }
+        (None, _) => Condition::Regex(value_regex(matcher)),
+        (Some(name), MatchKind::Equals) if plain => Condition::Field(FieldCondition {
+            name: name.clone(),
+            equals: Some(unescape(&matcher.value)),
+            ignore_case,
+            ..Default::default()
+        }),
+        (Some(name), MatchKind::Contains) if plain => Condition::Field(FieldCondition {
+            name: name.clone(),
+            contains: Some(unescape(&matcher.value)),
+            ignore_case,
+            ..Default::default()
+        }),
+        (Some(name), _) => Condition::Field(FieldCondition {
+            name: name.clone(),
+            regex: Some(value_regex(matcher)),
+            ..Default::default()
+        }),
+    }
+}
+
+fn value_regex(matcher: &Matcher) -> String {
+    let body = match matcher.kind {
+        MatchKind::Regex => return matcher.value.clone(),
+        _ => wildcard_to_regex(&matcher.value),
+    };
+    let anchored = match matcher.kind {
+        MatchKind::Equals => format!("^{body}$"),
+        MatchKind::StartsWith => format!("^{body}"),
+        MatchKind::EndsWith => format!("{body}$"),
+        MatchKind::Contains | MatchKind::Regex => body,
+    };
+    if matcher.case_sensitive {
+        anchored
+    } else {
+        format!("(?i){anchored}")
+    }
+}
+
+fn has_wildcards(value: &str) -> bool {
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '?' => return true,
+            '\\' => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Remove Sigma `\` escapes from a value without wildcards.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next().unwrap_or('\\')),
+            other => unescaped.push(other),
+        }
+    }
+    unescaped
+}
+
+/// Translate a Sigma value with `*` / `?` wildcards and `\` escapes.
+fn wildcard_to_regex(value: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '\\' => match chars.next() {
+                Some(escaped) => regex.push_str(&regex::escape(&escaped.to_string())),
+                None => regex.push_str(r"\\"),
+            },
+            other => regex.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::pattern_matcher::PatternMatcher;
+    use crate::config::rules::Rules;
+
+    fn convert_sigma_str(content: &str) -> anyhow::Result<SigmaConversion> {
+        let mut conversion = SigmaConversion::default();
+        conversion.add_str(content)?;
+        Ok(conversion)
+    }
+
+    /// Round-trip the conversion through TOML and build a matcher from it.
+    fn matcher_for(conversion: &SigmaConversion) -> PatternMatcher {
+        let rules: Rules = toml::from_str(&conversion.to_toml().unwrap()).unwrap();
+        PatternMatcher::new(&rules).unwrap()
+    }
+
+    const SSH_RULE: &str = r#"
+title: SSH Brute Force
+level: high
+detection:
+    keywords:
+        - 'Failed password'
+        - 'Invalid user'
+    timeframe: 1m
+    condition: keywords | count() > 5
+"#;
+
+    #[test]
+    fn converts_keywords_and_count_aggregation() {
+        let conversion = convert_sigma_str(SSH_RULE).unwrap();
+        assert!(conversion.unsupported.is_empty());
+        let rule = &conversion.regex_rules[0];
+        assert_eq!(rule.name, "SSH Brute Force");
+        assert_eq!(rule.severity, "high");
+        let matcher = matcher_for(&conversion);
+        assert_eq!(
+            matcher.check_for_patterns("sshd: failed PASSWORD for root"),
+            Some(("high", "SSH Brute Force"))
+        );
+        assert_eq!(
+            matcher.check_for_patterns("sshd: Accepted password for root"),
+            None
+        );
+
+        let frequency = conversion.frequency_rules.as_ref().unwrap();
+        assert_eq!(frequency.max_same_errors_per_minute, 5);
+        assert_eq!(frequency.time_window_seconds, 60);
+        assert!(conversion.to_toml().unwrap().contains("[frequency_rules]"));
+    }
+
+    #[test]
+    fn converts_field_modifiers_and_boolean_conditions() {
+        let conversion = convert_sigma_str(
+            r#"
+title: Sudo To Root Shell
+level: medium
+detection:
+    selection_proc:
+        process|endswith: 'sudo'
+    selection_cmd:
+        command|contains|all:
+            - '/bin/'
+            - 'sh'
+    filter:
+        user: 'backup'
+    condition: all of selection_* and not filter
+"#,
+        )
+        .unwrap();
+        assert!(
+            conversion.unsupported.is_empty(),
+            "{:?}",
+            conversion.unsupported
+        );
+        assert_eq!(conversion.regex_rules[0].severity, "error");
+        let matcher = matcher_for(&conversion);
+        assert!(matcher
+            .check_for_patterns("process=/usr/bin/sudo user=alice command=/bin/sh")
+            .is_some());
+        assert!(matcher
+            .check_for_patterns("process=/usr/bin/sudo user=BACKUP command=/bin/sh")
+            .is_none());
+        assert!(matcher
+            .check_for_patterns("process=/usr/bin/sudo user=alice command=/usr/bin/vim")
+            .is_none());
+    }
+
+    #[test]
+    fn reports_unsupported_constructs() {
+        let conversion = convert_sigma_str(
+            r#"
+title: Base64
+detection:
+    selection:
+        data|base64: 'secret'
+    condition: selection
+---
+title: Grouped
+detection:
+    selection: 'Failed'
+    timeframe: 5m
+    condition: selection | count() by user > 3
+---
+title: Dangling
+detection:
+    selection: 'Failed'
+    condition: selection and filter
+"#,
+        )
+        .unwrap();
+        assert!(conversion.regex_rules.is_empty());
+        assert_eq!(
+            conversion.unsupported,
+            vec![
+                "Base64: field modifier \"base64\"",
+                "Grouped: aggregation \"count() by user > 3\"",
+                "Dangling: unknown selection \"filter\" in condition",
+            ]
+        );
+    }
+
+    #[test]
+    fn translates_wildcards_and_escapes() {
+        assert_eq!(wildcard_to_regex(r"a*b?c\*"), r"a.*b.c\*");
+        assert_eq!(wildcard_to_regex("1.2"), r"1\.2");
+        assert_eq!(unescape(r"c:\\temp\*"), r"c:\temp*");
+        assert!(has_wildcards("a*"));
+        assert!(!has_wildcards(r"a\*"));
+    }
+}
@@ -0,0 +1,1224 @@
+use crate::analyzer::condition::CompiledCondition;
+use crate::analyzer::correlation_engine::CompiledJoinKey;
+use crate::analyzer::ioc::load_indicators;
+use crate::analyzer::network::{Cidr, NetworkSets};
+use crate::analyzer::new_values::compile_value_regex;
+use crate::analyzer::suppression::expiry_deadline;
+use crate::config::rules::{
+    parse_rules, rule_layers, CorrelatedRule, GeoIpConfig, IocList, NewValueRule, OutputConfig,
+    Rules, SinkConfig, Site, SuppressRule, TravelRule,
+};
+use crate::output::template::Template;
+use crate::output::Severity;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml_edit::{ImDocument, Item, Table, TableLike, Value};
+
+/// A single problem found in a rule file, located by line and column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleIssue {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RuleIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.message
+        )
+    }
+}
+
+/// A key or value in a layer's TOML document, used to point an issue found
+/// in the parsed `Rules` at the text it came from. A lookup that misses
+/// keeps the span of the closest enclosing value.
+#[derive(Clone)]
+struct Node<'a> {
+    entry: Option<Entry<'a>>,
+    span: Range<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum Entry<'a> {
+    Item(&'a Item),
+    Table(&'a Table),
+    Value(&'a Value),
+}
+
+impl<'a> Node<'a> {
+    fn root(document: Option<&'a ImDocument<&str>>) -> Self {
+        Node {
+            entry: document.map(|document| Entry::Table(document.as_table())),
+            span: 0..0,
+        }
+    }
+
+    fn key(&self, name: &str) -> Self {
+        let table: Option<&dyn TableLike> = match self.entry {
+            Some(Entry::Item(item)) => item.as_table_like(),
+            Some(Entry::Table(table)) => Some(table),
+            Some(Entry::Value(value)) => value.as_inline_table().map(|t| t as &dyn TableLike),
+            None => None,
+        };
+        self.step(table.and_then(|table| table.get(name)).map(Entry::Item))
+    }
+
+    fn index(&self, index: usize) -> Self {
+        let entry = match self.entry {
+            Some(Entry::Item(Item::ArrayOfTables(tables))) => tables.get(index).map(Entry::Table),
+            Some(Entry::Item(item)) => item
+                .as_array()
+                .and_then(|array| array.get(index))
+                .map(Entry::Value),
+            Some(Entry::Value(value)) => value
+                .as_array()
+                .and_then(|array| array.get(index))
+                .map(Entry::Value),
+            _ => None,
+        };
+        self.step(entry)
+    }
+
+    fn step(&self, entry: Option<Entry<'a>>) -> Self {
+        let span = entry.and_then(|entry| match entry {
+            Entry::Item(item) => item.span(),
+            Entry::Table(table) => table.span(),
+            Entry::Value(value) => value.span(),
+        });
+        Node {
+            entry,
+            span: span.unwrap_or_else(|| self.span.clone()),
+        }
+    }
+
+    fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+}
+
+struct IssueCollector<'a> {
+    file: &'a Path,
+    content: &'a str,
+    issues: Vec<RuleIssue>,
+}
+
+impl IssueCollector<'_> {
+    fn push(&mut self, span: Range<usize>, message: String) {
+        let (line, column) = line_col(self.content, span.start);
+        self.issues.push(RuleIssue {
+            file: self.file.to_path_buf(),
+            line,
+            column,
+            message,
+        });
+    }
+
+    /// Report `name` if an earlier entry of the same kind already used it.
+    fn check_unique<'n>(
+        &mut self,
+        seen: &mut HashMap<&'n str, usize>,
+        kind: &str,
+        name: &'n str,
+        node: &Node<'_>,
+    ) {
+        if let Some(first_line) = seen.get(name) {
+            self.push(
+                node.span(),
+                format!("duplicate {kind} name \"{name}\" (first defined on line {first_line})"),
+            );
+        } else {
+            seen.insert(name, line_col(self.content, node.span().start).0);
+        }
+    }
+}
+
+/// Convert a byte offset into a 1-based (line, column) pair.
+fn line_col(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rfind('\n')
+        .map_or(before.chars().count(), |i| before[i + 1..].chars().count())
+        + 1;
+    (line, column)
+}
+
+/// Check a rule file, its includes and `rules.d` drop-ins for problems that
+/// deserialization alone accepts: dangling rule references, duplicate names,
+/// unknown severities, zero thresholds and regexes that fail to compile.
+/// With `open_files`, IOC lists and GeoIP databases are opened as well;
+/// startup leaves that to the matcher, which opens them anyway.
+///
+/// Each layer is parsed by the rule loader itself, so anything the loader
+/// rejects is reported at the location it gives. Rule references may point
+/// at rules defined in any layer; duplicate names are only reported within
+/// a single file, since later layers override earlier ones by name.
+pub fn validate_rules_file(path: &Path, open_files: bool) -> anyhow::Result<Vec<RuleIssue>> {
+    let content = std::fs::read_to_string(path)?;
+    let layer_paths = match rule_layers(path) {
+        Ok(layer_paths) => layer_paths,
+        Err(e) => {
+            // Prefer located syntax errors over the include resolution error
+            let issues = validate_rules_str(path, &content);
+            return if issues.is_empty() {
+                Err(e)
+            } else {
+                Ok(issues)
+            };
+        }
+    };
+
+    let mut layers = Vec::with_capacity(layer_paths.len());
+    for layer in layer_paths {
+        let content = std::fs::read_to_string(&layer)?;
+        layers.push((layer, content));
+    }
+    Ok(validate_layers(&layers, open_files))
+}
+
+pub fn validate_rules_str(path: &Path, content: &str) -> Vec<RuleIssue> {
+    validate_layers(&[(path.to_path_buf(), content.to_string())], false)
+}
+
+fn validate_layers(layers: &[(PathBuf, String)], open_files: bool) -> Vec<RuleIssue> {
+    let mut issues = Vec::new();
+    let mut parsed = Vec::new();
+    for (path, content) in layers {
+        match parse_rules(path, content) {
+            Ok(rules) => parsed.push((path, content, rules)),
+            Err(e) => {
+                let mut collector = IssueCollector {
+                    file: path,
+                    content,
+                    issues: Vec::new(),
+                };
+                collector.push(e.span().unwrap_or(0..0), e.message().to_string());
+                issues.append(&mut collector.issues);
+            }
+        }
+    }
+
+    let known_names: HashSet<&str> = parsed
+        .iter()
+        .flat_map(|(_, _, rules)| {
+            rules
+                .patterns
+                .error_patterns
+                .iter()
+                .chain(&rules.patterns.warning_patterns)
+                .map(|pattern| pattern.name())
+                .chain(rules.regex_rules.iter().map(|r| r.name.as_str()))
+                .chain(rules.ioc_lists.iter().map(|l| l.name.as_str()))
+        })
+        .collect();
+
+    // Suppress rules may also target correlated, travel and new value rules
+    let detection_names: HashSet<&str> = parsed
+        .iter()
+        .flat_map(|(_, _, rules)| {
+            rules
+                .correlated_rules
+                .iter()
+                .map(|r| r.name.as_str())
+                .chain(rules.travel_rules.iter().map(|r| r.name.as_str()))
+                .chain(rules.new_value_rules.iter().map(|r| r.name.as_str()))
+        })
+        .chain(known_names.iter().copied())
+        .collect();
+
+    // Valid entries of every layer's network sets, later layers winning
+    let mut network_entries: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (_, _, rules) in &parsed {
+        for (name, entries) in &rules.networks {
+            let valid = entries
+                .iter()
+                .filter(|entry| entry.parse::<Cidr>().is_ok())
+                .cloned()
+                .collect();
+            network_entries.insert(name.clone(), valid);
+        }
+    }
+    let networks = NetworkSets::new(&network_entries).unwrap_or_default();
+
+    for (path, content, rules) in &parsed {
+        let document = ImDocument::parse(content.as_str()).ok();
+        let root = Node::root(document.as_ref());
+        let mut collector = IssueCollector {
+            file: path,
+            content,
+            issues: Vec::new(),
+        };
+        check_networks(&mut collector, &root, &rules.networks);
+        check_layer(&mut collector, &root, rules, &known_names, &networks);
+        check_suppress_rules(
+            &mut collector,
+            &root,
+            &rules.suppress_rules,
+            &detection_names,
+            &networks,
+        );
+        check_ioc_lists(&mut collector, &root, &rules.ioc_lists, open_files);
+        if let (Some(geoip), true) = (&rules.geoip, open_files) {
+            check_geoip(&mut collector, &root, geoip);
+        }
+        check_sites(&mut collector, &root, &rules.sites, &networks);
+        check_travel_rules(&mut collector, &root, &rules.travel_rules, &known_names);
+        check_new_value_rules(&mut collector, &root, &rules.new_value_rules, &networks);
+        check_outputs(&mut collector, &root, &rules.outputs, &detection_names);
+        issues.append(&mut collector.issues);
+    }
+    issues
+}
+
+fn check_networks(
+    collector: &mut IssueCollector<'_>,
+    root: &Node<'_>,
+    networks: &BTreeMap<String, Vec<String>>,
+) {
+    for (name, entries) in networks {
+        let set = root.key("networks").key(name);
+        for (index, entry) in entries.iter().enumerate() {
+            if let Err(e) = entry.parse::<Cidr>() {
+                collector.push(
+                    set.index(index).span(),
+                    format!("network set \"{name}\": {e}"),
+                );
+            }
+        }
+    }
+}
+
+fn check_layer(
+    collector: &mut IssueCollector<'_>,
+    root: &Node<'_>,
+    rules: &Rules,
+    known_names: &HashSet<&str>,
+    networks: &NetworkSets,
+) {
+    let patterns = root.key("rules");
+    for (key, list) in [
+        ("error_patterns", &rules.patterns.error_patterns),
+        ("warning_patterns", &rules.patterns.warning_patterns),
+    ] {
+        for (index, pattern) in list.iter().enumerate() {
+            let node = patterns.key(key).index(index);
+            if pattern.pattern().is_empty() {
+                collector.push(
+                    node.span(),
+                    "empty string pattern would match every line".to_string(),
+                );
+            }
+            if let Some(severity) = pattern.severity() {
+                if Severity::parse(severity).is_none() {
+                    collector.push(
+                        node.span(),
+                        format!(
+                            "unknown severity \"{severity}\" for string pattern \"{}\" (expected critical, high, error, warning or info)",
+                            pattern.name()
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    let mut regex_names = HashMap::new();
+    for (index, rule) in rules.regex_rules.iter().enumerate() {
+        let node = root.key("regex_rules").index(index);
+        let name = rule.name.as_str();
+        collector.check_unique(&mut regex_names, "regex rule", name, &node.key("name"));
+        check_severity(collector, &rule.severity, &node);
+        match (rule.pattern.is_empty(), &rule.condition) {
+            (false, None) => {
+                if let Err(e) = Regex::new(&rule.pattern) {
+                    collector.push(
+                        node.key("pattern").span(),
+                        format!("invalid regex in rule \"{name}\": {e}"),
+                    );
+                }
+            }
+            (true, Some(condition)) => {
+                if let Err(e) = CompiledCondition::compile(condition, networks) {
+                    collector.push(
+                        node.key("condition").span(),
+                        format!("invalid condition in rule \"{name}\": {e}"),
+                    );
+                }
+            }
+            (false, Some(_)) => collector.push(
+                node.key("pattern").span(),
+                format!("rule \"{name}\" sets both pattern and condition"),
+            ),
+            (true, None) => collector.push(
+                node.key("name").span(),
+                format!("rule \"{name}\" needs a pattern or a condition"),
+            ),
+        }
+    }
+
+    if let Some(frequency) = &rules.frequency_rules {
+        let node = root.key("frequency_rules");
+        check_nonzero(
+            collector,
+            &node,
+            "frequency_rules",
+            "max_same_errors_per_minute",
+            frequency.max_same_errors_per_minute,
+        );
+        check_nonzero(
+            collector,
+            &node,
+            "frequency_rules",
+            "time_window_seconds",
+            frequency.time_window_seconds,
+        );
+    }
+
+    let mut correlated_names = HashMap::new();
+    for (index, rule) in rules.correlated_rules.iter().enumerate() {
+        let node = root.key("correlated_rules").index(index);
+        collector.check_unique(
+            &mut correlated_names,
+            "correlated rule",
+            &rule.name,
+            &node.key("name"),
+        );
+        check_correlated_rule(collector, &node, rule, known_names, networks);
+    }
+}
+
+fn check_correlated_rule(
+    collector: &mut IssueCollector<'_>,
+    node: &Node<'_>,
+    rule: &CorrelatedRule,
+    known_names: &HashSet<&str>,
+    networks: &NetworkSets,
+) {
+    let name = rule.name.as_str();
+    check_severity(collector, &rule.severity, node);
+    check_nonzero(
+        collector,
+        node,
+        "correlated_rules",
+        "time_window_seconds",
+        rule.time_window_seconds,
+    );
+    let trigger = node.key("trigger_on_rule");
+    check_nonzero(
+        collector,
+        &trigger,
+        "correlated_rules.trigger_on_rule",
+        "count",
+        rule.trigger_on_rule.count,
+    );
+
+    if let Some(join) = &rule.join {
+        if let Err(e) = CompiledJoinKey::compile(join, networks) {
+            collector.push(
+                node.key("join").span(),
+                format!("invalid join in correlated rule \"{name}\": {e}"),
+            );
+        }
+    }
+
+    for (reference, reference_node) in [
+        (&rule.trigger_on_rule.name, trigger.key("name")),
+        (&rule.followed_by, node.key("followed_by")),
+    ] {
+        if !known_names.contains(reference.as_str()) {
+            collector.push(
+                reference_node.span(),
+                format!("correlated rule \"{name}\" references unknown rule \"{reference}\""),
+            );
+        }
+    }
+}
+
+fn check_suppress_rules(
+    collector: &mut IssueCollector<'_>,
+    root: &Node<'_>,
+    suppress_rules: &[SuppressRule],
+    detection_names: &HashSet<&str>,
+    networks: &NetworkSets,
+) {
+    let mut names = HashMap::new();
+    for (index, rule) in suppress_rules.iter().enumerate() {
+        let node = root.key("suppress_rules").index(index);
+        let name = rule.name.as_str();
+        collector.check_unique(&mut names, "suppress rule", name, &node.key("name"));
+
+        if rule.pattern.is_empty()
+            && rule.condition.is_none()
+            && rule.rules.is_empty()
+            && rule.files.is_empty()
+        {
+            collector.push(
+                node.key("name").span(),
+                format!(
+                    "suppress rule \"{name}\" would suppress everything; set pattern, condition, rules or files"
+                ),
+            );
+        }
+        if !rule.pattern.is_empty() {
+            if let Err(e) = Regex::new(&rule.pattern) {
+                collector.push(
+                    node.key("pattern").span(),
+                    format!("invalid regex in suppress rule \"{name}\": {e}"),
+                );
+            }
+        }
+        if let Some(condition) = &rule.condition {
+            if let Err(e) = CompiledCondition::compile(condition, networks) {
+                collector.push(
+                    node.key("condition").span(),
+                    format!("invalid condition in suppress rule \"{name}\": {e}"),
+                );
+            }
+        }
+        for (index, reference) in rule.rules.iter().enumerate() {
+            if !detection_names.contains(reference.as_str()) {
+                collector.push(
+                    node.key("rules").index(index).span(),
+                    format!("suppress rule \"{name}\" references unknown rule \"{reference}\""),
+                );
+            }
+        }
+        for (index, file) in rule.files.iter().enumerate() {
+            if let Err(e) = glob::Pattern::new(file) {
+                collector.push(
+                    node.key("files").index(index).span(),
+                    format!("invalid file glob in suppress rule \"{name}\": {e}"),
+                );
+            }
+        }
+        if let Some(expires) = &rule.expires {
+            if let Err(e) = expiry_deadline(expires) {
+                collector.push(
+                    node.key("expires").span(),
+                    format!("suppress rule \"{name}\": {e}"),
+                );
+            }
+        }
+    }
+}
+
+fn check_ioc_lists(
+    collector: &mut IssueCollector<'_>,
+    root: &Node<'_>,
+    ioc_lists: &[IocList],
+    open_files: bool,
+) {
+    let mut names = HashMap::new();
+    for (index, list) in ioc_lists.iter().enumerate() {
+        let node = root.key("ioc_lists").index(index);
+        let name = list.name.as_str();
+        collector.check_unique(&mut names, "IOC list", name, &node.key("name"));
+        check_severity(collector, &list.severity, &node);
+        if open_files {
+            if let Err(e) = load_indicators(list) {
+                collector.push(
+                    node.key("path").span(),
+                    format!("IOC list \"{name}\": {e:#}"),
+                );
+            }
+        }
+    }
+}
+
+fn check_geoip(collector: &mut IssueCollector<'_>, root: &Node<'_>, geoip: &GeoIpConfig) {
+    for (index, path) in geoip.databases.iter().enumerate() {
+        if let Err(e) = maxminddb::Reader::open_readfile(path) {
+            collector.push(
+                root.key("geoip").key("databases").index(index).span(),
+                format!("cannot open GeoIP database {}: {e}", path.display()),
+            );
+        }
+    }
+}
+
+fn check_sites(
+    collector: &mut IssueCollector<'_>,
+    root: &Node<'_>,
+    sites: &BTreeMap<String, Site>,
+    networks: &NetworkSets,
+) {
+    for (name, site) in sites {
+        let node = root.key("sites").key(name);
+        if let Err(e) = networks.resolve(&site.networks) {
+            collector.push(node.key("networks").span(), format!("site \"{name}\": {e}"));
+        }
+        for (key, value, limit) in [
+            ("latitude", site.latitude, 90.0),
+            ("longitude", site.longitude, 180.0),
+        ] {
+            if value.is_some_and(|value| value.abs() > limit) {
+                collector.push(
+                    node.key(key).span(),
+                    format!("site \"{name}\": {key} must be between -{limit} and {limit}"),
+                );
+            }
+        }
+        if site.latitude.is_some() != site.longitude.is_some() {
+            collector.push(
+                node.key("networks").span(),
+                format!("site \"{name}\" sets only one of latitude and longitude"),
+            );
+        }
+    }
+}
+
+fn check_travel_rules(
+    collector: &mut IssueCollector<'_>,
+    root: &Node<'_>,
+    travel_rules: &[TravelRule],
+    known_names: &HashSet<&str>,
+) {
+    let mut names = HashMap::new();
+    for (index, rule) in travel_rules.iter().enumerate() {
+        let node = root.key("travel_rules").index(index);
+        let name = rule.name.as_str();
+        collector.check_unique(&mut names, "travel rule", name, &node.key("name"));
+        check_severity(collector, &rule.severity, &node);
+        if rule.max_speed_kmh <= 0.0 {
+            collector.push(
+                node.key("max_speed_kmh").span(),
+                "travel_rules.max_speed_kmh must be greater than zero".to_string(),
+            );
+        }
+        if rule.rules.is_empty() {
+            collector.push(
+                node.key("name").span(),
+                format!("travel rule \"{name}\" lists no login rules"),
+            );
+        }
+        if rule.user_field.is_empty() {
+            collector.push(
+                node.key("user_field").span(),
+                format!("travel rule \"{name}\" has an empty user_field"),
+            );
+        }
+        for (index, reference) in rule.rules.iter().enumerate() {
+            if !known_names.contains(reference.as_str()) {
+                collector.push(
+                    node.key("rules").index(index).span(),
+                    format!("travel rule \"{name}\" references unknown rule \"{reference}\""),
+                );
+            }
+        }
+    }
+}
+
+fn check_new_value_rules(
+    collector: &mut IssueCollector<'_>,
+    root: &Node<'_>,
+    new_value_rules: &[NewValueRule],
+    networks: &NetworkSets,
+) {
+    let mut names = HashMap::new();
+    for (index, rule) in new_value_rules.iter().enumerate() {
+        let node = root.key("new_value_rules").index(index);
+        let name = rule.name.as_str();
+        collector.check_unique(&mut names, "new value rule", name, &node.key("name"));
+        check_severity(collector, &rule.severity, &node);
+        if let Some(condition) = &rule.condition {
+            if let Err(e) = CompiledCondition::compile(condition, networks) {
+                collector.push(
+                    node.key("condition").span(),
+                    format!("invalid condition in new value rule \"{name}\": {e}"),
+                );
+            }
+        }
+        match (&rule.field, &rule.regex) {
+            (Some(_), None) => {}
+            (None, Some(regex)) => {
+                if let Err(e) = compile_value_regex(regex) {
+                    collector.push(
+                        node.key("regex").span(),
+                        format!("invalid regex in new value rule \"{name}\": {e}"),
+                    );
+                }
+            }
+            (Some(_), Some(_)) => collector.push(
+                node.key("field").span(),
+                format!("new value rule \"{name}\" sets both field and regex"),
+            ),
+            (None, None) => collector.push(
+                node.key("name").span(),
+                format!("new value rule \"{name}\" needs a field or a regex"),
+            ),
+        }
+    }
+}
+
+fn check_outputs(
+    collector: &mut IssueCollector<'_>,
+    root: &Node<'_>,
+    outputs: &[OutputConfig],
+    detection_names: &HashSet<&str>,
+) {
+    let mut names = HashMap::new();
+    for (index, output) in outputs.iter().enumerate() {
+        let node = root.key("outputs").index(index);
+        let label = output.label();
+        if let Some(name) = &output.name {
+            collector.check_unique(&mut names, "output", name, &node.key("name"));
+        }
+        if let Some(severity) = &output.min_severity {
+            if Severity::parse(severity).is_none() {
+                collector.push(node.key("min_severity").span(), severity_message(severity));
+            }
+        }
+        let templates: Vec<(&String, Node<'_>)> = match &output.sink {
+            SinkConfig::Webhook(webhook) => webhook
+                .template
+                .iter()
+                .map(|template| (template, node.key("template")))
+                .collect(),
+            SinkConfig::Exec(exec) => {
+                let command = node.key("command");
+                if exec.command.is_empty() {
+                    collector.push(
+                        command.span(),
+                        format!("exec output \"{label}\" has an empty command"),
+                    );
+                } else if exec.command[0].contains("{{") {
+                    collector.push(
+                        command.index(0).span(),
+                        format!("exec output \"{label}\": the program cannot contain placeholders"),
+                    );
+                }
+                exec.command
+                    .iter()
+                    .enumerate()
+                    .map(|(index, arg)| (arg, command.index(index)))
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        for (template, template_node) in templates {
+            if let Err(e) = Template::parse(template, false) {
+                collector.push(template_node.span(), format!("output \"{label}\": {e}"));
+            }
+        }
+        for (key, list) in [
+            ("rules", &output.rules),
+            ("exclude_rules", &output.exclude_rules),
+        ] {
+            for (index, reference) in list.iter().enumerate() {
+                if !detection_names.contains(reference.as_str()) {
+                    collector.push(
+                        node.key(key).index(index).span(),
+                        format!("output \"{label}\" references unknown rule \"{reference}\""),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Report `severity` of the rule at `node` if it is not a known level.
+fn check_severity(collector: &mut IssueCollector<'_>, severity: &str, node: &Node<'_>) {
+    if Severity::parse(severity).is_none() {
+        collector.push(node.key("severity").span(), severity_message(severity));
+    }
+}
+
+fn severity_message(severity: &str) -> String {
+    format!("unknown severity \"{severity}\" (expected critical, high, error, warning or info)")
+}
+
+fn check_nonzero<T: Default + PartialEq>(
+    collector: &mut IssueCollector<'_>,
+    node: &Node<'_>,
+    section: &str,
+    key: &str,
+    value: T,
+) {
+    if value == T::default() {
+        collector.push(
+            node.key(key).span(),
+            format!("{section}.{key} must be greater than zero"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(content: &str) -> Vec<RuleIssue> {
+        validate_rules_str(Path::new("test.toml"), content)
+    }
+
+    #[test]
+    fn bundled_rule_files_are_valid() {
+        for file in ["rules.toml", "correlated_rules.toml"] {
+            let issues = validate_rules_file(Path::new(file), true).unwrap();
+            assert!(issues.is_empty(), "{file}: {issues:?}");
+        }
+    }
+
+    #[test]
+    fn reports_settings_only_the_loader_checks() {
+        let issues = validate(
+            r#"
+[[outputs]]
+type = "syslog"
+protocol = "tcp"
+"#,
+        );
+        assert_eq!(issues.len(), 1, "{issues:?}");
+        assert!(issues[0].message.contains("missing field `address`"));
+    }
+
+    #[test]
+    fn reports_unknown_references_with_location() {
+        let issues = validate(
+            r#"
+[[regex_rules]]
+name = "authentication_failure"
+pattern = "Failed password"
+severity = "high"
+
+[[correlated_rules]]
+name = "Brute force"
+severity = "critical"
+description = "test"
+time_window_seconds = 60
+followed_by = "Successful Login"
+
+[correlated_rules.trigger_on_rule]
+name = "authentication_failure"
+count = 3
+"#,
+        );
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, 12);
+        assert_eq!(issues[0].column, 15);
+        assert!(issues[0].message.contains("\"Successful Login\""));
+    }
+
+    #[test]
+    fn reports_duplicates_severities_and_zero_thresholds() {
+        let issues = validate(
+            r#"
+[[regex_rules]]
+name = "dup"
+pattern = "a"
+severity = "high"
+
+[[regex_rules]]
+name = "dup"
+pattern = "b"
+severity = "severe"
+
+[frequency_rules]
+max_same_errors_per_minute = 0
+time_window_seconds = 60
+"#,
+        );
+        let messages: Vec<_> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(issues.len(), 3, "{messages:?}");
+        assert!(messages[0].starts_with("duplicate regex rule name \"dup\""));
+        assert!(messages[1].starts_with("unknown severity \"severe\""));
+        assert!(messages[2].contains("max_same_errors_per_minute"));
+        assert_eq!(issues[2].line, 13);
+    }
+
+    #[test]
+    fn reports_syntax_errors_and_bad_regexes() {
+        let syntax = validate("[[regex_rules]\nname = 1\n");
+        assert_eq!(syntax.len(), 1);
+        assert_eq!(syntax[0].line, 1);
+
+        let regex =
+            validate("[[regex_rules]]\nname = \"x\"\npattern = \"(\"\nseverity = \"high\"\n");
+        assert_eq!(regex.len(), 1);
+        assert_eq!(regex[0].line, 3);
+        assert!(regex[0].message.starts_with("invalid regex"));
+    }
+
+    #[test]
+    fn checks_condition_rules() {
+        let issues = validate(
+            r#"
+[[regex_rules]]
+name = "ok"
+severity = "high"
+condition = { all = [{ contains = "sshd" }, { not = { regex = "from 10\\." } }] }
+
+[[regex_rules]]
+name = "bad_regex"
+severity = "high"
+condition = { any = [{ regex = "(" }] }
+
+[[regex_rules]]
+name = "both"
+pattern = "x"
+severity = "high"
+condition = { contains = "x" }
+
+[[regex_rules]]
+name = "neither"
+severity = "high"
+"#,
+        );
+        let messages: Vec<_> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{messages:?}");
+        assert!(messages[0].starts_with("invalid condition in rule \"bad_regex\""));
+        assert_eq!(issues[0].line, 10);
+        assert_eq!(messages[1], "rule \"both\" sets both pattern and condition");
+        assert_eq!(
+            messages[2],
+            "rule \"neither\" needs a pattern or a condition"
+        );
+    }
+
+    #[test]
+    fn checks_suppress_rules() {
+        let issues = validate(
+            r#"
+[[regex_rules]]
+name = "auth"
+pattern = "Failed password"
+severity = "high"
+
+[[suppress_rules]]
+name = "ok"
+rules = ["auth"]
+files = ["auth*.log"]
+expires = 2030-01-01
+
+[[suppress_rules]]
+name = "everything"
+
+[[suppress_rules]]
+name = "bad"
+pattern = "("
+rules = ["missing"]
+files = ["auth**"]
+expires = 12:00:00
+"#,
+        );
+        let messages: Vec<_> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(messages.len(), 5, "{messages:?}");
+        assert!(messages[0].contains("\"everything\" would suppress everything"));
+        assert!(messages[1].starts_with("invalid regex in suppress rule \"bad\""));
+        assert_eq!(
+            messages[2],
+            "suppress rule \"bad\" references unknown rule \"missing\""
+        );
+        assert!(messages[3].starts_with("invalid file glob"));
+        assert!(messages[4].contains("expires needs a date"));
+        assert_eq!(issues[4].line, 21);
+    }
+
+    #[test]
+    fn checks_string_pattern_entries() {
+        let issues = validate(
+            r#"
+[rules]
+error_patterns = ["ERROR", { pattern = "oom", severity = "severe" }]
+warning_patterns = [""]
+"#,
+        );
+        let messages: Vec<_> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(messages[0].starts_with("unknown severity \"severe\" for string pattern \"oom\""));
+        assert_eq!((issues[0].line, issues[0].column), (3, 28));
+        assert_eq!(messages[1], "empty string pattern would match every line");
+    }
+
+    #[test]
+    fn string_patterns_are_valid_correlation_targets() {
+        let issues = validate(
+            r#"
+[rules]
+error_patterns = ["ERROR"]
+
+[[correlated_rules]]
+name = "errors then more errors"
+severity = "high"
+description = "test"
+time_window_seconds = 60
+followed_by = "ERROR"
+
+[correlated_rules.trigger_on_rule]
+name = "ERROR"
+count = 2
+"#,
+        );
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+
+    #[test]
+    fn checks_networks_and_cidr_conditions() {
+        let issues = validate(
+            r#"
+[networks]
+internal = ["10.0.0.0/8", "fd00::/8", "10.0.0.0/40"]
+
+[[regex_rules]]
+name = "external_login"
+severity = "high"
+condition = { not_in_cidr = { networks = ["internal"] } }
+
+[[regex_rules]]
+name = "typo"
+severity = "high"
+condition = { in_cidr = { networks = ["internl"] } }
+
+[[correlated_rules]]
+name = "logins"
+severity = "high"
+description = "test"
+time_window_seconds = 60
+followed_by = "external_login"
+join = { not_in_cidr = ["internal"], ipv4_prefix = 33 }
+
+[correlated_rules.trigger_on_rule]
+name = "external_login"
+count = 2
+"#,
+        );
+        let messages: Vec<_> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{messages:?}");
+        assert!(messages[0].starts_with("network set \"internal\": prefix /40 is too long"));
+        assert_eq!((issues[0].line, issues[0].column), (3, 39));
+        assert!(messages[1].contains("\"internl\" is neither a network set nor a CIDR"));
+        assert_eq!(
+            messages[2],
+            "invalid join in correlated rule \"logins\": ipv4_prefix must be at most 32"
+        );
+    }
+
+    #[test]
+    fn checks_sites_and_travel_rules() {
+        let issues = validate(
+            r#"
+[sites.office]
+networks = ["10.1.0.0/16", "offices"]
+latitude = 141.0
+longitude = 29.0
+
+[[regex_rules]]
+name = "login"
+pattern = "Accepted"
+severity = "info"
+
+[[travel_rules]]
+name = "impossible_travel"
+rules = ["login", "vpn_login"]
+user_field = "user"
+max_speed_kmh = 0.0
+severity = "high"
+"#,
+        );
+        let messages: Vec<_> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "site \"office\": \"offices\" is neither a network set nor a CIDR: invalid IP address in \"offices\"",
+                "site \"office\": latitude must be between -90 and 90",
+                "travel_rules.max_speed_kmh must be greater than zero",
+                "travel rule \"impossible_travel\" references unknown rule \"vpn_login\"",
+            ]
+        );
+        assert_eq!((issues[1].line, issues[1].column), (4, 12));
+    }
+
+    #[test]
+    fn checks_new_value_rules() {
+        let issues = validate(
+            r#"
+[[new_value_rules]]
+name = "new_user"
+field = "user"
+severity = "warning"
+
+[[new_value_rules]]
+name = "new_process"
+regex = "kernel: \\w+"
+severity = "info"
+
+[[new_value_rules]]
+name = "new_user"
+severity = "loud"
+
+[[suppress_rules]]
+name = "known_service_accounts"
+rules = ["new_user"]
+"#,
+        );
+        let messages: Vec<_> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "invalid regex in new value rule \"new_process\": the regex needs a capture group for the value",
+                "duplicate new value rule name \"new_user\" (first defined on line 3)",
+                "unknown severity \"loud\" (expected critical, high, error, warning or info)",
+                "new value rule \"new_user\" needs a field or a regex",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_outputs() {
+        let issues = validate(
+            r#"
+[[regex_rules]]
+name = "auth_failure"
+pattern = "Failed password"
+severity = "high"
+
+[[outputs]]
+name = "alerts"
+type = "json"
+path = "alerts.jsonl"
+min_severity = "high"
+exclude_rules = ["auth_failure"]
+
+[[outputs]]
+name = "alerts"
+type = "console"
+min_severity = "urgent"
+rules = ["auth_failures"]
+
+[[outputs]]
+type = "webhook"
+url = "https://hooks.example.com/x"
+template = "{{rule_id}} {{rule}}"
+
+[[outputs]]
+name = "ban"
+type = "exec"
+command = ["fail2ban-client", "set", "sshd", "banip", "{{fields.ip}}", "{{ip}}"]
+
+[[outputs]]
+name = "tool"
+type = "exec"
+command = ["{{fields.tool}}", "--ban"]
+"#,
+        );
+        let messages: Vec<_> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "duplicate output name \"alerts\" (first defined on line 8)",
+                "unknown severity \"urgent\" (expected critical, high, error, warning or info)",
+                "output \"alerts\" references unknown rule \"auth_failures\"",
+                "output \"webhook\": template placeholder \"{{rule}}\" is not a detection record field",
+                "output \"ban\": template placeholder \"{{ip}}\" is not a detection record field",
+                "exec output \"tool\": the program cannot contain placeholders",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_outputs_the_loader_rejects() {
+        for (output, expected) in [
+            ("type = \"pager\"", "unknown variant `pager`"),
+            ("type = \"exec\"", "missing field `command`"),
+        ] {
+            let issues = validate(&format!("[[regex_rules]]\nname = \"x\"\npattern = \"x\"\nseverity = \"info\"\n\n[[outputs]]\n{output}\n"));
+            assert_eq!(issues.len(), 1, "{issues:?}");
+            assert!(issues[0].message.contains(expected), "{issues:?}");
+            assert_eq!(issues[0].line, 6);
+        }
+    }
+
+    #[test]
+    fn checks_ioc_lists() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        std::fs::create_dir_all(dir.join("feeds")).unwrap();
+        std::fs::write(dir.join("feeds/ips.txt"), "203.0.113.5\n").unwrap();
+        std::fs::write(dir.join("feeds/feed.csv"), "indicator\nevil.example\n").unwrap();
+        std::fs::write(
+            dir.join("rules.toml"),
+            r#"[[ioc_lists]]
+name = "scanners"
+path = "feeds/ips.txt"
+severity = "high"
+
+[[ioc_lists]]
+name = "feed"
+path = "feeds/feed.csv"
+column = "domain"
+severity = "high"
+
+[[ioc_lists]]
+name = "scanners"
+path = "feeds/missing.txt"
+severity = "severe"
+
+[[correlated_rules]]
+name = "scanner then feed"
+severity = "critical"
+description = "test"
+time_window_seconds = 60
+followed_by = "feed"
+
+[correlated_rules.trigger_on_rule]
+name = "scanners"
+count = 1
+"#,
+        )
+        .unwrap();
+
+        let issues = validate_rules_file(&dir.join("rules.toml"), true).unwrap();
+        let messages: Vec<_> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(messages.len(), 4, "{messages:?}");
+        assert!(messages[0].contains("no column named \"domain\""));
+        assert_eq!(issues[0].line, 8);
+        assert!(messages[1].starts_with("duplicate IOC list name \"scanners\""));
+        assert!(messages[2].starts_with("unknown severity \"severe\""));
+        assert!(messages[3].contains("Cannot read"));
+    }
+
+    #[test]
+    fn references_resolve_across_included_files() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        std::fs::write(
+            dir.join("base.toml"),
+            "[[regex_rules]]\nname = \"login\"\npattern = \"Accepted\"\nseverity = \"info\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("rules.toml"),
+            r#"include = ["base.toml"]
+
+[[regex_rules]]
+name = "login"
+pattern = "session opened"
+severity = "info"
+
+[[correlated_rules]]
+name = "login seen"
+severity = "info"
+description = "test"
+time_window_seconds = 60
+followed_by = "login"
+
+[correlated_rules.trigger_on_rule]
+name = "login"
+count = 1
+"#,
+        )
+        .unwrap();
+
+        let issues = validate_rules_file(&dir.join("rules.toml"), true).unwrap();
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+}
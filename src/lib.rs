@@ -1,4 +1,5 @@
 pub mod analyzer;
 pub mod config;
+pub mod metrics;
 pub mod output;
 pub mod watcher;
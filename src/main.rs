@@ -1,12 +1,8 @@
 use anyhow::Context;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use rust_hound::{analyzer, config, metrics, output, watcher};
 use std::path::PathBuf;
 
-mod analyzer;
-mod config;
-mod output;
-mod watcher;
-
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
@@ -22,14 +18,36 @@ struct Args {
     #[clap(long, short = 'r', value_parser)]
     rules: Option<PathBuf>,
 
-    /// Output format: console, json, both
-    #[clap(long, short = 'o', value_parser, default_value = "console")]
-    output: String,
+    /// Output format: console, json, both (default: the configured [[outputs]], else console)
+    #[clap(long, short = 'o', value_parser)]
+    output: Option<String>,
+
+    /// Write JSON output to this file instead of stdout ("-" for stdout);
+    /// implies JSON output when --output is console
+    #[clap(long, visible_alias = "json-path", value_parser)]
+    output_file: Option<PathBuf>,
+
+    /// JSON layout: lines (one object per line) or array (one document, not with --follow)
+    #[clap(long, value_parser, default_value = "lines")]
+    json_format: config::rules::JsonFormat,
+
+    /// Read [[outputs]] from this file instead of the rule files
+    #[clap(long, value_parser)]
+    outputs: Option<PathBuf>,
 
     /// Enable tail -f mode (real-time monitoring)
     #[clap(long, short = 'F')]
     follow: bool,
 
+    /// Write an incident report of a one-shot scan: .html or .md (Markdown)
+    #[clap(long, value_parser, conflicts_with = "follow")]
+    report: Option<PathBuf>,
+
+    /// Serve Prometheus metrics at http://<ADDR>/metrics in follow mode,
+    /// e.g. 127.0.0.1:9898
+    #[clap(long, value_parser, requires = "follow")]
+    metrics_addr: Option<String>,
+
     /// Enable debug output
     #[clap(long, short = 'v')]
     verbose: bool,
@@ -41,12 +59,91 @@ struct Args {
     /// Show only specific severity levels (critical,high,warning,error,info)
     #[clap(long, short = 's', value_parser)]
     severity: Option<String>,
+
+    /// Print every matching rule per line instead of only the most severe one
+    #[clap(long)]
+    all_matches: bool,
+
+    /// Number of worker threads for --dir scans (default: available CPUs)
+    #[clap(long, short = 'j', value_parser)]
+    jobs: Option<std::num::NonZeroUsize>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-fn min_severity_filter(args: &Args) -> Option<output::Severity> {
-    args.severity
-        .as_ref()
-        .map(|s| output::Severity::from(s.as_str()))
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check rule files for problems and exit non-zero if any are found
+    Validate {
+        /// Rule files to check (default: the resolved rules file)
+        files: Vec<PathBuf>,
+    },
+    /// Run the inline test cases declared in rule files
+    Test {
+        /// Rule files to test (default: the resolved rules file)
+        files: Vec<PathBuf>,
+    },
+    /// Convert Sigma detection rules into a rules.toml fragment
+    ImportSigma {
+        /// Sigma rule files (.yml)
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Write the converted rules here instead of stdout
+        #[clap(long, short = 'o', value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Print the JSON Schema of the detections written by JSON outputs
+    Schema,
+}
+
+/// The outputs of this run: `--output` / `--output-file` when given,
+/// otherwise the `[[outputs]]` of `--outputs` or the rule files, otherwise
+/// the console. `--severity` and `--all-matches` apply to console outputs.
+fn output_configs(
+    args: &Args,
+    rules: &config::rules::Rules,
+) -> anyhow::Result<Vec<config::rules::OutputConfig>> {
+    use config::rules::{OutputConfig, SinkConfig};
+
+    let json = || {
+        OutputConfig::new(SinkConfig::Json {
+            path: args.output_file.clone(),
+            format: args.json_format,
+        })
+    };
+    let mut configs = if args.output.is_some() || args.output_file.is_some() {
+        match args.output.as_deref().unwrap_or("console") {
+            "console" if args.output_file.is_some() => vec![OutputConfig::console(), json()],
+            "console" => vec![OutputConfig::console()],
+            "json" => vec![json()],
+            "both" => vec![OutputConfig::console(), json()],
+            other => {
+                anyhow::bail!("unknown output format \"{other}\" (expected console, json or both)")
+            }
+        }
+    } else if let Some(path) = &args.outputs {
+        config::rules::load_outputs_file(path)?
+    } else if !rules.outputs.is_empty() {
+        rules.outputs.clone()
+    } else {
+        vec![OutputConfig::console()]
+    };
+
+    for config in &mut configs {
+        if let SinkConfig::Console { all_matches } = &mut config.sink {
+            *all_matches |= args.all_matches;
+            if let Some(severity) = &args.severity {
+                config.min_severity = Some(
+                    output::Severity::from(severity.as_str())
+                        .as_str()
+                        .to_string(),
+                );
+            }
+        }
+    }
+    Ok(configs)
 }
 
 #[tokio::main]
@@ -62,25 +159,62 @@ async fn main() -> anyhow::Result<()> {
         println!("Arguments: {:#?}", args);
     }
 
-    let min_severity = min_severity_filter(&args);
+    let rules_path = resolve_rules_path(&args);
 
-    let rules_path = if let Some(path) = args.rules {
-        path
-    } else if let Some(config_dir) = dirs::config_dir() {
-        let config_path = config_dir.join("rusthound").join("rules.toml");
-        if config_path.exists() {
-            config_path
-        } else {
-            PathBuf::from("rules.toml")
+    match &args.command {
+        Some(Command::Validate { files }) => {
+            let files = files_or_default(files, rules_path);
+            validate_rule_files(&files, true)?;
+            for file in &files {
+                println!("{}: OK", file.display());
+            }
+            return Ok(());
         }
-    } else {
-        PathBuf::from("rules.toml")
-    };
+        Some(Command::Test { files }) => {
+            return test_rule_files(&files_or_default(files, rules_path));
+        }
+        Some(Command::ImportSigma { files, output }) => {
+            return import_sigma_rules(files, output.as_deref());
+        }
+        Some(Command::Schema) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&output::schema::json_schema())?
+            );
+            return Ok(());
+        }
+        None => {}
+    }
 
-    let rules = config::rules::load_rules_from_file(&rules_path)
+    validate_rule_files(std::slice::from_ref(&rules_path), false)?;
+    let rules = config::rules::load_layered_rules(&rules_path)
         .with_context(|| format!("Failed to load rules from {:?}", rules_path))?;
 
+    if args.verbose {
+        print_rule_sources(&rules);
+    }
+
     let pattern_matcher = analyzer::pattern_matcher::PatternMatcher::new(&rules)?;
+    let shared_state = std::sync::Arc::new(watcher::log_reader::SharedState::new(
+        &rules,
+        pattern_matcher.networks(),
+    )?);
+    let mut outputs =
+        output::sink::Outputs::from_config(&output_configs(&args, &rules)?, args.follow)?;
+    if let Some(path) = &args.report {
+        // The report lists what the console would show
+        let filter = output::sink::SinkFilter {
+            min_severity: args.severity.as_deref().map(output::Severity::from),
+            ..Default::default()
+        };
+        let report = output::report::ReportSink::create(path, !args.all_matches)?;
+        outputs.add("report", filter, Box::new(report));
+    }
+
+    if let Some(address) = &args.metrics_addr {
+        let bound = metrics::serve(address, metrics::global())?;
+        eprintln!("Serving metrics at http://{bound}/metrics");
+    }
 
     if let Some(file_path) = args.file {
         if args.follow {
@@ -90,50 +224,73 @@ async fn main() -> anyhow::Result<()> {
             let mut scan_state = watcher::log_reader::ScanState::new(
                 &rules.frequency_rules,
                 &rules.correlated_rules,
-            );
-            let mut current_offset = 0;
-            let mut current_line_number = 0;
-
-            let (offset, line_number, mut detections) = watcher::log_reader::read_file_from_offset(
-                &file_path,
-                &pattern_matcher,
-                &args.output,
-                &rules.frequency_rules,
-                &mut scan_state,
-                current_offset,
-                current_line_number,
-            )
-            .await?;
-            current_offset = offset;
-            current_line_number = line_number;
-            output::console::display_detections(&mut detections, min_severity);
-
-            while rx.recv().await.is_some() {
-                let (offset, line_number, mut new_detections) =
-                    watcher::log_reader::read_file_from_offset(
+                pattern_matcher.networks(),
+                &shared_state,
+            )?;
+            // Learned state is flushed even when reading the file fails
+            let followed = async {
+                let mut current_offset = 0;
+                let mut current_line_number = 0;
+
+                let (offset, line_number) = watcher::log_reader::read_file_from_offset(
+                    &file_path,
+                    &pattern_matcher,
+                    &outputs,
+                    &rules.frequency_rules,
+                    &mut scan_state,
+                    current_offset,
+                    current_line_number,
+                )
+                .await?;
+                current_offset = offset;
+                current_line_number = line_number;
+                metrics::global().set_offset(&file_path, current_offset);
+                outputs.flush();
+
+                let shutdown = shutdown_signal();
+                tokio::pin!(shutdown);
+                loop {
+                    let changed = tokio::select! {
+                        changed = rx.recv() => changed,
+                        () = &mut shutdown => None,
+                    };
+                    if changed.is_none() {
+                        break;
+                    }
+                    let (offset, line_number) = watcher::log_reader::read_file_from_offset(
                         &file_path,
                         &pattern_matcher,
-                        &args.output,
+                        &outputs,
                         &rules.frequency_rules,
                         &mut scan_state,
                         current_offset,
                         current_line_number,
                     )
                     .await?;
-                current_offset = offset;
-                current_line_number = line_number;
-                output::console::display_detections(&mut new_detections, min_severity);
+                    current_offset = offset;
+                    current_line_number = line_number;
+                    metrics::global().set_offset(&file_path, current_offset);
+                    outputs.flush();
+                }
+                Ok::<(), anyhow::Error>(())
             }
+            .await;
+            shared_state.flush();
+            outputs.flush();
+            followed?;
         } else {
-            let (mut detections, _) = watcher::log_reader::read_file_line_by_line(
+            let scan_state = watcher::log_reader::read_file_line_by_line(
                 &file_path,
                 &pattern_matcher,
-                &args.output,
+                &outputs,
                 &rules.frequency_rules,
                 &rules.correlated_rules,
+                &shared_state,
             )
             .await?;
-            output::console::display_detections(&mut detections, min_severity);
+            outputs.finish(&output::sink::RunSummary {
+                suppressed: scan_state.suppressed,
+            })?;
         }
     } else if let Some(dir_path) = args.dir {
         let log_files = watcher::log_reader::find_log_files(&dir_path)?;
@@ -152,6 +309,14 @@ async fn main() -> anyhow::Result<()> {
             }
         }
 
+        let mut scan_options = watcher::parallel_scan::ScanOptions::default();
+        if let Some(jobs) = args.jobs {
+            scan_options.workers = jobs.get();
+        }
+        if args.verbose {
+            println!("Scanning with {} worker thread(s)", scan_options.workers);
+        }
+
         if args.follow {
             println!("Monitoring {} log files in real-time...", log_files.len());
             let (tx, mut rx) = tokio::sync::mpsc::channel(100);
@@ -163,6 +328,7 @@ async fn main() -> anyhow::Result<()> {
                     if let Err(e) =
                         watcher::file_watcher::watch_file(file_path_clone, tx_clone).await
                     {
+                        metrics::global().watcher_error();
                         eprintln!("Error watching file: {e}");
                     }
                 });
@@ -173,77 +339,266 @@ async fn main() -> anyhow::Result<()> {
                 (u64, usize, watcher::log_reader::ScanState),
             > = std::collections::HashMap::new();
 
-            for file_path in &log_files {
-                let mut scan_state = watcher::log_reader::ScanState::new(
-                    &rules.frequency_rules,
-                    &rules.correlated_rules,
-                );
-                let (offset, line_number, mut detections) =
-                    watcher::log_reader::read_file_from_offset(
-                        file_path,
-                        &pattern_matcher,
-                        &args.output,
-                        &rules.frequency_rules,
-                        &mut scan_state,
-                        0,
-                        0,
-                    )
-                    .await?;
-                file_states.insert(file_path.clone(), (offset, line_number, scan_state));
-                output::console::display_detections(&mut detections, min_severity);
+            for scan in watcher::parallel_scan::scan_files(
+                &log_files,
+                &pattern_matcher,
+                &outputs,
+                &rules.frequency_rules,
+                &rules.correlated_rules,
+                &shared_state,
+                scan_options,
+            )? {
+                metrics::global().set_offset(&scan.path, scan.offset);
+                file_states.insert(scan.path, (scan.offset, scan.line_number, scan.scan_state));
             }
-
-            while let Some(changed_file) = rx.recv().await {
-                if let Some((current_offset, current_line_number, mut scan_state)) =
-                    file_states.remove(&changed_file)
-                {
-                    let (new_offset, new_line_number, mut new_detections) =
-                        watcher::log_reader::read_file_from_offset(
-                            &changed_file,
-                            &pattern_matcher,
-                            &args.output,
-                            &rules.frequency_rules,
-                            &mut scan_state,
-                            current_offset,
-                            current_line_number,
-                        )
-                        .await?;
-                    file_states.insert(changed_file, (new_offset, new_line_number, scan_state));
-                    output::console::display_detections(&mut new_detections, min_severity);
+            outputs.flush();
+
+            // Learned state is flushed even when reading a file fails
+            let followed = async {
+                let shutdown = shutdown_signal();
+                tokio::pin!(shutdown);
+                loop {
+                    let changed_file = tokio::select! {
+                        changed = rx.recv() => changed,
+                        () = &mut shutdown => None,
+                    };
+                    let Some(changed_file) = changed_file else {
+                        break;
+                    };
+                    if let Some((current_offset, current_line_number, mut scan_state)) =
+                        file_states.remove(&changed_file)
+                    {
+                        let (new_offset, new_line_number) =
+                            watcher::log_reader::read_file_from_offset(
+                                &changed_file,
+                                &pattern_matcher,
+                                &outputs,
+                                &rules.frequency_rules,
+                                &mut scan_state,
+                                current_offset,
+                                current_line_number,
+                            )
+                            .await?;
+                        metrics::global().set_offset(&changed_file, new_offset);
+                        file_states.insert(changed_file, (new_offset, new_line_number, scan_state));
+                        outputs.flush();
+                    }
                 }
+                Ok::<(), anyhow::Error>(())
             }
+            .await;
+            shared_state.flush();
+            outputs.flush();
+            followed?;
         } else {
-            let mut all_detections = Vec::new();
-
-            for file_path in &log_files {
-                if args.verbose {
-                    println!("Processing file: {}", file_path.display());
+            let mut suppressed = std::collections::HashMap::new();
+            for scan in watcher::parallel_scan::scan_files(
+                &log_files,
+                &pattern_matcher,
+                &outputs,
+                &rules.frequency_rules,
+                &rules.correlated_rules,
+                &shared_state,
+                scan_options,
+            )? {
+                for (rule_name, count) in scan.scan_state.suppressed {
+                    *suppressed.entry(rule_name).or_default() += count;
                 }
-                let (mut detections, _) = watcher::log_reader::read_file_line_by_line(
-                    file_path,
-                    &pattern_matcher,
-                    &args.output,
-                    &rules.frequency_rules,
-                    &rules.correlated_rules,
-                )
-                .await?;
-                all_detections.append(&mut detections);
             }
-            output::console::display_detections(&mut all_detections, min_severity);
+            outputs.finish(&output::sink::RunSummary { suppressed })?;
         }
     } else if args.file.is_none() && args.dir.is_none() {
         let default_file_path = PathBuf::from("sample.log");
-        let (mut detections, _) = watcher::log_reader::read_file_line_by_line(
+        let scan_state = watcher::log_reader::read_file_line_by_line(
             &default_file_path,
             &pattern_matcher,
-            &args.output,
+            &outputs,
             &rules.frequency_rules,
             &rules.correlated_rules,
+            &shared_state,
         )
         .await?;
-        output::console::display_detections(&mut detections, min_severity);
+        outputs.finish(&output::sink::RunSummary {
+            suppressed: scan_state.suppressed,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Resolves on Ctrl-C or SIGTERM, so follow mode can save learned state
+/// before exiting.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => eprintln!("Cannot listen for SIGTERM: {e}"),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        eprintln!("Cannot listen for Ctrl-C: {e}");
+        std::future::pending::<()>().await;
+    }
+}
+
+fn resolve_rules_path(args: &Args) -> PathBuf {
+    if let Some(path) = &args.rules {
+        path.clone()
+    } else if let Some(config_dir) = dirs::config_dir() {
+        let config_path = config_dir.join("rusthound").join("rules.toml");
+        if config_path.exists() {
+            config_path
+        } else {
+            PathBuf::from("rules.toml")
+        }
+    } else {
+        PathBuf::from("rules.toml")
+    }
+}
+
+fn files_or_default(files: &[PathBuf], default: PathBuf) -> Vec<PathBuf> {
+    if files.is_empty() {
+        vec![default]
+    } else {
+        files.to_vec()
     }
+}
 
+/// Print every problem found in the given rule files, failing if there are any.
+/// IOC lists and GeoIP databases are only opened with `open_files`; elsewhere
+/// the matcher opens them and reports their errors itself.
+fn validate_rule_files(files: &[PathBuf], open_files: bool) -> anyhow::Result<()> {
+    let mut issue_count = 0;
+    for file in files {
+        let issues = config::validate::validate_rules_file(file, open_files)
+            .with_context(|| format!("Failed to read rules from {:?}", file))?;
+        for issue in &issues {
+            eprintln!("{issue}");
+        }
+        issue_count += issues.len();
+    }
+
+    if issue_count > 0 {
+        anyhow::bail!("{issue_count} problem(s) found in rule files");
+    }
+    Ok(())
+}
+
+fn print_rule_sources(rules: &config::rules::Rules) {
+    let source_of = |source: &Option<PathBuf>| {
+        source
+            .as_ref()
+            .map_or_else(|| "<unknown>".to_string(), |p| p.display().to_string())
+    };
+    for rule in &rules.regex_rules {
+        println!("Rule {:?} from {}", rule.name, source_of(&rule.source));
+    }
+    for rule in &rules.correlated_rules {
+        println!(
+            "Correlated rule {:?} from {}",
+            rule.name,
+            source_of(&rule.source)
+        );
+    }
+    for rule in &rules.suppress_rules {
+        let expiry = rule
+            .expires
+            .as_ref()
+            .map_or_else(String::new, |expires| format!(" (expires {expires})"));
+        println!(
+            "Suppress rule {:?} from {}{expiry}",
+            rule.name,
+            source_of(&rule.source)
+        );
+    }
+    for list in &rules.ioc_lists {
+        println!(
+            "IOC list {:?} ({}) from {}",
+            list.name,
+            list.path.display(),
+            source_of(&list.source)
+        );
+    }
+    for rule in &rules.travel_rules {
+        println!(
+            "Travel rule {:?} from {}",
+            rule.name,
+            source_of(&rule.source)
+        );
+    }
+    for rule in &rules.new_value_rules {
+        println!(
+            "New value rule {:?} from {}",
+            rule.name,
+            source_of(&rule.source)
+        );
+    }
+}
+
+/// Run inline rule tests for each file, failing if any case fails
+fn test_rule_files(files: &[PathBuf]) -> anyhow::Result<()> {
+    validate_rule_files(files, false)?;
+
+    let mut failed = 0;
+    for file in files {
+        let rules = config::rules::load_layered_rules(file)
+            .with_context(|| format!("Failed to load rules from {:?}", file))?;
+        println!("Testing rules in {}", file.display());
+        let results = analyzer::rule_tests::run_rule_tests(&rules)?;
+        output::console::display_rule_test_results(&results);
+        failed += results.iter().filter(|r| !r.passed).count();
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{failed} rule test(s) failed");
+    }
+    Ok(())
+}
+
+/// Convert Sigma rules, printing approximations and skipped constructs to stderr
+fn import_sigma_rules(files: &[PathBuf], output: Option<&std::path::Path>) -> anyhow::Result<()> {
+    let mut conversion = config::sigma::SigmaConversion::default();
+    for file in files {
+        conversion
+            .add_file(file)
+            .with_context(|| format!("Failed to read Sigma rules from {:?}", file))?;
+    }
+
+    for warning in &conversion.warnings {
+        eprintln!("warning: {warning}");
+    }
+    for unsupported in &conversion.unsupported {
+        eprintln!("unsupported: {unsupported}");
+    }
+
+    let sources: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+    let toml_output = format!(
+        "# Converted from Sigma rules: {}\n{}",
+        sources.join(", "),
+        conversion.to_toml()?
+    );
+    match output {
+        Some(path) => {
+            std::fs::write(path, &toml_output)?;
+            println!("Converted rules written to: {}", path.display());
+        }
+        None => print!("{toml_output}"),
+    }
+
+    if !conversion.unsupported.is_empty() {
+        anyhow::bail!(
+            "{} Sigma rule(s) could not be converted",
+            conversion.unsupported.len()
+        );
+    }
     Ok(())
 }
 
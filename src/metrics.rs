@@ -0,0 +1,288 @@
+use crate::output::Severity;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+static GLOBAL: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// The counters of this process, updated by the readers and exposed by
+/// `serve`. Counting is cheap and always on; only the endpoint is optional.
+pub fn global() -> &'static Metrics {
+    &GLOBAL
+}
+
+/// Counters of what RustHound read and found, in Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    lines_read: AtomicU64,
+    bytes_read: AtomicU64,
+    parse_errors: AtomicU64,
+    watcher_errors: AtomicU64,
+    detections: Mutex<BTreeMap<(String, Severity), u64>>,
+    suppressed: Mutex<BTreeMap<String, u64>>,
+    output_errors: Mutex<BTreeMap<String, u64>>,
+    /// Read offset of every followed file, for the read lag
+    offsets: Mutex<BTreeMap<PathBuf, u64>>,
+}
+
+impl Metrics {
+    pub fn lines_read(&self, lines: u64, bytes: u64) {
+        self.lines_read.fetch_add(lines, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// A line that was not valid UTF-8 and was read with replacement
+    /// characters.
+    pub fn parse_error(&self) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn watcher_error(&self) {
+        self.watcher_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn detection(&self, rule: &str, severity: Severity) {
+        *lock(&self.detections)
+            .entry((rule.to_string(), severity))
+            .or_default() += 1;
+    }
+
+    pub fn suppressed(&self, suppress_rule: &str) {
+        *lock(&self.suppressed)
+            .entry(suppress_rule.to_string())
+            .or_default() += 1;
+    }
+
+    /// Writing to or flushing the output named `output` failed.
+    pub fn output_error(&self, output: &str) {
+        *lock(&self.output_errors)
+            .entry(output.to_string())
+            .or_default() += 1;
+    }
+
+    /// `path` has been read up to `offset`; its lag is the file size beyond.
+    pub fn set_offset(&self, path: &Path, offset: u64) {
+        lock(&self.offsets).insert(path.to_path_buf(), offset);
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, value: &AtomicU64| {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        };
+        counter(
+            "rusthound_lines_read_total",
+            "Log lines read.",
+            &self.lines_read,
+        );
+        counter(
+            "rusthound_bytes_read_total",
+            "Log bytes read.",
+            &self.bytes_read,
+        );
+        counter(
+            "rusthound_parse_errors_total",
+            "Lines that were not valid UTF-8.",
+            &self.parse_errors,
+        );
+        counter(
+            "rusthound_watcher_errors_total",
+            "Errors reported by the file watcher.",
+            &self.watcher_errors,
+        );
+
+        header(
+            &mut out,
+            "rusthound_detections_total",
+            "Detections sent to the outputs, by rule and severity.",
+            "counter",
+        );
+        for ((rule, severity), count) in lock(&self.detections).iter() {
+            let _ = writeln!(
+                out,
+                "rusthound_detections_total{{rule=\"{}\",severity=\"{}\"}} {count}",
+                label(rule),
+                severity.as_str()
+            );
+        }
+
+        header(
+            &mut out,
+            "rusthound_suppressed_total",
+            "Detections dropped by suppress rules, by suppress rule.",
+            "counter",
+        );
+        for (rule, count) in lock(&self.suppressed).iter() {
+            let _ = writeln!(
+                out,
+                "rusthound_suppressed_total{{rule=\"{}\"}} {count}",
+                label(rule)
+            );
+        }
+
+        header(
+            &mut out,
+            "rusthound_output_errors_total",
+            "Failed writes and flushes, by output.",
+            "counter",
+        );
+        for (output, count) in lock(&self.output_errors).iter() {
+            let _ = writeln!(
+                out,
+                "rusthound_output_errors_total{{output=\"{}\"}} {count}",
+                label(output)
+            );
+        }
+
+        header(
+            &mut out,
+            "rusthound_read_lag_bytes",
+            "Bytes of a followed file not read yet (file size minus read offset).",
+            "gauge",
+        );
+        for (path, offset) in lock(&self.offsets).iter() {
+            // A file that was truncated or removed has nothing left to read
+            let size = std::fs::metadata(path).map_or(*offset, |m| m.len());
+            let _ = writeln!(
+                out,
+                "rusthound_read_lag_bytes{{file=\"{}\"}} {}",
+                label(&path.display().to_string()),
+                size.saturating_sub(*offset)
+            );
+        }
+        out
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+/// Escape a label value.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve `metrics` at `http://<address>/metrics` from a background thread.
+/// Returns the bound address, which tells the port when `address` uses 0.
+pub fn serve(address: &str, metrics: &'static Metrics) -> anyhow::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("cannot listen for metrics on {address}"))?;
+    let bound = listener.local_addr()?;
+    std::thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = respond(stream, metrics) {
+                    eprintln!("metrics request failed: {e}");
+                }
+            }
+        })
+        .context("cannot start metrics thread")?;
+    Ok(bound)
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 16 * 1024 {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("");
+    let path = request_line.next().unwrap_or("");
+    let (status, content_type, body) = match (method, path.split('?').next()) {
+        ("GET", Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            metrics.render(),
+        ),
+        ("GET", _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrape(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_counters_and_read_lag_over_http() {
+        let scratch = tempfile::tempdir().unwrap();
+        let log = scratch.path().join("metrics.log");
+        std::fs::write(&log, "0123456789").unwrap();
+
+        let metrics: &'static Metrics = Box::leak(Box::default());
+        metrics.lines_read(3, 120);
+        metrics.parse_error();
+        metrics.watcher_error();
+        metrics.detection("ssh \"root\"", Severity::High);
+        metrics.detection("ssh \"root\"", Severity::High);
+        metrics.detection("oom", Severity::Critical);
+        metrics.suppressed("health_checks");
+        metrics.output_error("siem");
+        metrics.set_offset(&log, 4);
+
+        let address = serve("127.0.0.1:0", metrics).unwrap();
+        let response = scrape(address, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        for line in [
+            "rusthound_lines_read_total 3",
+            "rusthound_bytes_read_total 120",
+            "rusthound_parse_errors_total 1",
+            "rusthound_watcher_errors_total 1",
+            "# TYPE rusthound_detections_total counter",
+            "rusthound_detections_total{rule=\"oom\",severity=\"critical\"} 1",
+            "rusthound_detections_total{rule=\"ssh \\\"root\\\"\",severity=\"high\"} 2",
+            "rusthound_suppressed_total{rule=\"health_checks\"} 1",
+            "rusthound_output_errors_total{output=\"siem\"} 1",
+            "# TYPE rusthound_read_lag_bytes gauge",
+        ] {
+            assert!(response.lines().any(|l| l == line), "{line} in {response}");
+        }
+        let lag = format!("rusthound_read_lag_bytes{{file=\"{}\"}} 6", log.display());
+        assert!(response.lines().any(|l| l == lag), "{response}");
+
+        assert!(scrape(address, "/").starts_with("HTTP/1.1 404"));
+    }
+}
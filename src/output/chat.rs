@@ -0,0 +1,344 @@
+use super::schema::DetectionRecord;
+use super::Severity;
+use crate::config::rules::ChatFormat;
+use serde_json::{json, Value};
+
+/// Longest matched line shown in a chat message; chat services cap message
+/// sizes and long lines are unreadable there anyway.
+const MAX_LINE_CHARS: usize = 500;
+const MAX_DESCRIPTION_CHARS: usize = 300;
+
+impl ChatFormat {
+    /// Most detections one message may carry; larger batches are split.
+    pub fn max_batch(self) -> usize {
+        match self {
+            ChatFormat::Slack | ChatFormat::Teams | ChatFormat::TeamsMessageCard => 10,
+            // Discord allows 10 embeds but only 6000 characters in total
+            ChatFormat::Discord => 5,
+        }
+    }
+
+    pub fn render(self, records: &[DetectionRecord]) -> Value {
+        match self {
+            ChatFormat::Slack => slack(records),
+            ChatFormat::Teams => teams(records),
+            ChatFormat::TeamsMessageCard => message_card(records),
+            ChatFormat::Discord => discord(records),
+        }
+    }
+}
+
+/// RGB color of a severity, matching the console colors.
+pub fn severity_color(severity: Severity) -> u32 {
+    match severity {
+        Severity::Critical => 0xB7_1C_1C,
+        Severity::High => 0xE5_39_35,
+        Severity::Error => 0xF4_51_1E,
+        Severity::Warning => 0xFB_C0_2D,
+        Severity::Info => 0x1E_88_E5,
+    }
+}
+
+fn hex_color(severity: Severity) -> String {
+    format!("{:06X}", severity_color(severity))
+}
+
+fn title(record: &DetectionRecord) -> String {
+    format!(
+        "{}: {}",
+        record.severity.as_str().to_uppercase(),
+        record.rule_id
+    )
+}
+
+fn summary(records: &[DetectionRecord]) -> String {
+    match records {
+        [record] => format!("RustHound on {}: {}", record.host, title(record)),
+        _ => format!(
+            "RustHound on {}: {} detections",
+            records.first().map_or("", |record| record.host.as_str()),
+            records.len()
+        ),
+    }
+}
+
+/// The most severe record decides the color of a whole message.
+fn worst(records: &[DetectionRecord]) -> Severity {
+    records
+        .iter()
+        .map(|record| record.severity)
+        .max_by_key(Severity::rank)
+        .unwrap_or(Severity::Info)
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// A Markdown code block that the line cannot break out of.
+fn code_block(line: &str) -> String {
+    format!(
+        "```\n{}\n```",
+        truncate(line, MAX_LINE_CHARS).replace("```", "`\u{200b}``")
+    )
+}
+
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn slack(records: &[DetectionRecord]) -> Value {
+    let attachments: Vec<Value> = records
+        .iter()
+        .map(|record| {
+            let mrkdwn = |text: String| json!({ "type": "mrkdwn", "text": text });
+            json!({
+                "color": format!("#{}", hex_color(record.severity)),
+                "fallback": title(record),
+                "blocks": [
+                    {
+                        "type": "section",
+                        "text": mrkdwn(format!(
+                            "*{}*\n{}",
+                            slack_escape(&title(record)),
+                            slack_escape(&truncate(&record.description, MAX_DESCRIPTION_CHARS))
+                        )),
+                    },
+                    {
+                        "type": "section",
+                        "fields": [
+                            mrkdwn(format!("*File*\n{}", slack_escape(&record.source))),
+                            mrkdwn(format!("*Line*\n{}", record.line_number)),
+                            mrkdwn(format!("*Host*\n{}", slack_escape(&record.host))),
+                            mrkdwn(format!("*Severity*\n{}", record.severity.as_str())),
+                        ],
+                    },
+                    {
+                        "type": "section",
+                        "text": mrkdwn(slack_escape(&code_block(&record.matched_line))),
+                    },
+                ],
+            })
+        })
+        .collect();
+    json!({ "text": summary(records), "attachments": attachments })
+}
+
+/// Facts of a Teams card, whose two card formats name the label key differently.
+fn facts(record: &DetectionRecord, label_key: &str) -> Vec<Value> {
+    [
+        ("Rule", record.rule_id.clone()),
+        ("Severity", record.severity.as_str().to_string()),
+        ("File", record.source.clone()),
+        ("Line", record.line_number.to_string()),
+        ("Host", record.host.clone()),
+    ]
+    .into_iter()
+    .map(|(label, value)| json!({ (label_key): label, "value": value }))
+    .collect()
+}
+
+fn teams(records: &[DetectionRecord]) -> Value {
+    let body: Vec<Value> = records
+        .iter()
+        .map(|record| {
+            // Adaptive Cards have no free colors, only these styles
+            let (style, color) = match record.severity {
+                Severity::Critical | Severity::High => ("attention", "Attention"),
+                Severity::Error | Severity::Warning => ("warning", "Warning"),
+                Severity::Info => ("accent", "Accent"),
+            };
+            json!({
+                "type": "Container",
+                "style": style,
+                "separator": true,
+                "items": [
+                    {
+                        "type": "TextBlock",
+                        "text": title(record),
+                        "weight": "Bolder",
+                        "color": color,
+                        "wrap": true,
+                    },
+                    {
+                        "type": "TextBlock",
+                        "text": truncate(&record.description, MAX_DESCRIPTION_CHARS),
+                        "wrap": true,
+                    },
+                    { "type": "FactSet", "facts": facts(record, "title") },
+                    {
+                        "type": "TextBlock",
+                        "text": truncate(&record.matched_line, MAX_LINE_CHARS),
+                        "fontType": "Monospace",
+                        "wrap": true,
+                    },
+                ],
+            })
+        })
+        .collect();
+    json!({
+        "type": "message",
+        "summary": summary(records),
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "msteams": { "width": "Full" },
+                "body": body,
+            },
+        }],
+    })
+}
+
+fn html_escape(text: &str) -> String {
+    slack_escape(text).replace('"', "&quot;")
+}
+
+fn message_card(records: &[DetectionRecord]) -> Value {
+    let sections: Vec<Value> = records
+        .iter()
+        .map(|record| {
+            json!({
+                "activityTitle": html_escape(&title(record)),
+                "activitySubtitle": html_escape(&truncate(&record.description, MAX_DESCRIPTION_CHARS)),
+                "facts": facts(record, "name"),
+                "text": format!(
+                    "<pre>{}</pre>",
+                    html_escape(&truncate(&record.matched_line, MAX_LINE_CHARS))
+                ),
+            })
+        })
+        .collect();
+    json!({
+        "@type": "MessageCard",
+        "@context": "https://schema.org/extensions",
+        "themeColor": hex_color(worst(records)),
+        "summary": summary(records),
+        "title": summary(records),
+        "sections": sections,
+    })
+}
+
+fn discord(records: &[DetectionRecord]) -> Value {
+    let embeds: Vec<Value> = records
+        .iter()
+        .map(|record| {
+            let field = |name: &str, value: String| {
+                json!({ "name": name, "value": truncate(&value, 200), "inline": true })
+            };
+            json!({
+                "title": truncate(&title(record), 250),
+                "description": format!(
+                    "{}\n{}",
+                    truncate(&record.description, MAX_DESCRIPTION_CHARS),
+                    code_block(&record.matched_line)
+                ),
+                "color": severity_color(record.severity),
+                "fields": [
+                    field("File", record.source.clone()),
+                    field("Line", record.line_number.to_string()),
+                    field("Host", record.host.clone()),
+                ],
+                "timestamp": record.event_time.unwrap_or(record.ingest_time).to_rfc3339(),
+            })
+        })
+        .collect();
+    json!({
+        "username": "RustHound",
+        "content": summary(records),
+        // Never ping @everyone or roles from a log line
+        "allowed_mentions": { "parse": [] },
+        "embeds": embeds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::console::create_detection;
+    use std::path::Path;
+
+    fn records() -> Vec<DetectionRecord> {
+        [("warning", "disk_full"), ("critical", "root_login")]
+            .into_iter()
+            .map(|(severity, rule)| {
+                let detection = create_detection(
+                    severity,
+                    Path::new("/var/log/auth.log"),
+                    42,
+                    "sshd: <root> & ```x```",
+                    rule,
+                );
+                DetectionRecord::new(&detection, "web1")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slack_colors_and_escapes_each_detection() {
+        let payload = ChatFormat::Slack.render(&records());
+        assert_eq!(payload["text"], "RustHound on web1: 2 detections");
+        let attachment = &payload["attachments"][1];
+        assert_eq!(attachment["color"], "#B71C1C");
+        assert_eq!(
+            attachment["blocks"][0]["text"]["text"],
+            "*CRITICAL: root_login*\nLine matched rule \"root_login\""
+        );
+        assert_eq!(
+            attachment["blocks"][1]["fields"][0]["text"],
+            "*File*\n/var/log/auth.log"
+        );
+        assert_eq!(attachment["blocks"][1]["fields"][1]["text"], "*Line*\n42");
+        assert_eq!(
+            attachment["blocks"][2]["text"]["text"],
+            "```\nsshd: &lt;root&gt; &amp; `\u{200b}``x`\u{200b}``\n```"
+        );
+    }
+
+    #[test]
+    fn teams_cards_use_severity_styles_and_facts() {
+        let payload = ChatFormat::Teams.render(&records());
+        let card = &payload["attachments"][0]["content"];
+        assert_eq!(card["type"], "AdaptiveCard");
+        assert_eq!(card["body"][0]["style"], "warning");
+        assert_eq!(card["body"][1]["style"], "attention");
+        let facts = &card["body"][1]["items"][2]["facts"];
+        assert_eq!(
+            facts[2],
+            json!({ "title": "File", "value": "/var/log/auth.log" })
+        );
+        assert_eq!(facts[3], json!({ "title": "Line", "value": "42" }));
+
+        let payload = ChatFormat::TeamsMessageCard.render(&records());
+        assert_eq!(payload["themeColor"], "B71C1C");
+        assert_eq!(
+            payload["sections"][0]["text"],
+            "<pre>sshd: &lt;root&gt; &amp; ```x```</pre>"
+        );
+    }
+
+    #[test]
+    fn discord_embeds_carry_color_and_location() {
+        let mut records = records();
+        records[0].matched_line = "x".repeat(2000);
+        let payload = ChatFormat::Discord.render(&records);
+        assert_eq!(payload["allowed_mentions"]["parse"], json!([]));
+        let embed = &payload["embeds"][1];
+        assert_eq!(embed["title"], "CRITICAL: root_login");
+        assert_eq!(embed["color"], 0xB71C1C);
+        assert_eq!(
+            embed["fields"][1],
+            json!({ "name": "Line", "value": "42", "inline": true })
+        );
+        let long = payload["embeds"][0]["description"].as_str().unwrap();
+        assert!(long.chars().count() < MAX_LINE_CHARS + MAX_DESCRIPTION_CHARS + 20);
+        assert!(long.contains('…'));
+    }
+}
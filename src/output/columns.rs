@@ -0,0 +1,94 @@
+use super::schema::DetectionRecord;
+use std::collections::BTreeMap;
+
+/// `DetectionRecord` fields every tabular output starts with, in order.
+pub const RECORD_COLUMNS: [&str; 13] = [
+    "schema_version",
+    "id",
+    "event_time",
+    "ingest_time",
+    "host",
+    "source",
+    "rule_id",
+    "rule_type",
+    "severity",
+    "description",
+    "line_number",
+    "line_numbers",
+    "matched_line",
+];
+
+/// Column of the fields that have no column of their own.
+pub const OTHER_FIELDS_COLUMN: &str = "fields";
+
+/// Column layout shared by the CSV and Parquet outputs: `RECORD_COLUMNS`,
+/// one `fields.<name>` column per configured field, then `fields` holding
+/// every other field as a JSON object.
+///
+/// The layout depends only on the configuration, never on the detections,
+/// so every run of an output writes the same header.
+#[derive(Debug, Clone)]
+pub struct Columns {
+    fields: Vec<String>,
+}
+
+impl Columns {
+    pub fn new(fields: &[String]) -> Self {
+        let mut unique = Vec::new();
+        for field in fields {
+            if !unique.contains(field) {
+                unique.push(field.clone());
+            }
+        }
+        Columns { fields: unique }
+    }
+
+    /// Every column name, in order.
+    pub fn names(&self) -> Vec<String> {
+        RECORD_COLUMNS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(self.fields.iter().map(|field| format!("fields.{field}")))
+            .chain([OTHER_FIELDS_COLUMN.to_string()])
+            .collect()
+    }
+
+    /// Names of the configured fields, without the `fields.` prefix.
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// The configured fields of `record`; None where it lacks one.
+    pub fn field_values<'r>(&self, record: &'r DetectionRecord) -> Vec<Option<&'r str>> {
+        self.fields
+            .iter()
+            .map(|field| record.fields.get(field).map(String::as_str))
+            .collect()
+    }
+
+    /// The fields of `record` without a column of their own as a JSON
+    /// object; None if there are none.
+    pub fn other_fields(&self, record: &DetectionRecord) -> Option<String> {
+        let others: BTreeMap<&str, &str> = record
+            .fields
+            .iter()
+            .filter(|(name, _)| !self.fields.contains(name))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        if others.is_empty() {
+            None
+        } else {
+            serde_json::to_string(&others).ok()
+        }
+    }
+}
+
+/// `line_numbers` as one cell: the numbers separated by spaces.
+pub fn line_numbers(record: &DetectionRecord) -> String {
+    record
+        .line_numbers
+        .iter()
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -1,13 +1,58 @@
-use super::{Detection, Severity};
+use super::sink::{OutputSink, RunSummary};
+use super::{Detection, DetectionKind, Severity};
+use crate::analyzer::ioc::IocMatch;
+use crate::analyzer::rule_tests::RuleTestResult;
 use colored::Colorize;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
-pub fn display_detections(detections: &mut Vec<Detection>, min_severity: Option<Severity>) {
-    if let Some(minimum) = min_severity {
-        detections.retain(|d| d.severity.meets_minimum(&minimum));
+/// Prints detections with `display_detections` at the end of every batch,
+/// so a batch is sorted by line and, unless `all_matches` is set, shows one
+/// pattern match per line.
+pub struct ConsoleSink {
+    collapse_matches: bool,
+    pending: Mutex<Vec<Detection>>,
+    shown: AtomicUsize,
+}
+
+impl ConsoleSink {
+    pub fn new(collapse_matches: bool) -> Self {
+        ConsoleSink {
+            collapse_matches,
+            pending: Mutex::new(Vec::new()),
+            shown: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl OutputSink for ConsoleSink {
+    fn write(&self, detection: &Detection) -> anyhow::Result<()> {
+        self.pending.lock().unwrap().push(detection.clone());
+        Ok(())
     }
 
-    detections.sort_by(|a, b| a.line_number.cmp(&b.line_number));
+    fn flush(&self) -> anyhow::Result<()> {
+        let mut detections = std::mem::take(&mut *self.pending.lock().unwrap());
+        display_detections(&mut detections, None, self.collapse_matches);
+        self.shown.fetch_add(detections.len(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn finish(&self, summary: &RunSummary) -> anyhow::Result<()> {
+        self.flush()?;
+        display_run_summary(self.shown.load(Ordering::Relaxed), &summary.suppressed);
+        Ok(())
+    }
+}
+
+pub fn display_detections(
+    detections: &mut Vec<Detection>,
+    min_severity: Option<Severity>,
+    collapse_matches: bool,
+) {
+    select_detections(detections, min_severity, collapse_matches);
 
     for detection in detections {
         let colored_severity = match detection.severity {
@@ -18,10 +63,27 @@ pub fn display_detections(detections: &mut Vec<Detection>, min_severity: Option<
             Severity::Info => "INFO".blue().bold(),
         };
 
+        let fields = if detection.fields.is_empty() {
+            String::new()
+        } else {
+            let pairs: Vec<String> = detection
+                .fields
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect();
+            format!(" [{}]", pairs.join(" "))
+        };
+
+        // Frequency detections are named after the rule they count
+        let name = match detection.kind {
+            DetectionKind::Frequency => &detection.description,
+            _ => &detection.pattern_name,
+        };
         println!(
-            "[{}] {} (Line: {}, File: {}): {}",
+            "[{}] {}{} (Line: {}, File: {}): {}",
             colored_severity,
-            detection.pattern_name.cyan(),
+            name.cyan(),
+            fields.dimmed(),
             detection.line_number.to_string().yellow(),
             detection.file_path.bright_magenta(),
             detection.matched_line
@@ -29,6 +91,88 @@ pub fn display_detections(detections: &mut Vec<Detection>, min_severity: Option<
     }
 }
 
+/// Filter, collapse and order detections the way `display_detections`
+/// shows them; reports use it to list the same detections.
+pub fn select_detections(
+    detections: &mut Vec<Detection>,
+    min_severity: Option<Severity>,
+    collapse_matches: bool,
+) {
+    if let Some(minimum) = min_severity {
+        detections.retain(|d| d.severity.meets_minimum(&minimum));
+    }
+
+    if collapse_matches {
+        collapse_pattern_matches(detections);
+    }
+
+    detections.sort_by_key(|d| d.line_number);
+}
+
+/// Print the totals of a finished scan: detections shown and detections
+/// dropped by suppress rules, broken down by suppress rule.
+pub fn display_run_summary(shown: usize, suppressed: &HashMap<String, usize>) {
+    let suppressed_total: usize = suppressed.values().sum();
+    println!("{} detection(s), {} suppressed", shown, suppressed_total);
+
+    let mut by_rule: Vec<_> = suppressed.iter().collect();
+    by_rule.sort();
+    for (rule_name, count) in by_rule {
+        println!("  suppressed by {}: {}", rule_name.cyan(), count);
+    }
+}
+
+/// Keep only the highest-severity pattern detection for each file line.
+/// Frequency and correlation detections are always kept.
+pub fn collapse_pattern_matches(detections: &mut Vec<Detection>) {
+    let mut best: HashMap<(&str, usize), (usize, u8)> = HashMap::new();
+    for (index, detection) in detections.iter().enumerate() {
+        if detection.kind != DetectionKind::Pattern {
+            continue;
+        }
+        let rank = detection.severity.rank();
+        best.entry((&detection.file_path, detection.line_number))
+            .and_modify(|(best_index, best_rank)| {
+                if rank > *best_rank {
+                    *best_index = index;
+                    *best_rank = rank;
+                }
+            })
+            .or_insert((index, rank));
+    }
+
+    let keep: HashSet<usize> = best.values().map(|(index, _)| *index).collect();
+    let mut index = 0;
+    detections.retain(|detection| {
+        let kept = detection.kind != DetectionKind::Pattern || keep.contains(&index);
+        index += 1;
+        kept
+    });
+}
+
+pub fn display_rule_test_results(results: &[RuleTestResult]) {
+    for result in results {
+        let status = if result.passed {
+            "PASS".green().bold()
+        } else {
+            "FAIL".red().bold()
+        };
+        match &result.detail {
+            Some(detail) => println!(
+                "[{}] {}: {} ({})",
+                status,
+                result.rule_name.cyan(),
+                result.case,
+                detail
+            ),
+            None => println!("[{}] {}: {}", status, result.rule_name.cyan(), result.case),
+        }
+    }
+
+    let failed = results.iter().filter(|r| !r.passed).count();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+}
+
 pub fn create_detection(
     severity: &str,
     file_path: &Path,
@@ -37,11 +181,47 @@ pub fn create_detection(
     rule_name: &str,
 ) -> Detection {
     Detection {
+        kind: DetectionKind::Pattern,
         severity: Severity::from(severity),
         file_path: file_path.to_string_lossy().to_string(),
         line_number,
         matched_line: matched_line.to_string(),
         pattern_name: rule_name.to_string(),
+        description: format!("Line matched rule \"{rule_name}\""),
+        related_lines: Vec::new(),
+        fields: BTreeMap::new(),
+    }
+}
+
+pub fn create_ioc_detection(
+    ioc_match: &IocMatch<'_>,
+    file_path: &Path,
+    line_number: usize,
+    matched_line: &str,
+) -> Detection {
+    let fields = BTreeMap::from([
+        ("ioc.list".to_string(), ioc_match.list.to_string()),
+        ("ioc.indicator".to_string(), ioc_match.indicator.to_string()),
+        (
+            "ioc.type".to_string(),
+            ioc_match.indicator.kind().to_string(),
+        ),
+    ]);
+    Detection {
+        kind: DetectionKind::Ioc,
+        severity: Severity::from(ioc_match.severity),
+        file_path: file_path.to_string_lossy().to_string(),
+        line_number,
+        matched_line: matched_line.to_string(),
+        pattern_name: ioc_match.list.to_string(),
+        description: format!(
+            "{} {} is listed in \"{}\"",
+            ioc_match.indicator.kind(),
+            ioc_match.indicator,
+            ioc_match.list
+        ),
+        related_lines: Vec::new(),
+        fields,
     }
 }
 
@@ -55,13 +235,59 @@ pub fn create_frequency_detection(
     matched_line: &str,
 ) -> Detection {
     Detection {
+        kind: DetectionKind::Frequency,
         severity: Severity::Warning, // Frequency detections can be warnings or higher depending on threshold
         file_path: file_path.to_string_lossy().to_string(),
         line_number,
         matched_line: matched_line.to_string(),
-        pattern_name: format!(
+        pattern_name: pattern_name.to_string(),
+        description: format!(
             "Too many \"{}\" errors ({} in {}s, threshold: {})",
             pattern_name, count, time_window_seconds, max_same_errors_per_minute
         ),
+        related_lines: Vec::new(),
+        fields: BTreeMap::from([
+            ("frequency.count".to_string(), count.to_string()),
+            (
+                "frequency.window_seconds".to_string(),
+                time_window_seconds.to_string(),
+            ),
+            (
+                "frequency.threshold".to_string(),
+                max_same_errors_per_minute.to_string(),
+            ),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapse_keeps_highest_severity_match_per_line() {
+        let path = Path::new("app.log");
+        let mut detections = vec![
+            create_detection("ERROR", path, 1, "line", "ERROR"),
+            create_detection("critical", path, 1, "line", "disk_io_error"),
+            create_frequency_detection("ERROR", 11, 10, 60, path, 1, "line"),
+            create_detection("high", path, 2, "other", "auth"),
+            create_detection("high", Path::new("other.log"), 1, "line", "auth"),
+        ];
+        collapse_pattern_matches(&mut detections);
+        let names: Vec<_> = detections
+            .iter()
+            .map(|d| (d.file_path.as_str(), d.line_number, d.kind))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("app.log", 1, DetectionKind::Pattern),
+                ("app.log", 1, DetectionKind::Frequency),
+                ("app.log", 2, DetectionKind::Pattern),
+                ("other.log", 1, DetectionKind::Pattern),
+            ]
+        );
+        assert_eq!(detections[0].pattern_name, "disk_io_error");
     }
 }
@@ -0,0 +1,188 @@
+use super::columns::{line_numbers, Columns};
+use super::schema::{host_name, DetectionRecord};
+use super::sink::{OutputSink, RunSummary};
+use super::Detection;
+use anyhow::Context;
+use chrono::SecondsFormat;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Detections as CSV rows with the columns of `Columns`, headed by a row of
+/// column names.
+///
+/// The file is truncated when the writer is created and flushed after every
+/// batch, so in follow mode it can be read while it grows.
+pub struct CsvWriter {
+    columns: Columns,
+    escape_formulas: bool,
+    host: String,
+    out: Mutex<csv::Writer<Box<dyn Write + Send>>>,
+}
+
+impl CsvWriter {
+    /// Write to `path`, or to stdout when `path` is `None` or `-`.
+    pub fn create(
+        path: Option<&Path>,
+        fields: &[String],
+        escape_formulas: bool,
+    ) -> anyhow::Result<Self> {
+        let out: Box<dyn Write + Send> = match path {
+            Some(path) if path != Path::new("-") => Box::new(
+                std::fs::File::create(path)
+                    .with_context(|| format!("Cannot create {}", path.display()))?,
+            ),
+            _ => Box::new(io::stdout()),
+        };
+        CsvWriter::new(out, fields, escape_formulas)
+    }
+
+    pub fn new(
+        out: Box<dyn Write + Send>,
+        fields: &[String],
+        escape_formulas: bool,
+    ) -> anyhow::Result<Self> {
+        let columns = Columns::new(fields);
+        let mut out = csv::Writer::from_writer(out);
+        out.write_record(columns.names())?;
+        Ok(CsvWriter {
+            columns,
+            escape_formulas,
+            host: host_name(),
+            out: Mutex::new(out),
+        })
+    }
+
+    fn row(&self, record: &DetectionRecord) -> Vec<String> {
+        let text = |value: &str| {
+            if self.escape_formulas && starts_formula(value) {
+                format!("'{value}")
+            } else {
+                value.to_string()
+            }
+        };
+        let time =
+            |time: chrono::DateTime<chrono::Utc>| time.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        let mut row = vec![
+            record.schema_version.to_string(),
+            record.id.clone(),
+            record.event_time.map(time).unwrap_or_default(),
+            time(record.ingest_time),
+            text(&record.host),
+            text(&record.source),
+            text(&record.rule_id),
+            record.rule_type.as_str().to_string(),
+            record.severity.as_str().to_string(),
+            text(&record.description),
+            record.line_number.to_string(),
+            line_numbers(record),
+            text(&record.matched_line),
+        ];
+        row.extend(
+            self.columns
+                .field_values(record)
+                .into_iter()
+                .map(|value| value.map(text).unwrap_or_default()),
+        );
+        row.push(self.columns.other_fields(record).unwrap_or_default());
+        row
+    }
+}
+
+/// Whether a spreadsheet would evaluate a cell starting like `value`.
+fn starts_formula(value: &str) -> bool {
+    value.starts_with(['=', '+', '-', '@', '\t', '\r'])
+}
+
+impl OutputSink for CsvWriter {
+    fn write(&self, detection: &Detection) -> anyhow::Result<()> {
+        let row = self.row(&DetectionRecord::new(detection, &self.host));
+        self.out.lock().unwrap().write_record(row)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        Ok(self.out.lock().unwrap().flush()?)
+    }
+
+    fn finish(&self, _summary: &RunSummary) -> anyhow::Result<()> {
+        self.flush()
+    }
+}
+
+impl Drop for CsvWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.out.lock().unwrap_or_else(|e| e.into_inner()).flush() {
+            eprintln!("Failed to finish CSV output: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::console::create_detection;
+
+    #[test]
+    fn writes_fixed_columns_with_flattened_fields() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("detections.csv");
+        let fields = [
+            "geo.country".to_string(),
+            "user".to_string(),
+            "user".to_string(),
+        ];
+
+        let mut first = create_detection(
+            "high",
+            Path::new("auth.log"),
+            3,
+            "2024-05-01T10:00:00Z Failed password, \"root\"",
+            "auth",
+        );
+        first
+            .fields
+            .insert("geo.country".to_string(), "TR".to_string());
+        first
+            .fields
+            .insert("ioc.indicator".to_string(), "203.0.113.5".to_string());
+        let mut second = create_detection("info", Path::new("app.log"), 9, "=HYPERLINK(1)", "app");
+        second.fields.insert("user".to_string(), "-1+2".to_string());
+
+        let writer = CsvWriter::create(Some(&path), &fields, true).unwrap();
+        writer.write(&first).unwrap();
+        writer.write(&second).unwrap();
+        writer.finish(&RunSummary::default()).unwrap();
+        drop(writer);
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let header: Vec<String> = reader.headers().unwrap().iter().map(String::from).collect();
+        assert_eq!(header.len(), 16);
+        assert_eq!(
+            header[10..],
+            [
+                "line_number",
+                "line_numbers",
+                "matched_line",
+                "fields.geo.country",
+                "fields.user",
+                "fields"
+            ]
+        );
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[0][2], "2024-05-01T10:00:00Z");
+        assert_eq!(&rows[0][6], "auth");
+        assert_eq!(
+            &rows[0][12],
+            "2024-05-01T10:00:00Z Failed password, \"root\""
+        );
+        assert_eq!(&rows[0][13], "TR");
+        assert_eq!(&rows[0][14], "");
+        assert_eq!(&rows[0][15], r#"{"ioc.indicator":"203.0.113.5"}"#);
+        assert_eq!(&rows[1][2], "");
+        assert_eq!(&rows[1][12], "'=HYPERLINK(1)");
+        assert_eq!(&rows[1][14], "'-1+2");
+        assert_eq!(&rows[1][15], "");
+    }
+}
@@ -0,0 +1,350 @@
+use super::schema::{host_name, DetectionRecord};
+use super::sink::{OutputSink, RunSummary};
+use super::template::Template;
+use super::Detection;
+use crate::config::rules::ExecConfig;
+use anyhow::Context;
+use std::io::{Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Detections waiting for a free command slot before `write` blocks.
+const QUEUE_CAPACITY: usize = 1000;
+/// How much of a failed command's stderr is logged.
+const MAX_STDERR_CHARS: usize = 500;
+
+/// Runs a local command for every detection.
+///
+/// The command is started directly with its arguments, never through a
+/// shell, so log content substituted into an argument stays one argument.
+/// Every run also gets the detection as `RUSTHOUND_*` environment variables
+/// and, with `stdin = true`, as a JSON record on stdin. `max_concurrent`
+/// threads take detections from a queue; when all are busy and the queue is
+/// full, `write` waits.
+pub struct ExecSink {
+    host: String,
+    /// Dropped on close, which lets the threads finish the queue and stop
+    sender: Mutex<Option<SyncSender<DetectionRecord>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl ExecSink {
+    pub fn new(name: &str, config: &ExecConfig) -> anyhow::Result<Self> {
+        let runner = Arc::new(Runner::new(name, config)?);
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut workers = Vec::new();
+        for index in 0..config.max_concurrent.max(1) {
+            let runner = runner.clone();
+            let receiver = receiver.clone();
+            let handle = std::thread::Builder::new()
+                .name(format!("exec-{name}-{index}"))
+                .spawn(move || runner.serve(&receiver))
+                .context("cannot start exec thread")?;
+            workers.push(handle);
+        }
+        Ok(ExecSink {
+            host: host_name(),
+            sender: Mutex::new(Some(sender)),
+            workers: Mutex::new(workers),
+        })
+    }
+
+    /// Wait for every queued command to finish.
+    fn close(&self) {
+        drop(self.sender.lock().unwrap_or_else(|e| e.into_inner()).take());
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap_or_else(|e| e.into_inner()));
+        for handle in workers {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl OutputSink for ExecSink {
+    fn write(&self, detection: &Detection) -> anyhow::Result<()> {
+        let record = DetectionRecord::new(detection, &self.host);
+        let sender = self
+            .sender
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("exec output is closed"))?;
+        sender
+            .send(record)
+            .map_err(|_| anyhow::anyhow!("exec threads have stopped"))
+    }
+
+    fn finish(&self, _summary: &RunSummary) -> anyhow::Result<()> {
+        self.close();
+        Ok(())
+    }
+}
+
+impl Drop for ExecSink {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+struct Runner {
+    name: String,
+    program: String,
+    args: Vec<Template>,
+    stdin: bool,
+    timeout: Duration,
+}
+
+impl Runner {
+    fn new(name: &str, config: &ExecConfig) -> anyhow::Result<Self> {
+        let (program, args) = config
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("exec command is empty"))?;
+        // Log content may fill in arguments, never pick the executable
+        if program.contains("{{") {
+            return Err(anyhow::anyhow!(
+                "exec program \"{program}\" cannot contain placeholders; use them in the arguments"
+            ));
+        }
+        let parse = |arg: &String| {
+            Template::parse(arg, false).with_context(|| format!("exec argument \"{arg}\""))
+        };
+        Ok(Runner {
+            name: name.to_string(),
+            program: program.clone(),
+            args: args.iter().map(parse).collect::<anyhow::Result<_>>()?,
+            stdin: config.stdin,
+            timeout: Duration::from_secs(config.timeout_seconds.max(1)),
+        })
+    }
+
+    fn serve(&self, receiver: &Mutex<Receiver<DetectionRecord>>) {
+        loop {
+            let received = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
+            let Ok(record) = received else {
+                return;
+            };
+            if let Err(e) = self.run(&record) {
+                eprintln!(
+                    "exec \"{}\": {} for \"{}\" (line {} of {}): {e:#}",
+                    self.name, self.program, record.rule_id, record.line_number, record.source
+                );
+            }
+        }
+    }
+
+    fn run(&self, record: &DetectionRecord) -> anyhow::Result<()> {
+        let mut child = Command::new(&self.program)
+            .args(self.args.iter().map(|arg| arg.render(record)))
+            .envs(detection_env(record))
+            .stdin(if self.stdin {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("cannot start")?;
+
+        // Written from its own thread so a command that never reads its
+        // input still runs into the timeout instead of blocking here
+        if let Some(mut stdin) = child.stdin.take() {
+            let json = serde_json::to_string(record)?;
+            std::thread::spawn(move || {
+                // A command that does not read its input is not an error
+                let _ = stdin.write_all(json.as_bytes());
+            });
+        }
+        let stderr = child.stderr.take().map(|mut stderr| {
+            std::thread::spawn(move || {
+                let mut output = String::new();
+                let _ = stderr.read_to_string(&mut output);
+                output
+            })
+        });
+
+        let status = wait_with_timeout(&mut child, self.timeout)?;
+        let Some(status) = status else {
+            return Err(anyhow::anyhow!(
+                "killed after {}s timeout",
+                self.timeout.as_secs()
+            ));
+        };
+        if status.success() {
+            return Ok(());
+        }
+        let stderr = stderr
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
+        let stderr = stderr.trim();
+        let mut message = match status.code() {
+            Some(code) => format!("exited with code {code}"),
+            None => format!("ended by {status}"),
+        };
+        if !stderr.is_empty() {
+            let excerpt: String = stderr.chars().take(MAX_STDERR_CHARS).collect();
+            message.push_str(&format!(": {excerpt}"));
+        }
+        Err(anyhow::anyhow!(message))
+    }
+}
+
+/// Wait for `child`, killing it after `timeout`; None if it was killed.
+fn wait_with_timeout(
+    child: &mut std::process::Child,
+    timeout: Duration,
+) -> anyhow::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// `RUSTHOUND_*` variables describing a detection; fields become
+/// `RUSTHOUND_FIELD_<NAME>` with non-alphanumerics turned into `_`.
+fn detection_env(record: &DetectionRecord) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("RUSTHOUND_ID".to_string(), record.id.clone()),
+        ("RUSTHOUND_RULE".to_string(), record.rule_id.clone()),
+        (
+            "RUSTHOUND_RULE_TYPE".to_string(),
+            record.rule_type.as_str().to_string(),
+        ),
+        (
+            "RUSTHOUND_SEVERITY".to_string(),
+            record.severity.as_str().to_string(),
+        ),
+        (
+            "RUSTHOUND_DESCRIPTION".to_string(),
+            record.description.clone(),
+        ),
+        ("RUSTHOUND_SOURCE".to_string(), record.source.clone()),
+        (
+            "RUSTHOUND_LINE_NUMBER".to_string(),
+            record.line_number.to_string(),
+        ),
+        (
+            "RUSTHOUND_MATCHED_LINE".to_string(),
+            record.matched_line.clone(),
+        ),
+        ("RUSTHOUND_HOST".to_string(), record.host.clone()),
+    ];
+    for (name, value) in &record.fields {
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        env.push((format!("RUSTHOUND_FIELD_{name}"), value.clone()));
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::console::create_detection;
+    use std::path::Path;
+
+    #[test]
+    fn passes_detections_as_arguments_env_and_stdin_without_a_shell() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let out = dir.join("out.txt");
+        let marker = dir.join("injected");
+        let script = format!(
+            "printf '%s\\n' \"$1\" \"$RUSTHOUND_RULE\" \"$RUSTHOUND_FIELD_IOC_INDICATOR\" >> '{}'; cat >> '{}'",
+            out.display(),
+            out.display()
+        );
+        let mut config = ExecConfig::new(vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            script,
+            "sh".to_string(),
+            "{{matched_line}}".to_string(),
+        ]);
+        config.stdin = true;
+        config.max_concurrent = 1;
+
+        let line = format!(
+            "user=\"$(touch {})\"; touch {}",
+            marker.display(),
+            marker.display()
+        );
+        let mut detection = create_detection("high", Path::new("auth.log"), 7, &line, "bad_ip");
+        detection
+            .fields
+            .insert("ioc.indicator".to_string(), "10.0.0.1".to_string());
+        let sink = ExecSink::new("ban", &config).unwrap();
+        sink.write(&detection).unwrap();
+        sink.finish(&RunSummary::default()).unwrap();
+
+        let output = std::fs::read_to_string(&out).unwrap();
+        let mut lines = output.lines();
+        assert_eq!(lines.next(), Some(line.as_str()));
+        assert_eq!(lines.next(), Some("bad_ip"));
+        assert_eq!(lines.next(), Some("10.0.0.1"));
+        let record: DetectionRecord = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(record.line_number, 7);
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn limits_concurrency_and_kills_slow_commands() {
+        let detection = create_detection("high", Path::new("a.log"), 1, "line", "rule");
+
+        let mut config = ExecConfig::new(vec!["sleep".to_string(), "0.3".to_string()]);
+        config.max_concurrent = 2;
+        let started = Instant::now();
+        let sink = ExecSink::new("sleep", &config).unwrap();
+        for _ in 0..4 {
+            sink.write(&detection).unwrap();
+        }
+        sink.finish(&RunSummary::default()).unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(600), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1150), "{elapsed:?}");
+
+        let mut config = ExecConfig::new(vec!["sleep".to_string(), "30".to_string()]);
+        config.timeout_seconds = 1;
+        let started = Instant::now();
+        let sink = ExecSink::new("slow", &config).unwrap();
+        sink.write(&detection).unwrap();
+        sink.finish(&RunSummary::default()).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // More input than a pipe buffers, for a command that never reads it
+        let long_line = "x".repeat(1 << 20);
+        let detection = create_detection("high", Path::new("a.log"), 1, &long_line, "rule");
+        config.stdin = true;
+        let started = Instant::now();
+        let sink = ExecSink::new("deaf", &config).unwrap();
+        sink.write(&detection).unwrap();
+        sink.finish(&RunSummary::default()).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        assert!(ExecSink::new("empty", &ExecConfig::new(Vec::new())).is_err());
+        let bad = ExecConfig::new(vec!["echo".to_string(), "{{rule}}".to_string()]);
+        assert!(ExecSink::new("bad", &bad).is_err());
+        let chosen = ExecConfig::new(vec!["{{fields.program}}".to_string()]);
+        assert!(ExecSink::new("chosen", &chosen).is_err());
+    }
+}
@@ -1,20 +1,167 @@
-use serde::Serialize;
-use std::fs::File;
-use std::io::{self, Write};
-
-#[derive(Serialize, Debug)]
-pub struct AnomalyDetection {
-    pub timestamp: String,
-    pub severity: String,
-    pub rule_name: String,
-    pub file_path: String,
-    pub line_number: usize,
-    pub matched_line: String,
-    pub pattern: String,
+use super::schema::{host_name, DetectionRecord};
+use super::sink::{OutputSink, RunSummary};
+use super::Detection;
+use crate::config::rules::JsonFormat;
+use anyhow::Context;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The JSON destination of one run, shared by every file it reads. Each
+/// detection is written as a `DetectionRecord`.
+///
+/// In `Lines` mode the file is appended to, so a restarted follow run
+/// keeps earlier detections, and every detection is flushed as soon as it
+/// is written so followers of the file see it immediately. In `Array` mode
+/// the file is truncated when the writer is created and the document is
+/// only valid once `finish` has run.
+pub struct JsonWriter {
+    format: JsonFormat,
+    host: String,
+    state: Mutex<WriterState>,
+}
+
+struct WriterState {
+    out: Box<dyn Write + Send>,
+    written: usize,
+    finished: bool,
+}
+
+impl JsonWriter {
+    /// Write to `path`, or to stdout when `path` is `None` or `-`.
+    pub fn create(path: Option<&Path>, format: JsonFormat) -> anyhow::Result<Self> {
+        let out: Box<dyn Write + Send> = match path {
+            Some(path) if path != Path::new("-") => {
+                let mut options = std::fs::OpenOptions::new();
+                match format {
+                    JsonFormat::Lines => options.append(true),
+                    JsonFormat::Array => options.write(true).truncate(true),
+                };
+                let file = options
+                    .create(true)
+                    .open(path)
+                    .with_context(|| format!("Cannot create {}", path.display()))?;
+                Box::new(BufWriter::new(file))
+            }
+            _ => Box::new(io::stdout()),
+        };
+        Ok(JsonWriter::new(out, format))
+    }
+
+    pub fn new(out: Box<dyn Write + Send>, format: JsonFormat) -> Self {
+        JsonWriter {
+            format,
+            host: host_name(),
+            state: Mutex::new(WriterState {
+                out,
+                written: 0,
+                finished: false,
+            }),
+        }
+    }
+
+    /// Close the array in `Array` mode and flush. Later calls do nothing.
+    fn close(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.finished {
+            return Ok(());
+        }
+        state.finished = true;
+        if self.format == JsonFormat::Array {
+            let closing = if state.written == 0 { "[]\n" } else { "\n]\n" };
+            state.out.write_all(closing.as_bytes())?;
+        }
+        state.out.flush()
+    }
+}
+
+impl OutputSink for JsonWriter {
+    fn write(&self, detection: &Detection) -> anyhow::Result<()> {
+        let json = serde_json::to_string(&DetectionRecord::new(detection, &self.host))?;
+        let mut state = self.state.lock().unwrap();
+        match self.format {
+            JsonFormat::Lines => {
+                writeln!(state.out, "{json}")?;
+                state.out.flush()?;
+            }
+            JsonFormat::Array => {
+                let separator = if state.written == 0 { "[\n" } else { ",\n" };
+                write!(state.out, "{separator}{json}")?;
+            }
+        }
+        state.written += 1;
+        Ok(())
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        Ok(self.state.lock().unwrap().out.flush()?)
+    }
+
+    fn finish(&self, _summary: &RunSummary) -> anyhow::Result<()> {
+        Ok(self.close()?)
+    }
+}
+
+impl Drop for JsonWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            eprintln!("Failed to finish JSON output: {e}");
+        }
+    }
 }
 
-pub fn write_json_output(detection: &AnomalyDetection, output_file: &mut File) -> io::Result<()> {
-    let json_string = serde_json::to_string_pretty(detection)?;
-    writeln!(output_file, "{json_string}")?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(line_number: usize) -> Detection {
+        crate::output::console::create_detection(
+            "high",
+            Path::new("auth.log"),
+            line_number,
+            "Failed password",
+            "auth",
+        )
+    }
+
+    fn write_all(path: &Path, format: JsonFormat, count: usize) -> String {
+        let writer = JsonWriter::create(Some(path), format).unwrap();
+        for line_number in 1..=count {
+            writer.write(&detection(line_number)).unwrap();
+        }
+        writer.finish(&RunSummary::default()).unwrap();
+        drop(writer);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn writes_json_lines_and_arrays() {
+        let scratch = tempfile::tempdir().unwrap();
+        let path = scratch.path().join("detections.json");
+
+        let lines = write_all(&path, JsonFormat::Lines, 2);
+        let parsed: Vec<serde_json::Value> = lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1]["line_number"], 2);
+
+        // A restarted run appends to earlier lines
+        let lines = write_all(&path, JsonFormat::Lines, 1);
+        assert_eq!(lines.lines().count(), 3);
+
+        // An array replaces the previous run's output
+        let array = write_all(&path, JsonFormat::Array, 3);
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&array).unwrap();
+        assert_eq!(parsed.len(), 3);
+
+        let empty = write_all(&path, JsonFormat::Array, 0);
+        assert_eq!(
+            serde_json::from_str::<Vec<serde_json::Value>>(&empty)
+                .unwrap()
+                .len(),
+            0
+        );
+    }
 }
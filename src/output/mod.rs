@@ -1,17 +1,73 @@
 // src/output/mod.rs
+pub mod chat;
+pub mod columns;
 pub mod console;
+pub mod csv_writer;
+pub mod exec;
 pub mod json_writer;
+#[cfg(feature = "parquet")]
+pub mod parquet_writer;
+pub mod report;
+pub mod schema;
+pub mod sink;
+pub mod syslog;
+pub mod template;
+pub mod webhook;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone)]
 pub struct Detection {
+    pub kind: DetectionKind,
     pub severity: Severity,
     pub file_path: String,
     pub line_number: usize,
     pub pattern_name: String,
     pub matched_line: String,
+    /// One-sentence explanation of why this was reported
+    pub description: String,
+    /// Earlier lines that contributed, e.g. the trigger events of a
+    /// correlated rule; `line_number` is not repeated here
+    pub related_lines: Vec<usize>,
+    /// Extra context attached by the analyzer, e.g. `ioc.indicator`
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Which analyzer produced a detection.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectionKind {
+    Pattern,
+    Frequency,
+    Correlation,
+    /// An indicator found in an `[[ioc_lists]]` list
+    Ioc,
+    /// A login reported by a `[[travel_rules]]` entry
+    Travel,
+    /// A value first seen by a `[[new_value_rules]]` entry
+    NewValue,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+impl DetectionKind {
+    /// The name used in JSON records, e.g. `new_value`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DetectionKind::Pattern => "pattern",
+            DetectionKind::Frequency => "frequency",
+            DetectionKind::Correlation => "correlation",
+            DetectionKind::Ioc => "ioc",
+            DetectionKind::Travel => "travel",
+            DetectionKind::NewValue => "new_value",
+        }
+    }
+}
+
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum Severity {
     Critical,
     High,
@@ -22,17 +78,24 @@ pub enum Severity {
 
 impl From<&str> for Severity {
     fn from(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "critical" => Severity::Critical,
-            "high" => Severity::High,
-            "warn" | "warning" => Severity::Warning,
-            "error" | "fatal" | "exception" => Severity::Error,
-            _ => Severity::Info,
-        }
+        Severity::parse(s).unwrap_or(Severity::Info)
     }
 }
 
 impl Severity {
+    /// Strict variant of `From<&str>`: returns `None` for unrecognised names
+    /// instead of falling back to `Info`.
+    pub fn parse(s: &str) -> Option<Severity> {
+        match s.to_lowercase().as_str() {
+            "critical" => Some(Severity::Critical),
+            "high" => Some(Severity::High),
+            "warn" | "warning" => Some(Severity::Warning),
+            "error" | "fatal" | "exception" => Some(Severity::Error),
+            "info" => Some(Severity::Info),
+            _ => None,
+        }
+    }
+
     pub fn rank(&self) -> u8 {
         match self {
             Severity::Critical => 5,
@@ -43,6 +106,16 @@ impl Severity {
         }
     }
 
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Critical => "critical",
+            Severity::High => "high",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Info => "info",
+        }
+    }
+
     pub fn meets_minimum(&self, minimum: &Severity) -> bool {
         self.rank() >= minimum.rank()
     }
@@ -59,4 +132,12 @@ mod tests {
         assert!(!Severity::Warning.meets_minimum(&Severity::High));
         assert!(!Severity::Info.meets_minimum(&Severity::Error));
     }
+
+    #[test]
+    fn parse_rejects_unknown_names() {
+        assert_eq!(Severity::parse("HIGH"), Some(Severity::High));
+        assert_eq!(Severity::parse("info"), Some(Severity::Info));
+        assert_eq!(Severity::parse("severe"), None);
+        assert_eq!(Severity::from("severe"), Severity::Info);
+    }
 }
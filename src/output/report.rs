@@ -0,0 +1,834 @@
+use super::chat::severity_color;
+use super::console::select_detections;
+use super::schema::{host_name, DetectionRecord};
+use super::sink::{OutputSink, RunSummary};
+use super::{Detection, DetectionKind, Severity};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Lines shown before and after each detection.
+const CONTEXT_LINES: usize = 2;
+/// Detections listed with their context; the summaries count all of them.
+const MAX_LISTED: usize = 1000;
+const TOP_FILES: usize = 10;
+const TOP_VALUES: usize = 5;
+/// Most bars in the timeline; the bucket width grows to stay below it.
+const MAX_BUCKETS: i64 = 48;
+const BUCKET_SECONDS: [i64; 9] = [
+    60,
+    5 * 60,
+    15 * 60,
+    3600,
+    3 * 3600,
+    6 * 3600,
+    86400,
+    7 * 86400,
+    30 * 86400,
+];
+const SEVERITIES: [Severity; 5] = [
+    Severity::Critical,
+    Severity::High,
+    Severity::Error,
+    Severity::Warning,
+    Severity::Info,
+];
+
+/// Layout of an incident report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// A self-contained HTML page
+    Html,
+    /// Markdown for tickets and wikis
+    Markdown,
+}
+
+impl ReportFormat {
+    /// The format named by the extension of `path`.
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("html" | "htm") => Ok(ReportFormat::Html),
+            Some("md" | "markdown") => Ok(ReportFormat::Markdown),
+            _ => Err(anyhow::anyhow!(
+                "cannot tell the report format of {}; use a .html or .md file",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Collects the detections of a one-shot scan and writes an incident report
+/// when the run finishes.
+///
+/// The report lists the detections the console shows, selected by
+/// `select_detections`, with the lines around each one read back from its
+/// log file.
+pub struct ReportSink {
+    path: PathBuf,
+    format: ReportFormat,
+    collapse_matches: bool,
+    detections: Mutex<Vec<Detection>>,
+}
+
+impl ReportSink {
+    /// Check that `path` can be written now rather than after a long scan.
+    pub fn create(path: &Path, collapse_matches: bool) -> anyhow::Result<Self> {
+        let format = ReportFormat::from_path(path)?;
+        std::fs::File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+        Ok(ReportSink {
+            path: path.to_path_buf(),
+            format,
+            collapse_matches,
+            detections: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+impl OutputSink for ReportSink {
+    fn write(&self, detection: &Detection) -> anyhow::Result<()> {
+        self.detections.lock().unwrap().push(detection.clone());
+        Ok(())
+    }
+
+    fn finish(&self, summary: &RunSummary) -> anyhow::Result<()> {
+        let mut detections = std::mem::take(&mut *self.detections.lock().unwrap());
+        select_detections(&mut detections, None, self.collapse_matches);
+        let report = Report::new(&detections, summary, &host_name());
+        let text = match self.format {
+            ReportFormat::Html => report.to_html(),
+            ReportFormat::Markdown => report.to_markdown(),
+        };
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("Cannot write {}", self.path.display()))?;
+        eprintln!("Report written to: {}", self.path.display());
+        Ok(())
+    }
+}
+
+/// Everything a report shows, computed once for either format.
+struct Report {
+    host: String,
+    generated: DateTime<Utc>,
+    /// Ordered by file, then line
+    records: Vec<DetectionRecord>,
+    /// Numbered lines around each listed record, parallel to `records`
+    contexts: Vec<Vec<(usize, String)>>,
+    suppressed: usize,
+}
+
+struct RuleCount {
+    rule: String,
+    kind: DetectionKind,
+    /// The most severe detection of the rule
+    severity: Severity,
+    count: usize,
+}
+
+struct Timeline {
+    bucket_seconds: i64,
+    buckets: Vec<(DateTime<Utc>, usize)>,
+    /// Detections whose line has no timestamp
+    untimed: usize,
+}
+
+impl Report {
+    fn new(detections: &[Detection], summary: &RunSummary, host: &str) -> Self {
+        let mut records: Vec<DetectionRecord> = detections
+            .iter()
+            .map(|detection| DetectionRecord::new(detection, host))
+            .collect();
+        records.sort_by(|a, b| {
+            (a.source.as_str(), a.line_number).cmp(&(b.source.as_str(), b.line_number))
+        });
+        let listed = records.len().min(MAX_LISTED);
+        let contexts = read_contexts(&records[..listed]);
+        Report {
+            host: host.to_string(),
+            generated: Utc::now(),
+            records,
+            contexts,
+            suppressed: summary.suppressed.values().sum(),
+        }
+    }
+
+    fn severity_counts(&self) -> Vec<(Severity, usize)> {
+        SEVERITIES
+            .iter()
+            .map(|&severity| {
+                let count = self
+                    .records
+                    .iter()
+                    .filter(|record| record.severity == severity)
+                    .count();
+                (severity, count)
+            })
+            .collect()
+    }
+
+    fn rule_counts(&self) -> Vec<RuleCount> {
+        let mut rules: BTreeMap<(&str, &str), RuleCount> = BTreeMap::new();
+        for record in &self.records {
+            let entry = rules
+                .entry((record.rule_id.as_str(), record.rule_type.as_str()))
+                .or_insert_with(|| RuleCount {
+                    rule: record.rule_id.clone(),
+                    kind: record.rule_type,
+                    severity: record.severity,
+                    count: 0,
+                });
+            entry.count += 1;
+            if record.severity.rank() > entry.severity.rank() {
+                entry.severity = record.severity;
+            }
+        }
+        let mut rules: Vec<RuleCount> = rules.into_values().collect();
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.count));
+        rules
+    }
+
+    fn top_files(&self) -> Vec<(&str, usize)> {
+        let mut files = ranked(self.records.iter().map(|record| record.source.as_str()));
+        files.truncate(TOP_FILES);
+        files
+    }
+
+    /// The most frequent values of every extracted field, by field name.
+    fn top_values(&self) -> Vec<(&str, Vec<(&str, usize)>)> {
+        let mut fields: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for record in &self.records {
+            for (name, value) in &record.fields {
+                fields.entry(name).or_default().push(value);
+            }
+        }
+        fields
+            .into_iter()
+            .map(|(name, values)| {
+                let mut values = ranked(values.into_iter());
+                values.truncate(TOP_VALUES);
+                (name, values)
+            })
+            .collect()
+    }
+
+    fn timeline(&self) -> Timeline {
+        let times: Vec<i64> = self
+            .records
+            .iter()
+            .filter_map(|record| record.event_time)
+            .map(|time| time.timestamp())
+            .collect();
+        let untimed = self.records.len() - times.len();
+        let (Some(&first), Some(&last)) = (times.iter().min(), times.iter().max()) else {
+            return Timeline {
+                bucket_seconds: 0,
+                buckets: Vec::new(),
+                untimed,
+            };
+        };
+
+        let bucket_seconds = BUCKET_SECONDS
+            .into_iter()
+            .find(|width| last.div_euclid(*width) - first.div_euclid(*width) < MAX_BUCKETS)
+            .unwrap_or((last - first) / (MAX_BUCKETS - 1) + 1);
+        let start = first.div_euclid(bucket_seconds);
+        let mut counts = vec![0; (last.div_euclid(bucket_seconds) - start + 1) as usize];
+        for time in &times {
+            counts[(time.div_euclid(bucket_seconds) - start) as usize] += 1;
+        }
+        let buckets = counts
+            .into_iter()
+            .enumerate()
+            .map(|(index, count)| {
+                let seconds = (start + index as i64) * bucket_seconds;
+                (
+                    DateTime::from_timestamp(seconds, 0).unwrap_or_default(),
+                    count,
+                )
+            })
+            .collect();
+        Timeline {
+            bucket_seconds,
+            buckets,
+            untimed,
+        }
+    }
+
+    fn listed(&self) -> impl Iterator<Item = (&DetectionRecord, &[(usize, String)])> {
+        self.records
+            .iter()
+            .zip(&self.contexts)
+            .map(|(record, context)| (record, context.as_slice()))
+    }
+
+    fn to_html(&self) -> String {
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>RustHound report: {}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n",
+            html(&self.host)
+        );
+        let _ = writeln!(out, "<h1>RustHound incident report</h1>");
+        let _ = writeln!(
+            out,
+            "<p class=\"meta\">Host {} &middot; generated {} &middot; {} detection(s), {} suppressed</p>",
+            html(&self.host),
+            self.generated.format("%Y-%m-%d %H:%M:%S UTC"),
+            self.records.len(),
+            self.suppressed
+        );
+
+        let _ = writeln!(out, "<h2>Severity</h2>\n<table>\n<tr><th>Severity</th><th class=\"num\">Detections</th></tr>");
+        for (severity, count) in self.severity_counts() {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td class=\"num\">{count}</td></tr>",
+                badge(severity)
+            );
+        }
+        let _ = writeln!(out, "</table>");
+
+        let _ = writeln!(out, "<h2>Rules</h2>");
+        let rules = self.rule_counts();
+        if rules.is_empty() {
+            let _ = writeln!(out, "<p>No detections.</p>");
+        } else {
+            let _ = writeln!(out, "<table>\n<tr><th>Rule</th><th>Type</th><th>Severity</th><th class=\"num\">Detections</th></tr>");
+            for rule in rules {
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"num\">{}</td></tr>",
+                    html(&rule.rule),
+                    rule.kind.as_str(),
+                    badge(rule.severity),
+                    rule.count
+                );
+            }
+            let _ = writeln!(out, "</table>");
+        }
+
+        let _ = writeln!(out, "<h2>Timeline</h2>");
+        let timeline = self.timeline();
+        if timeline.buckets.is_empty() {
+            let _ = writeln!(out, "<p>No detection has a timestamp.</p>");
+        } else {
+            let peak = timeline.buckets.iter().map(|(_, count)| *count).max();
+            let peak = peak.unwrap_or(1).max(1);
+            let _ = writeln!(
+                out,
+                "<table class=\"timeline\">\n<tr><th>{} (UTC)</th><th class=\"num\">Detections</th><th></th></tr>",
+                bucket_title(timeline.bucket_seconds)
+            );
+            for (start, count) in &timeline.buckets {
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td class=\"num\">{count}</td><td><div class=\"bar\" style=\"width:{}%\"></div></td></tr>",
+                    bucket_label(*start, timeline.bucket_seconds),
+                    count * 100 / peak
+                );
+            }
+            let _ = writeln!(out, "</table>");
+        }
+        if timeline.untimed > 0 {
+            let _ = writeln!(
+                out,
+                "<p>{} detection(s) without a timestamp.</p>",
+                timeline.untimed
+            );
+        }
+
+        let _ = writeln!(out, "<h2>Top files</h2>");
+        let files = self.top_files();
+        if files.is_empty() {
+            let _ = writeln!(out, "<p>No detections.</p>");
+        } else {
+            let _ = writeln!(
+                out,
+                "<table>\n<tr><th>File</th><th class=\"num\">Detections</th></tr>"
+            );
+            for (file, count) in files {
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td class=\"num\">{count}</td></tr>",
+                    html(file)
+                );
+            }
+            let _ = writeln!(out, "</table>");
+        }
+
+        let _ = writeln!(out, "<h2>Top field values</h2>");
+        let fields = self.top_values();
+        if fields.is_empty() {
+            let _ = writeln!(out, "<p>No fields were extracted.</p>");
+        } else {
+            let _ = writeln!(
+                out,
+                "<table>\n<tr><th>Field</th><th>Value</th><th class=\"num\">Detections</th></tr>"
+            );
+            for (name, values) in fields {
+                for (value, count) in values {
+                    let _ = writeln!(
+                        out,
+                        "<tr><td>{}</td><td>{}</td><td class=\"num\">{count}</td></tr>",
+                        html(name),
+                        html(value)
+                    );
+                }
+            }
+            let _ = writeln!(out, "</table>");
+        }
+
+        let _ = writeln!(out, "<h2>Detections</h2>");
+        if self.records.len() > self.contexts.len() {
+            let _ = writeln!(
+                out,
+                "<p>Showing the first {} of {} detections.</p>",
+                self.contexts.len(),
+                self.records.len()
+            );
+        }
+        if !self.records.is_empty() {
+            let _ = writeln!(out, "<table class=\"detections\">\n<tr><th>#</th><th>Severity</th><th>Rule</th><th>Location</th><th>Time</th><th>Description</th></tr>");
+        }
+        for (index, (record, context)) in self.listed().enumerate() {
+            let fields: Vec<String> = record
+                .fields
+                .iter()
+                .map(|(name, value)| format!("{}={}", html(name), html(value)))
+                .collect();
+            let _ = writeln!(
+                out,
+                "<tr><td class=\"num\">{}</td><td>{}</td><td>{}</td><td>{}:{}</td><td>{}</td><td>{}{}</td></tr>",
+                index + 1,
+                badge(record.severity),
+                html(&record.rule_id),
+                html(&record.source),
+                record.line_number,
+                record
+                    .event_time
+                    .map_or_else(String::new, |time| time.format("%Y-%m-%d %H:%M:%S").to_string()),
+                html(&record.description),
+                if fields.is_empty() {
+                    String::new()
+                } else {
+                    format!("<div class=\"fields\">{}</div>", fields.join(" "))
+                }
+            );
+            let _ = write!(
+                out,
+                "<tr class=\"context\"><td></td><td colspan=\"5\"><pre>"
+            );
+            for (number, line) in context {
+                let text = format!("{number:>6}  {}", html(line));
+                if *number == record.line_number {
+                    let _ = writeln!(out, "<mark>{text}</mark>");
+                } else {
+                    let _ = writeln!(out, "{text}");
+                }
+            }
+            let _ = writeln!(out, "</pre></td></tr>");
+        }
+        if !self.records.is_empty() {
+            let _ = writeln!(out, "</table>");
+        }
+        let _ = writeln!(out, "</body>\n</html>");
+        out
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# RustHound incident report\n");
+        let _ = writeln!(
+            out,
+            "Host {} · generated {} · **{}** detection(s), {} suppressed\n",
+            markdown(&self.host),
+            self.generated.format("%Y-%m-%d %H:%M:%S UTC"),
+            self.records.len(),
+            self.suppressed
+        );
+
+        let _ = writeln!(out, "## Severity\n\n| Severity | Detections |\n|---|---:|");
+        for (severity, count) in self.severity_counts() {
+            let _ = writeln!(out, "| {} | {count} |", severity.as_str());
+        }
+
+        let _ = writeln!(out, "\n## Rules\n");
+        let rules = self.rule_counts();
+        if rules.is_empty() {
+            let _ = writeln!(out, "No detections.");
+        } else {
+            let _ = writeln!(
+                out,
+                "| Rule | Type | Severity | Detections |\n|---|---|---|---:|"
+            );
+            for rule in rules {
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {} | {} |",
+                    markdown(&rule.rule),
+                    rule.kind.as_str(),
+                    rule.severity.as_str(),
+                    rule.count
+                );
+            }
+        }
+
+        let _ = writeln!(out, "\n## Timeline\n");
+        let timeline = self.timeline();
+        if timeline.buckets.is_empty() {
+            let _ = writeln!(out, "No detection has a timestamp.");
+        } else {
+            let peak = timeline.buckets.iter().map(|(_, count)| *count).max();
+            let peak = peak.unwrap_or(1).max(1);
+            let _ = writeln!(
+                out,
+                "| {} (UTC) | Detections | |\n|---|---:|---|",
+                bucket_title(timeline.bucket_seconds)
+            );
+            for (start, count) in &timeline.buckets {
+                let _ = writeln!(
+                    out,
+                    "| {} | {count} | {} |",
+                    bucket_label(*start, timeline.bucket_seconds),
+                    "█".repeat((count * 30).div_ceil(peak))
+                );
+            }
+        }
+        if timeline.untimed > 0 {
+            let _ = writeln!(
+                out,
+                "\n{} detection(s) without a timestamp.",
+                timeline.untimed
+            );
+        }
+
+        let _ = writeln!(out, "\n## Top files\n");
+        let files = self.top_files();
+        if files.is_empty() {
+            let _ = writeln!(out, "No detections.");
+        } else {
+            let _ = writeln!(out, "| File | Detections |\n|---|---:|");
+            for (file, count) in files {
+                let _ = writeln!(out, "| {} | {count} |", markdown(file));
+            }
+        }
+
+        let _ = writeln!(out, "\n## Top field values\n");
+        let fields = self.top_values();
+        if fields.is_empty() {
+            let _ = writeln!(out, "No fields were extracted.");
+        } else {
+            let _ = writeln!(out, "| Field | Value | Detections |\n|---|---|---:|");
+            for (name, values) in fields {
+                for (value, count) in values {
+                    let _ = writeln!(
+                        out,
+                        "| {} | {} | {count} |",
+                        markdown(name),
+                        markdown(value)
+                    );
+                }
+            }
+        }
+
+        let _ = writeln!(out, "\n## Detections");
+        if self.records.len() > self.contexts.len() {
+            let _ = writeln!(
+                out,
+                "\nShowing the first {} of {} detections.",
+                self.contexts.len(),
+                self.records.len()
+            );
+        }
+        for (index, (record, context)) in self.listed().enumerate() {
+            let _ = writeln!(
+                out,
+                "\n### {}. {} {} — {}:{}\n\n{}",
+                index + 1,
+                record.severity.as_str().to_uppercase(),
+                markdown(&record.rule_id),
+                markdown(&record.source),
+                record.line_number,
+                markdown(&record.description)
+            );
+            if record.event_time.is_some() || !record.fields.is_empty() {
+                let _ = writeln!(out);
+            }
+            if let Some(time) = record.event_time {
+                let _ = writeln!(out, "- Time: {}", time.format("%Y-%m-%d %H:%M:%S UTC"));
+            }
+            for (name, value) in &record.fields {
+                let _ = writeln!(out, "- {}: {}", markdown(name), markdown(value));
+            }
+            let fence = fence_for(context.iter().map(|(_, line)| line.as_str()));
+            let _ = writeln!(out, "\n{fence}");
+            for (number, line) in context {
+                let marker = if *number == record.line_number {
+                    '>'
+                } else {
+                    ' '
+                };
+                let _ = writeln!(out, "{marker}{number:>6}  {line}");
+            }
+            let _ = writeln!(out, "{fence}");
+        }
+        out
+    }
+}
+
+const HTML_STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:1em}\
+th,td{border-bottom:1px solid #ddd;padding:4px 8px;text-align:left;vertical-align:top}\
+.num{text-align:right}.meta{color:#666}\
+.badge{color:#fff;border-radius:3px;padding:1px 6px;font-size:0.85em}\
+.timeline td:last-child{width:400px}.bar{background:#1E88E5;height:1em}\
+.fields{color:#666;font-size:0.85em}\
+.context pre{margin:0;background:#f6f6f6;padding:4px;overflow-x:auto}\
+mark{background:#fff59d}";
+
+/// Count equal items, most frequent first; ties keep the sorted order.
+fn ranked<'a>(items: impl Iterator<Item = &'a str>) -> Vec<(&'a str, usize)> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for item in items {
+        *counts.entry(item).or_default() += 1;
+    }
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    counts
+}
+
+/// The lines around each record, read back from its log file. A file that
+/// cannot be read any more shows only the matched line.
+fn read_contexts(records: &[DetectionRecord]) -> Vec<Vec<(usize, String)>> {
+    let window = |line_number: usize| {
+        line_number.saturating_sub(CONTEXT_LINES).max(1)..=line_number + CONTEXT_LINES
+    };
+    let mut wanted: HashMap<&str, BTreeSet<usize>> = HashMap::new();
+    for record in records {
+        wanted
+            .entry(&record.source)
+            .or_default()
+            .extend(window(record.line_number));
+    }
+
+    let mut lines: HashMap<(&str, usize), String> = HashMap::new();
+    for (source, numbers) in &wanted {
+        let Ok(file) = std::fs::File::open(source) else {
+            continue;
+        };
+        let last = numbers.last().copied().unwrap_or(0);
+        for (index, line) in BufReader::new(file).split(b'\n').enumerate() {
+            let number = index + 1;
+            if number > last {
+                break;
+            }
+            let Ok(line) = line else {
+                break;
+            };
+            if numbers.contains(&number) {
+                let line = line.strip_suffix(b"\r").unwrap_or(&line);
+                lines.insert((source, number), String::from_utf8_lossy(line).into_owned());
+            }
+        }
+    }
+
+    records
+        .iter()
+        .map(|record| {
+            let context: Vec<(usize, String)> = window(record.line_number)
+                .filter_map(|number| {
+                    lines
+                        .get(&(record.source.as_str(), number))
+                        .map(|line| (number, line.clone()))
+                })
+                .collect();
+            if context
+                .iter()
+                .any(|(number, _)| *number == record.line_number)
+            {
+                context
+            } else {
+                vec![(record.line_number, record.matched_line.clone())]
+            }
+        })
+        .collect()
+}
+
+fn bucket_title(bucket_seconds: i64) -> String {
+    match bucket_seconds {
+        60 => "Minute".to_string(),
+        3600 => "Hour".to_string(),
+        86400 => "Day".to_string(),
+        seconds if seconds % 86400 == 0 => format!("{} days", seconds / 86400),
+        seconds if seconds % 3600 == 0 => format!("{} hours", seconds / 3600),
+        seconds if seconds % 60 == 0 => format!("{} minutes", seconds / 60),
+        seconds => format!("{seconds} seconds"),
+    }
+}
+
+fn bucket_label(start: DateTime<Utc>, bucket_seconds: i64) -> String {
+    if bucket_seconds % 86400 == 0 {
+        start.format("%Y-%m-%d").to_string()
+    } else {
+        start.format("%Y-%m-%d %H:%M").to_string()
+    }
+}
+
+fn html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn badge(severity: Severity) -> String {
+    format!(
+        "<span class=\"badge\" style=\"background:#{:06X}\">{}</span>",
+        severity_color(severity),
+        severity.as_str()
+    )
+}
+
+/// Markdown text that stays plain text, also inside a table cell.
+fn markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '|' | '~' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\r' | '\n' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A code fence longer than any backtick run in `lines`.
+fn fence_for<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    let longest = lines
+        .flat_map(|line| line.split(|c| c != '`'))
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::console::create_detection;
+
+    fn at(time: &str) -> DetectionRecord {
+        let detection = create_detection("info", Path::new("a.log"), 1, time, "rule");
+        DetectionRecord::new(&detection, "web1")
+    }
+
+    #[test]
+    fn writes_html_and_markdown_reports_with_context() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let log = dir.join("auth.log");
+        let lines = [
+            "2024-05-01T10:00:00Z sshd: session opened",
+            "2024-05-01T10:01:00Z sshd: Failed password for <root> | ```",
+            "2024-05-01T10:02:00Z sshd: session closed",
+            "2024-05-01T10:03:00Z cron: job ran",
+            "2024-05-01T10:09:30Z sshd: Failed password for admin",
+        ];
+        std::fs::write(&log, lines.join("\r\n")).unwrap();
+
+        let mut first = create_detection("high", &log, 2, lines[1], "auth_failure");
+        first
+            .fields
+            .insert("user".to_string(), "<root>".to_string());
+        let duplicate = create_detection("info", &log, 2, lines[1], "sshd");
+        let second = create_detection("critical", &log, 5, lines[4], "auth_failure");
+        let summary = RunSummary {
+            suppressed: HashMap::from([("health".to_string(), 3)]),
+        };
+
+        let write_report = |name: &str| {
+            let path = dir.join(name);
+            let sink = ReportSink::create(&path, true).unwrap();
+            for detection in [&second, &first, &duplicate] {
+                sink.write(detection).unwrap();
+            }
+            sink.finish(&summary).unwrap();
+            std::fs::read_to_string(path).unwrap()
+        };
+
+        let text = write_report("report.md");
+        assert!(text.contains("**2** detection(s), 3 suppressed"));
+        assert!(text.contains("| critical | 1 |\n| high | 1 |\n| error | 0 |"));
+        assert!(text.contains("| auth\\_failure | pattern | critical | 2 |"));
+        assert!(!text.contains("sshd |"));
+        assert!(text.contains("| 2024-05-01 10:01 | 1 | ██████████████████████████████ |"));
+        assert!(text.contains("| 2024-05-01 10:09 | 1 |"));
+        assert!(text.contains("| user | \\<root\\> | 1 |"));
+        assert!(text.contains(&format!(
+            "### 1. HIGH auth\\_failure — {}:2",
+            markdown_path(&log)
+        )));
+        assert!(text.contains(&format!(
+            "````\n      1  {}\n>     2  {}\n      3  {}\n      4  {}\n````",
+            lines[0], lines[1], lines[2], lines[3]
+        )));
+
+        let html = write_report("report.html");
+        assert!(html.contains("<td>user</td><td>&lt;root&gt;</td>"));
+        assert!(html
+            .contains("<mark>     5  2024-05-01T10:09:30Z sshd: Failed password for admin</mark>"));
+        assert!(!html.contains("<root>"));
+
+        assert!(ReportSink::create(&dir.join("report.txt"), true).is_err());
+    }
+
+    fn markdown_path(path: &Path) -> String {
+        markdown(&path.display().to_string())
+    }
+
+    #[test]
+    fn timeline_buckets_widen_with_the_time_span() {
+        let report = |records: Vec<DetectionRecord>| Report {
+            host: "web1".to_string(),
+            generated: Utc::now(),
+            records,
+            contexts: Vec::new(),
+            suppressed: 0,
+        };
+
+        let timeline = report(vec![
+            at("2024-05-01T10:00:10Z a"),
+            at("2024-05-01T10:00:50Z b"),
+            at("2024-05-01T10:03:00Z c"),
+            at("no time here"),
+        ])
+        .timeline();
+        assert_eq!(timeline.bucket_seconds, 60);
+        let counts: Vec<usize> = timeline.buckets.iter().map(|(_, count)| *count).collect();
+        assert_eq!(counts, vec![2, 0, 0, 1]);
+        assert_eq!(timeline.untimed, 1);
+
+        let timeline = report(vec![
+            at("2024-05-01T10:00:00Z a"),
+            at("2024-05-03T12:00:00Z b"),
+        ])
+        .timeline();
+        assert_eq!(timeline.bucket_seconds, 3 * 3600);
+        assert_eq!(timeline.buckets.len(), 18);
+        assert_eq!(
+            bucket_label(timeline.buckets[0].0, timeline.bucket_seconds),
+            "2024-05-01 09:00"
+        );
+    }
+}
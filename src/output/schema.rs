@@ -0,0 +1,188 @@
+use super::{Detection, DetectionKind, Severity};
+use crate::analyzer::condition::LineContext;
+use crate::analyzer::timestamp::event_time;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Version of `DetectionRecord`. Bumped when a field is removed, renamed or
+/// changes meaning; new optional fields keep the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// One detection as written to JSON outputs, whatever analyzer produced it.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(title = "RustHound detection")]
+pub struct DetectionRecord {
+    /// Version of this layout
+    #[schemars(extend("const" = SCHEMA_VERSION))]
+    pub schema_version: u32,
+    /// Derived from host, source, line, rule, type and fields, so the same
+    /// detection found by a later run gets the same id
+    pub id: String,
+    /// When the event happened, from the line's timestamp; null if the line
+    /// has none
+    pub event_time: Option<DateTime<Utc>>,
+    /// When RustHound processed the line
+    pub ingest_time: DateTime<Utc>,
+    /// Host RustHound ran on
+    pub host: String,
+    /// Log file the line was read from
+    pub source: String,
+    /// Name of the rule, IOC list or analyzer rule that fired
+    pub rule_id: String,
+    pub rule_type: DetectionKind,
+    pub severity: Severity,
+    pub description: String,
+    /// The line that completed the detection
+    pub line_number: usize,
+    /// Every contributing line, ascending; includes `line_number`
+    pub line_numbers: Vec<usize>,
+    pub matched_line: String,
+    /// Fields attached by the analyzers, e.g. `geo.country` or `ioc.indicator`
+    pub fields: BTreeMap<String, String>,
+}
+
+impl DetectionRecord {
+    pub fn new(detection: &Detection, host: &str) -> Self {
+        let mut line_numbers = detection.related_lines.clone();
+        line_numbers.push(detection.line_number);
+        line_numbers.sort_unstable();
+        line_numbers.dedup();
+        DetectionRecord {
+            schema_version: SCHEMA_VERSION,
+            id: detection_id(detection, host),
+            event_time: event_time(&LineContext::new(&detection.matched_line), None),
+            ingest_time: Utc::now(),
+            host: host.to_string(),
+            source: detection.file_path.clone(),
+            rule_id: detection.pattern_name.clone(),
+            rule_type: detection.kind,
+            severity: detection.severity,
+            description: detection.description.clone(),
+            line_number: detection.line_number,
+            line_numbers,
+            matched_line: detection.matched_line.clone(),
+            fields: detection.fields.clone(),
+        }
+    }
+}
+
+/// The JSON Schema of `DetectionRecord`, printed by `rusthound schema`.
+pub fn json_schema() -> serde_json::Value {
+    schemars::schema_for!(DetectionRecord).to_value()
+}
+
+/// Name of this machine for the `host` field.
+pub fn host_name() -> String {
+    gethostname::gethostname().to_string_lossy().into_owned()
+}
+
+/// 64-bit FNV-1a over the identifying parts of a detection, as hex. Stable
+/// across runs and builds, unlike `DefaultHasher`.
+///
+/// The fields tell apart detections of one rule on one line, such as two
+/// IOC indicators. `geo.*` fields are left out, so updating the GeoIP
+/// databases keeps the ids.
+fn detection_id(detection: &Detection, host: &str) -> String {
+    let line_number = detection.line_number.to_string();
+    let kind = serde_json::to_string(&detection.kind).unwrap_or_default();
+    let parts = [
+        host,
+        &detection.file_path,
+        &line_number,
+        &detection.pattern_name,
+        &kind,
+        &detection.matched_line,
+    ];
+    let fields = detection
+        .fields
+        .iter()
+        .filter(|(name, _)| !name.starts_with("geo."))
+        .flat_map(|(name, value)| [name.as_str(), value.as_str()]);
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts.into_iter().chain(fields) {
+        for byte in part.bytes().chain([0]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correlation() -> Detection {
+        Detection {
+            kind: DetectionKind::Correlation,
+            severity: Severity::High,
+            file_path: "auth.log".to_string(),
+            line_number: 7,
+            pattern_name: "brute_force_success".to_string(),
+            matched_line: "2024-05-01T10:00:00Z sshd: Accepted password for root".to_string(),
+            description: "Failed logins followed by a success".to_string(),
+            related_lines: vec![5, 3],
+            fields: BTreeMap::from([("correlation.key".to_string(), "10.0.0.1".to_string())]),
+        }
+    }
+
+    #[test]
+    fn records_carry_times_lines_and_stable_ids() {
+        let record = DetectionRecord::new(&correlation(), "web1");
+        assert_eq!(record.line_numbers, vec![3, 5, 7]);
+        assert_eq!(
+            record.event_time,
+            Some(
+                DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z")
+                    .unwrap()
+                    .to_utc()
+            )
+        );
+        assert_eq!(record.id, DetectionRecord::new(&correlation(), "web1").id);
+        assert_ne!(record.id, DetectionRecord::new(&correlation(), "web2").id);
+
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["rule_type"], "correlation");
+        assert_eq!(json["severity"], "high");
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn ids_tell_apart_detections_on_one_line() {
+        let ioc = |indicator: &str| Detection {
+            kind: DetectionKind::Ioc,
+            severity: Severity::High,
+            file_path: "proxy.log".to_string(),
+            line_number: 3,
+            pattern_name: "bad_domains".to_string(),
+            matched_line: "GET http://evil.example/ referer=http://worse.example/".to_string(),
+            description: format!("{indicator} is listed in bad_domains"),
+            related_lines: Vec::new(),
+            fields: BTreeMap::from([
+                ("ioc.list".to_string(), "bad_domains".to_string()),
+                ("ioc.indicator".to_string(), indicator.to_string()),
+            ]),
+        };
+        let first = DetectionRecord::new(&ioc("evil.example"), "web1");
+        let second = DetectionRecord::new(&ioc("worse.example"), "web1");
+        assert_ne!(first.id, second.id);
+
+        let mut enriched = ioc("evil.example");
+        enriched
+            .fields
+            .insert("geo.country".to_string(), "TR".to_string());
+        assert_eq!(DetectionRecord::new(&enriched, "web1").id, first.id);
+    }
+
+    #[test]
+    fn schema_describes_every_record_field() {
+        let schema = json_schema();
+        let properties = schema["properties"].as_object().unwrap();
+        let record = serde_json::to_value(DetectionRecord::new(&correlation(), "web1")).unwrap();
+        let keys: Vec<_> = record.as_object().unwrap().keys().collect();
+        assert_eq!(properties.keys().collect::<Vec<_>>(), keys);
+        assert_eq!(properties["schema_version"]["const"], SCHEMA_VERSION);
+    }
+}
@@ -0,0 +1,338 @@
+use super::console::ConsoleSink;
+use super::csv_writer::CsvWriter;
+use super::exec::ExecSink;
+use super::json_writer::JsonWriter;
+use super::syslog::SyslogSink;
+use super::webhook::WebhookSink;
+use super::{Detection, Severity};
+use crate::config::rules::{JsonFormat, OutputConfig, SinkConfig};
+use crate::metrics;
+use anyhow::Context;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// A destination for detections, such as the console or a JSON file.
+///
+/// Sinks are shared by every file of a run, including the threads of a
+/// parallel scan, so they take `&self` and synchronize internally.
+pub trait OutputSink: Send + Sync {
+    /// Accept one detection that passed the sink's filter.
+    fn write(&self, detection: &Detection) -> anyhow::Result<()>;
+
+    /// A batch of reads is done: a one-shot scan, or one read in follow
+    /// mode. Buffering sinks emit what they hold.
+    fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The run is over; called once after the last `flush` of a one-shot
+    /// scan. Follow mode never finishes.
+    fn finish(&self, summary: &RunSummary) -> anyhow::Result<()> {
+        let _ = summary;
+        self.flush()
+    }
+}
+
+/// Totals of a finished run, for sinks that report them.
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    /// Detections dropped by suppress rules, per suppress rule name
+    pub suppressed: HashMap<String, usize>,
+}
+
+/// Which detections an output receives.
+#[derive(Debug, Default)]
+pub struct SinkFilter {
+    pub min_severity: Option<Severity>,
+    /// Only these rules; empty means every rule
+    pub rules: HashSet<String>,
+    pub exclude_rules: HashSet<String>,
+}
+
+impl SinkFilter {
+    pub fn accepts(&self, detection: &Detection) -> bool {
+        self.min_severity
+            .is_none_or(|minimum| detection.severity.meets_minimum(&minimum))
+            && (self.rules.is_empty() || self.rules.contains(&detection.pattern_name))
+            && !self.exclude_rules.contains(&detection.pattern_name)
+    }
+}
+
+struct FilteredSink {
+    name: String,
+    filter: SinkFilter,
+    sink: Box<dyn OutputSink>,
+}
+
+/// Fans every detection out to the outputs whose filter accepts it.
+///
+/// A failing output does not keep the others from receiving a detection;
+/// the first error is returned once all of them had their turn.
+#[derive(Default)]
+pub struct Outputs {
+    sinks: Vec<FilteredSink>,
+}
+
+impl Outputs {
+    /// Open every configured output. `follow` rejects outputs that need the
+    /// run to end, such as JSON arrays.
+    pub fn from_config(configs: &[OutputConfig], follow: bool) -> anyhow::Result<Self> {
+        let stdout_users: Vec<&str> = configs
+            .iter()
+            .filter(|config| writes_to_stdout(&config.sink))
+            .map(OutputConfig::label)
+            .collect();
+        if stdout_users.len() > 1 {
+            return Err(anyhow::anyhow!(
+                "outputs {} all write to stdout; give the JSON output a path",
+                stdout_users.join(", ")
+            ));
+        }
+
+        let mut outputs = Outputs::default();
+        for config in configs {
+            let name = config.label().to_string();
+            let filter = filter_for(config).with_context(|| format!("output \"{name}\""))?;
+            let sink: Box<dyn OutputSink> = match &config.sink {
+                SinkConfig::Console { all_matches } => Box::new(ConsoleSink::new(!all_matches)),
+                SinkConfig::Json { path, format } => {
+                    if follow && *format == JsonFormat::Array {
+                        return Err(anyhow::anyhow!(
+                            "output \"{name}\": JSON arrays need a finished run and cannot be used with --follow"
+                        ));
+                    }
+                    if let Some(path) = path.as_ref().filter(|path| path.as_os_str() != "-") {
+                        eprintln!("Writing JSON output to: {}", path.display());
+                    }
+                    Box::new(JsonWriter::create(path.as_deref(), *format)?)
+                }
+                SinkConfig::Csv {
+                    path,
+                    fields,
+                    escape_formulas,
+                } => {
+                    if let Some(path) = path.as_ref().filter(|path| path.as_os_str() != "-") {
+                        eprintln!("Writing CSV output to: {}", path.display());
+                    }
+                    Box::new(CsvWriter::create(
+                        path.as_deref(),
+                        fields,
+                        *escape_formulas,
+                    )?)
+                }
+                SinkConfig::Parquet { path, fields } => {
+                    if follow {
+                        return Err(anyhow::anyhow!(
+                            "output \"{name}\": Parquet files need a finished run and cannot be used with --follow"
+                        ));
+                    }
+                    let sink =
+                        parquet_sink(path, fields).with_context(|| format!("output \"{name}\""))?;
+                    eprintln!("Writing Parquet output to: {}", path.display());
+                    sink
+                }
+                SinkConfig::Exec(exec) => Box::new(
+                    ExecSink::new(&name, exec).with_context(|| format!("output \"{name}\""))?,
+                ),
+                SinkConfig::Syslog(syslog) => Box::new(
+                    SyslogSink::new(&name, syslog).with_context(|| format!("output \"{name}\""))?,
+                ),
+                SinkConfig::Webhook(webhook) => Box::new(
+                    WebhookSink::new(&name, webhook)
+                        .with_context(|| format!("output \"{name}\""))?,
+                ),
+            };
+            outputs.add(name, filter, sink);
+        }
+        Ok(outputs)
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, filter: SinkFilter, sink: Box<dyn OutputSink>) {
+        self.sinks.push(FilteredSink {
+            name: name.into(),
+            filter,
+            sink,
+        });
+    }
+
+    /// Send `detection` to every output whose filter accepts it. A failing
+    /// output is logged and counted in the metrics, and the scan goes on.
+    pub fn write(&self, detection: &Detection) {
+        self.each_logged(|sink| {
+            if sink.filter.accepts(detection) {
+                sink.sink.write(detection)
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    /// Flush every output, logging and counting failures like `write`.
+    pub fn flush(&self) {
+        self.each_logged(|sink| sink.sink.flush())
+    }
+
+    /// End the run; unlike `write` and `flush`, a failure here is returned.
+    pub fn finish(&self, summary: &RunSummary) -> anyhow::Result<()> {
+        self.each(|sink| sink.sink.finish(summary))
+    }
+
+    fn each(&self, mut f: impl FnMut(&FilteredSink) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        let mut first_error = None;
+        for sink in &self.sinks {
+            if let Err(e) = f(sink) {
+                let e = e.context(format!("output \"{}\" failed", sink.name));
+                if first_error.is_none() {
+                    first_error = Some(e);
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    fn each_logged(&self, mut f: impl FnMut(&FilteredSink) -> anyhow::Result<()>) {
+        for sink in &self.sinks {
+            if let Err(e) = f(sink) {
+                metrics::global().output_error(&sink.name);
+                eprintln!("output \"{}\" failed: {e:#}", sink.name);
+            }
+        }
+    }
+}
+
+fn writes_to_stdout(sink: &SinkConfig) -> bool {
+    match sink {
+        SinkConfig::Console { .. } => true,
+        SinkConfig::Json { path, .. } => path.as_ref().is_none_or(|path| path.as_os_str() == "-"),
+        SinkConfig::Csv { path, .. } => path.as_ref().is_none_or(|path| path.as_os_str() == "-"),
+        SinkConfig::Parquet { .. }
+        | SinkConfig::Webhook(_)
+        | SinkConfig::Exec(_)
+        | SinkConfig::Syslog(_) => false,
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_sink(path: &Path, fields: &[String]) -> anyhow::Result<Box<dyn OutputSink>> {
+    Ok(Box::new(super::parquet_writer::ParquetWriter::create(
+        path, fields,
+    )?))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_sink(_path: &Path, _fields: &[String]) -> anyhow::Result<Box<dyn OutputSink>> {
+    Err(anyhow::anyhow!(
+        "this build has no Parquet support; rebuild with `--features parquet`"
+    ))
+}
+
+fn filter_for(config: &OutputConfig) -> anyhow::Result<SinkFilter> {
+    let min_severity = config
+        .min_severity
+        .as_deref()
+        .map(|name| {
+            Severity::parse(name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown severity \"{name}\" (expected critical, high, error, warning or info)"
+                )
+            })
+        })
+        .transpose()?;
+    Ok(SinkFilter {
+        min_severity,
+        rules: config.rules.iter().cloned().collect(),
+        exclude_rules: config.exclude_rules.iter().cloned().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::console::create_detection;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl OutputSink for Collect {
+        fn write(&self, detection: &Detection) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(detection.pattern_name.clone());
+            Ok(())
+        }
+    }
+
+    struct Broken;
+
+    impl OutputSink for Broken {
+        fn write(&self, _detection: &Detection) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("disk full"))
+        }
+    }
+
+    #[test]
+    fn fans_out_through_filters_and_survives_failing_outputs() {
+        let high = Arc::new(Mutex::new(Vec::new()));
+        let auth = Arc::new(Mutex::new(Vec::new()));
+        let mut outputs = Outputs::default();
+        outputs.add("broken", SinkFilter::default(), Box::new(Broken));
+        outputs.add(
+            "high",
+            SinkFilter {
+                min_severity: Some(Severity::High),
+                exclude_rules: HashSet::from(["noisy".to_string()]),
+                ..Default::default()
+            },
+            Box::new(Collect(high.clone())),
+        );
+        outputs.add(
+            "auth",
+            SinkFilter {
+                rules: HashSet::from(["auth_failure".to_string()]),
+                ..Default::default()
+            },
+            Box::new(Collect(auth.clone())),
+        );
+
+        for (severity, rule) in [
+            ("critical", "oom"),
+            ("info", "auth_failure"),
+            ("critical", "noisy"),
+        ] {
+            outputs.write(&create_detection(
+                severity,
+                Path::new("a.log"),
+                1,
+                "line",
+                rule,
+            ));
+        }
+        assert_eq!(*high.lock().unwrap(), vec!["oom"]);
+        assert_eq!(*auth.lock().unwrap(), vec!["auth_failure"]);
+        assert!(metrics::global()
+            .render()
+            .lines()
+            .any(|line| line == "rusthound_output_errors_total{output=\"broken\"} 3"));
+    }
+
+    #[test]
+    fn rejects_conflicting_output_configs() {
+        let json = |format| OutputConfig::new(SinkConfig::Json { path: None, format });
+        let error =
+            Outputs::from_config(&[OutputConfig::console(), json(JsonFormat::Lines)], false)
+                .err()
+                .unwrap();
+        assert!(error
+            .to_string()
+            .contains("console, json all write to stdout"));
+
+        let error = Outputs::from_config(&[json(JsonFormat::Array)], true)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("--follow"));
+
+        let mut console = OutputConfig::console();
+        console.min_severity = Some("severe".to_string());
+        let error = Outputs::from_config(&[console], false).err().unwrap();
+        assert!(format!("{error:#}").contains("unknown severity \"severe\""));
+    }
+}
@@ -0,0 +1,705 @@
+use super::schema::{host_name, DetectionRecord};
+use super::sink::{OutputSink, RunSummary};
+use super::{Detection, Severity};
+use crate::config::rules::{Facility, SyslogConfig, SyslogFormat, SyslogFraming, SyslogProtocol};
+use anyhow::Context;
+use chrono::SecondsFormat;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Messages waiting to be sent before new ones are dropped.
+const QUEUE_CAPACITY: usize = 10_000;
+/// How long messages are dropped after a failed connect before the next try.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Longest datagram sent over UDP; longer messages are cut.
+const MAX_UDP_BYTES: usize = 8192;
+/// SD-ID of the structured data; 32473 is the example enterprise number of
+/// RFC 5612.
+const SD_ID: &str = "rusthound@32473";
+const FIELDS_SD_ID: &str = "fields@32473";
+
+/// Syslog severity of a detection: 1 (alert) for critical down to 6 (info).
+pub fn syslog_severity(severity: Severity) -> u8 {
+    match severity {
+        Severity::Critical => 1,
+        Severity::High => 2,
+        Severity::Error => 3,
+        Severity::Warning => 4,
+        Severity::Info => 6,
+    }
+}
+
+/// CEF and LEEF severity, 0 (lowest) to 10.
+fn event_severity(severity: Severity) -> u8 {
+    match severity {
+        Severity::Critical => 10,
+        Severity::High => 8,
+        Severity::Error => 6,
+        Severity::Warning => 4,
+        Severity::Info => 2,
+    }
+}
+
+/// Sends every detection as one syslog message.
+///
+/// Messages are built on the calling thread and sent by a background
+/// thread, which reconnects when a TCP or TLS connection breaks. Syslog is
+/// lossy by design: while the receiver is unreachable, or the queue is full,
+/// messages are dropped and the count is logged once sending works again.
+pub struct SyslogSink {
+    host: String,
+    formatter: Formatter,
+    sender: Mutex<Option<SyncSender<String>>>,
+    overflow: Arc<AtomicUsize>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SyslogSink {
+    pub fn new(name: &str, config: &SyslogConfig) -> anyhow::Result<Self> {
+        let transport = Transport::new(name, config)?;
+        let overflow = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let worker_overflow = overflow.clone();
+        let handle = std::thread::Builder::new()
+            .name(format!("syslog-{name}"))
+            .spawn(move || transport.run(&receiver, &worker_overflow))
+            .context("cannot start syslog thread")?;
+        Ok(SyslogSink {
+            host: host_name(),
+            formatter: Formatter {
+                format: config.format,
+                facility: config.facility,
+                app_name: config.app_name.clone(),
+                proc_id: std::process::id(),
+            },
+            sender: Mutex::new(Some(sender)),
+            overflow,
+            worker: Mutex::new(Some(handle)),
+        })
+    }
+
+    /// Send what is queued and stop the thread.
+    fn close(&self) {
+        drop(self.sender.lock().unwrap_or_else(|e| e.into_inner()).take());
+        let handle = self.worker.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(handle) = handle {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl OutputSink for SyslogSink {
+    fn write(&self, detection: &Detection) -> anyhow::Result<()> {
+        let message = self
+            .formatter
+            .format(&DetectionRecord::new(detection, &self.host));
+        let sender = self.sender.lock().unwrap_or_else(|e| e.into_inner());
+        let sender = sender
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("syslog output is closed"))?;
+        match sender.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.overflow.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(anyhow::anyhow!("syslog thread has stopped")),
+        }
+    }
+
+    fn finish(&self, _summary: &RunSummary) -> anyhow::Result<()> {
+        self.close();
+        Ok(())
+    }
+}
+
+impl Drop for SyslogSink {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Builds RFC 5424 messages: `<PRI>1 TIMESTAMP HOST APP PROCID MSGID SD MSG`.
+struct Formatter {
+    format: SyslogFormat,
+    facility: Facility,
+    app_name: String,
+    proc_id: u32,
+}
+
+impl Formatter {
+    fn format(&self, record: &DetectionRecord) -> String {
+        let priority = self.facility as u8 * 8 + syslog_severity(record.severity);
+        let rule_type = record.rule_type.as_str();
+        let header = format!(
+            "<{priority}>1 {} {} {} {} {}",
+            record
+                .ingest_time
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+            header_field(&record.host, 255),
+            header_field(&self.app_name, 48),
+            self.proc_id,
+            header_field(rule_type, 32),
+        );
+        match self.format {
+            SyslogFormat::Rfc5424 => format!(
+                "{header} {} {}: {}",
+                structured_data(record, rule_type),
+                record.description,
+                record.matched_line
+            ),
+            SyslogFormat::Cef => format!("{header} - {}", cef(record, rule_type)),
+            SyslogFormat::Leef => format!("{header} - {}", leef(record, rule_type)),
+        }
+    }
+}
+
+/// A header field: printable ASCII without spaces, `-` when empty.
+fn header_field(value: &str, max_len: usize) -> String {
+    let field: String = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max_len)
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+fn sd_param(name: &str, value: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"'))
+        .take(32)
+        .collect();
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    format!(" {name}=\"{escaped}\"")
+}
+
+fn structured_data(record: &DetectionRecord, rule_type: &str) -> String {
+    let mut sd = format!("[{SD_ID}");
+    for (name, value) in [
+        ("rule", record.rule_id.as_str()),
+        ("ruleType", rule_type),
+        ("severity", record.severity.as_str()),
+        ("file", record.source.as_str()),
+        ("line", &record.line_number.to_string()),
+        ("id", record.id.as_str()),
+    ] {
+        sd.push_str(&sd_param(name, value));
+    }
+    if let Some(event_time) = record.event_time {
+        sd.push_str(&sd_param(
+            "eventTime",
+            &event_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        ));
+    }
+    sd.push(']');
+    if !record.fields.is_empty() {
+        sd.push_str(&format!("[{FIELDS_SD_ID}"));
+        for (name, value) in &record.fields {
+            sd.push_str(&sd_param(name, value));
+        }
+        sd.push(']');
+    }
+    sd
+}
+
+fn cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn cef_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+/// `CEF:0|vendor|product|version|signature|name|severity|extensions`
+fn cef(record: &DetectionRecord, rule_type: &str) -> String {
+    let mut extensions = vec![
+        format!("rt={}", record.ingest_time.timestamp_millis()),
+        format!("dvchost={}", cef_value(&record.host)),
+        format!("filePath={}", cef_value(&record.source)),
+        "cs1Label=rule".to_string(),
+        format!("cs1={}", cef_value(&record.rule_id)),
+        "cn1Label=line".to_string(),
+        format!("cn1={}", record.line_number),
+        format!("cat={rule_type}"),
+        format!("externalId={}", record.id),
+        format!("msg={}", cef_value(&record.matched_line)),
+    ];
+    if let Some(event_time) = record.event_time {
+        extensions.push(format!("start={}", event_time.timestamp_millis()));
+    }
+    format!(
+        "CEF:0|RustHound|RustHound|{}|{}|{}|{}|{}",
+        env!("CARGO_PKG_VERSION"),
+        cef_header(&record.rule_id),
+        cef_header(&record.description),
+        event_severity(record.severity),
+        extensions.join(" ")
+    )
+}
+
+fn leef_value(value: &str) -> String {
+    value.replace(['\t', '\r', '\n'], " ")
+}
+
+/// `LEEF:1.0|vendor|product|version|eventID|` and tab-separated attributes.
+fn leef(record: &DetectionRecord, rule_type: &str) -> String {
+    let mut attributes = vec![
+        format!("sev={}", event_severity(record.severity).max(1)),
+        format!("cat={rule_type}"),
+        format!("identHostName={}", leef_value(&record.host)),
+        format!("rule={}", leef_value(&record.rule_id)),
+        format!("file={}", leef_value(&record.source)),
+        format!("line={}", record.line_number),
+        format!("id={}", record.id),
+        format!("description={}", leef_value(&record.description)),
+        format!("msg={}", leef_value(&record.matched_line)),
+    ];
+    if let Some(event_time) = record.event_time {
+        attributes.push(format!("devTime={}", event_time.timestamp_millis()));
+        attributes.push("devTimeFormat=epoch".to_string());
+    }
+    format!(
+        "LEEF:1.0|RustHound|RustHound|{}|{}|{}",
+        env!("CARGO_PKG_VERSION"),
+        cef_header(&record.rule_id),
+        attributes.join("\t")
+    )
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Connection {
+    fn send(&mut self, message: &str, framing: SyslogFraming) -> std::io::Result<()> {
+        let stream: &mut dyn Write = match self {
+            Connection::Udp(socket) => {
+                let mut end = message.len().min(MAX_UDP_BYTES);
+                while !message.is_char_boundary(end) {
+                    end -= 1;
+                }
+                return socket.send(&message.as_bytes()[..end]).map(|_| ());
+            }
+            Connection::Tcp(stream) => stream,
+            Connection::Tls(stream) => stream.as_mut(),
+        };
+        match framing {
+            SyslogFraming::OctetCounting => write!(stream, "{} {message}", message.len())?,
+            SyslogFraming::Newline => writeln!(stream, "{}", message.replace('\n', " "))?,
+        }
+        stream.flush()
+    }
+}
+
+struct Transport {
+    name: String,
+    address: String,
+    protocol: SyslogProtocol,
+    framing: SyslogFraming,
+    timeout: Duration,
+    tls: Option<(Arc<ClientConfig>, ServerName<'static>)>,
+    connection: Option<Connection>,
+    /// No connect attempts before this time after a failed one
+    retry_at: Option<Instant>,
+    dropped: usize,
+}
+
+impl Transport {
+    fn new(name: &str, config: &SyslogConfig) -> anyhow::Result<Self> {
+        let host = match config.address.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() && !host.is_empty() => {
+                host.trim_start_matches('[').trim_end_matches(']')
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "syslog address \"{}\" must be host:port",
+                    config.address
+                ))
+            }
+        };
+        let tls = match config.protocol {
+            SyslogProtocol::Tls => {
+                let server_name = config.server_name.as_deref().unwrap_or(host).to_string();
+                let server_name = ServerName::try_from(server_name.clone())
+                    .map_err(|_| anyhow::anyhow!("invalid TLS server name \"{server_name}\""))?;
+                Some((tls_config(config.ca_file.as_deref())?, server_name))
+            }
+            _ => None,
+        };
+        Ok(Transport {
+            name: name.to_string(),
+            address: config.address.clone(),
+            protocol: config.protocol,
+            framing: config.framing,
+            timeout: Duration::from_secs(config.timeout_seconds.max(1)),
+            tls,
+            connection: None,
+            retry_at: None,
+            dropped: 0,
+        })
+    }
+
+    fn run(mut self, receiver: &Receiver<String>, overflow: &AtomicUsize) {
+        while let Ok(message) = receiver.recv() {
+            self.dropped += overflow.swap(0, Ordering::Relaxed);
+            self.deliver(&message);
+        }
+        self.dropped += overflow.swap(0, Ordering::Relaxed);
+        if let Some(Connection::Tls(stream)) = &mut self.connection {
+            // Closing with unread data (e.g. session tickets) resets the
+            // connection, which can lose the last messages at the receiver
+            stream.conn.send_close_notify();
+            let _ = stream.flush();
+            let _ = stream.sock.shutdown(std::net::Shutdown::Write);
+            let _ = stream.sock.set_read_timeout(Some(Duration::from_secs(1)));
+            let _ = std::io::copy(&mut stream.sock, &mut std::io::sink());
+        }
+        if self.dropped > 0 {
+            eprintln!(
+                "syslog \"{}\": {} message(s) to {} were dropped",
+                self.name, self.dropped, self.address
+            );
+        }
+    }
+
+    fn deliver(&mut self, message: &str) {
+        if self.retry_at.is_some_and(|at| Instant::now() < at) {
+            self.dropped += 1;
+            return;
+        }
+        // A broken TCP connection often only shows on the next write, so a
+        // failed send gets one more try on a fresh connection
+        for attempt in 0..2 {
+            if self.connection.is_none() {
+                match self.connect() {
+                    Ok(connection) => self.connection = Some(connection),
+                    Err(e) => {
+                        eprintln!(
+                            "syslog \"{}\": cannot connect to {}: {e:#}; retrying in {}s",
+                            self.name,
+                            self.address,
+                            RECONNECT_DELAY.as_secs()
+                        );
+                        self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+                        self.dropped += 1;
+                        return;
+                    }
+                }
+            }
+            let Some(connection) = &mut self.connection else {
+                return;
+            };
+            match connection.send(message, self.framing) {
+                Ok(()) => {
+                    self.retry_at = None;
+                    if self.dropped > 0 {
+                        eprintln!(
+                            "syslog \"{}\": {} message(s) to {} were dropped",
+                            self.name, self.dropped, self.address
+                        );
+                        self.dropped = 0;
+                    }
+                    return;
+                }
+                Err(e) => {
+                    self.connection = None;
+                    if attempt == 1 {
+                        eprintln!(
+                            "syslog \"{}\": sending to {} failed: {e}",
+                            self.name, self.address
+                        );
+                        self.dropped += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    fn connect(&self) -> anyhow::Result<Connection> {
+        let addresses: Vec<_> = self.address.to_socket_addrs()?.collect();
+        if self.protocol == SyslogProtocol::Udp {
+            let address = addresses
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("no address found"))?;
+            let local = if address.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(local)?;
+            socket.connect(address)?;
+            return Ok(Connection::Udp(socket));
+        }
+
+        let mut last_error = None;
+        let stream = addresses.iter().find_map(|address| {
+            TcpStream::connect_timeout(address, self.timeout)
+                .map_err(|e| last_error = Some(e))
+                .ok()
+        });
+        let stream = match stream {
+            Some(stream) => stream,
+            None => {
+                return Err(last_error
+                    .map(anyhow::Error::from)
+                    .unwrap_or_else(|| anyhow::anyhow!("no address found")))
+            }
+        };
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        match &self.tls {
+            Some((config, server_name)) => {
+                let connection = ClientConnection::new(config.clone(), server_name.clone())?;
+                let mut stream = StreamOwned::new(connection, stream);
+                // Handshake now, so certificate problems show up as connect errors
+                while stream.conn.is_handshaking() {
+                    stream
+                        .conn
+                        .complete_io(&mut stream.sock)
+                        .context("TLS handshake failed")?;
+                }
+                Ok(Connection::Tls(Box::new(stream)))
+            }
+            None => Ok(Connection::Tcp(stream)),
+        }
+    }
+}
+
+/// Trust `ca_file` (PEM) when given, else the public web roots.
+fn tls_config(ca_file: Option<&Path>) -> anyhow::Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            let certificates = CertificateDer::pem_file_iter(path)
+                .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| anyhow::anyhow!("cannot read CA file {}: {e}", path.display()))?;
+            if certificates.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "CA file {} holds no certificates",
+                    path.display()
+                ));
+            }
+            for certificate in certificates {
+                roots
+                    .add(certificate)
+                    .with_context(|| format!("invalid certificate in {}", path.display()))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::console::create_detection;
+    use chrono::{DateTime, Utc};
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn record() -> DetectionRecord {
+        let mut detection = create_detection(
+            "critical",
+            Path::new("/var/log/auth.log"),
+            12,
+            "2024-05-01T10:00:00Z sshd: bad \"user\" ] from 10.0.0.1",
+            "root|login",
+        );
+        detection
+            .fields
+            .insert("ioc.indicator".to_string(), "10.0.0.1".to_string());
+        let mut record = DetectionRecord::new(&detection, "web 1");
+        record.ingest_time = DateTime::parse_from_rfc3339("2024-05-01T10:00:01Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        record.id = "abc".to_string();
+        record
+    }
+
+    fn formatter(format: SyslogFormat) -> Formatter {
+        Formatter {
+            format,
+            facility: Facility::Local4,
+            app_name: "rusthound".to_string(),
+            proc_id: 42,
+        }
+    }
+
+    #[test]
+    fn formats_rfc5424_cef_and_leef() {
+        let header = "<161>1 2024-05-01T10:00:01.000000Z web_1 rusthound 42 pattern";
+        assert_eq!(
+            formatter(SyslogFormat::Rfc5424).format(&record()),
+            format!(
+                "{header} [rusthound@32473 rule=\"root|login\" ruleType=\"pattern\" severity=\"critical\" \
+                 file=\"/var/log/auth.log\" line=\"12\" id=\"abc\" eventTime=\"2024-05-01T10:00:00Z\"]\
+                 [fields@32473 ioc.indicator=\"10.0.0.1\"] Line matched rule \"root|login\": \
+                 2024-05-01T10:00:00Z sshd: bad \"user\" ] from 10.0.0.1"
+            )
+        );
+        assert_eq!(
+            formatter(SyslogFormat::Cef).format(&record()),
+            format!(
+                "{header} - CEF:0|RustHound|RustHound|{}|root\\|login|Line matched rule \"root\\|login\"|10|\
+                 rt=1714557601000 dvchost=web 1 filePath=/var/log/auth.log cs1Label=rule cs1=root|login \
+                 cn1Label=line cn1=12 cat=pattern externalId=abc \
+                 msg=2024-05-01T10:00:00Z sshd: bad \"user\" ] from 10.0.0.1 start=1714557600000",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        let leef = formatter(SyslogFormat::Leef).format(&record());
+        assert!(leef.starts_with(&format!(
+            "{header} - LEEF:1.0|RustHound|RustHound|{}|root\\|login|sev=10\tcat=pattern\t",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(leef.contains("\tfile=/var/log/auth.log\tline=12\t"));
+        assert!(leef.ends_with("\tdevTime=1714557600000\tdevTimeFormat=epoch"));
+    }
+
+    fn detection(rule: &str) -> Detection {
+        create_detection("high", Path::new("a.log"), 3, "line", rule)
+    }
+
+    #[test]
+    fn sends_over_udp_and_tcp() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sink = SyslogSink::new(
+            "udp",
+            &SyslogConfig::new(socket.local_addr().unwrap().to_string()),
+        )
+        .unwrap();
+        sink.write(&detection("udp_rule")).unwrap();
+        sink.finish(&RunSummary::default()).unwrap();
+        let mut buf = [0; 2048];
+        let n = socket.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..n]);
+        assert!(message.starts_with("<10>1 "), "{message}");
+        assert!(message.contains("rule=\"udp_rule\""));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = SyslogConfig::new(listener.local_addr().unwrap().to_string());
+        config.protocol = SyslogProtocol::Tcp;
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+        let sink = SyslogSink::new("tcp", &config).unwrap();
+        sink.write(&detection("first")).unwrap();
+        sink.write(&detection("second")).unwrap();
+        sink.finish(&RunSummary::default()).unwrap();
+        drop(sink);
+
+        let received = server.join().unwrap();
+        let mut rest = received.as_str();
+        let mut rules = Vec::new();
+        while let Some((length, tail)) = rest.split_once(' ') {
+            let length: usize = length.parse().unwrap();
+            let message = &tail[..length];
+            rules.push(
+                message
+                    .split("rule=\"")
+                    .nth(1)
+                    .unwrap()
+                    .split('"')
+                    .next()
+                    .unwrap(),
+            );
+            rest = &tail[length..];
+        }
+        assert_eq!(rules, vec!["first", "second"]);
+
+        assert!(SyslogSink::new("bad", &SyslogConfig::new("localhost")).is_err());
+    }
+
+    #[test]
+    fn sends_over_tls_trusting_a_local_ca() {
+        use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::CertifiedIssuer::self_signed(ca_params, ca_key).unwrap();
+        let server_key = rcgen::KeyPair::generate().unwrap();
+        let server_cert = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &ca)
+            .unwrap();
+
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let ca_file = dir.join("ca.pem");
+        std::fs::write(&ca_file, ca.pem()).unwrap();
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let server_config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![server_cert.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(server_key.serialize_der())),
+            )
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = SyslogConfig::new(listener.local_addr().unwrap().to_string());
+        config.protocol = SyslogProtocol::Tls;
+        config.framing = SyslogFraming::Newline;
+        config.server_name = Some("localhost".to_string());
+        config.ca_file = Some(ca_file);
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let connection = rustls::ServerConnection::new(Arc::new(server_config)).unwrap();
+            let mut stream = rustls::StreamOwned::new(connection, stream);
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let sink = SyslogSink::new("tls", &config).unwrap();
+        sink.write(&detection("over_tls")).unwrap();
+        sink.finish(&RunSummary::default()).unwrap();
+        drop(sink);
+        let received = server.join().unwrap();
+        assert!(received.starts_with("<10>1 "), "{received}");
+        assert!(received.contains("rule=\"over_tls\""));
+        assert!(received.ends_with('\n'));
+
+        config.ca_file = Some(dir.join("missing.pem"));
+        let error = SyslogSink::new("tls", &config).err().unwrap();
+        assert!(error.to_string().contains("cannot read CA file"));
+    }
+}
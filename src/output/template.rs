@@ -0,0 +1,76 @@
+use super::schema::{json_schema, DetectionRecord};
+
+/// Text with `{{name}}` placeholders for detection record fields, used for
+/// webhook bodies and exec arguments; `{{fields.user}}` reads an analyzer
+/// field.
+pub struct Template {
+    parts: Vec<TemplatePart>,
+    /// Escape values for use inside JSON strings
+    json_escape: bool,
+}
+
+enum TemplatePart {
+    Text(String),
+    Field(String),
+}
+
+impl Template {
+    pub fn parse(template: &str, json_escape: bool) -> anyhow::Result<Self> {
+        let schema = json_schema();
+        let known = schema["properties"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .map(|end| start + end)
+                .ok_or_else(|| anyhow::anyhow!("template has an unclosed \"{{{{\""))?;
+            if start > 0 {
+                parts.push(TemplatePart::Text(rest[..start].to_string()));
+            }
+            let name = rest[start + 2..end].trim();
+            if !name.starts_with("fields.") && !known.contains_key(name) {
+                return Err(anyhow::anyhow!(
+                    "template placeholder \"{{{{{name}}}}}\" is not a detection record field"
+                ));
+            }
+            parts.push(TemplatePart::Field(name.to_string()));
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+        Ok(Template { parts, json_escape })
+    }
+
+    pub fn render(&self, record: &DetectionRecord) -> String {
+        let value = serde_json::to_value(record).unwrap_or_default();
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => out.push_str(text),
+                TemplatePart::Field(name) => {
+                    let field = match name.strip_prefix("fields.") {
+                        Some(key) => value["fields"].get(key),
+                        None => value.get(name.as_str()),
+                    };
+                    let text = match field {
+                        None | Some(serde_json::Value::Null) => String::new(),
+                        Some(serde_json::Value::String(text)) => text.clone(),
+                        Some(other) => other.to_string(),
+                    };
+                    if self.json_escape {
+                        let quoted = serde_json::Value::String(text).to_string();
+                        out.push_str(&quoted[1..quoted.len() - 1]);
+                    } else {
+                        out.push_str(&text);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
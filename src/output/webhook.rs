@@ -0,0 +1,743 @@
+use super::schema::{host_name, DetectionRecord};
+use super::sink::{OutputSink, RunSummary};
+use super::template::Template;
+use super::Detection;
+use crate::config::rules::{ChatFormat, WebhookConfig};
+use anyhow::Context;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Longest wait between two retries, whatever the backoff doubled to.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Shortest wait before retrying a spool, for `batch_seconds = 0`.
+const MIN_REPLAY_INTERVAL: Duration = Duration::from_secs(1);
+/// Detections waiting for the webhook thread before `write` blocks.
+const QUEUE_CAPACITY: usize = 10_000;
+
+/// POSTs detections to an HTTP endpoint.
+///
+/// Requests are made by a background thread, so a slow or unreachable
+/// endpoint does not hold up reading: `write` only queues the detection,
+/// and blocks only once `QUEUE_CAPACITY` detections are waiting. The thread
+/// collects detections for `batch_seconds`, sends them as one request,
+/// retries failures with a doubling backoff and, when the endpoint stays
+/// down, appends the batch to the spool file. While the spool holds
+/// detections the thread tries it again every `batch_seconds`, new
+/// detections or not. A full spool drops its oldest detections.
+pub struct WebhookSink {
+    host: String,
+    /// Dropped on close, which tells the thread to send its last batch
+    sender: Mutex<Option<SyncSender<DetectionRecord>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WebhookSink {
+    pub fn new(name: &str, config: &WebhookConfig) -> anyhow::Result<Self> {
+        let body = WebhookBody::from_config(config)?;
+        if !config.url.starts_with("http://") && !config.url.starts_with("https://") {
+            return Err(anyhow::anyhow!(
+                "webhook url \"{}\" must start with http:// or https://",
+                config.url
+            ));
+        }
+        let worker = Worker::new(name, config, body);
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let handle = std::thread::Builder::new()
+            .name(format!("webhook-{name}"))
+            .spawn(move || worker.run(receiver))
+            .context("cannot start webhook thread")?;
+        Ok(WebhookSink {
+            host: host_name(),
+            sender: Mutex::new(Some(sender)),
+            worker: Mutex::new(Some(handle)),
+        })
+    }
+
+    /// Send what is queued and wait for the thread; retries included.
+    fn close(&self) {
+        drop(self.sender.lock().unwrap_or_else(|e| e.into_inner()).take());
+        let handle = self.worker.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(handle) = handle {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl OutputSink for WebhookSink {
+    fn write(&self, detection: &Detection) -> anyhow::Result<()> {
+        let record = DetectionRecord::new(detection, &self.host);
+        self.sender
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("webhook is closed"))?
+            .send(record)
+            .map_err(|_| anyhow::anyhow!("webhook thread has stopped"))
+    }
+
+    fn finish(&self, _summary: &RunSummary) -> anyhow::Result<()> {
+        self.close();
+        Ok(())
+    }
+}
+
+impl Drop for WebhookSink {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// How a batch of detections becomes a request body.
+pub enum WebhookBody {
+    /// A JSON array of detection records
+    Records,
+    /// `template` rendered for a single detection; templated webhooks send
+    /// one request per detection, since most templates are one JSON object
+    Template(Template),
+    /// A chat message showing each detection
+    Chat(ChatFormat),
+}
+
+impl WebhookBody {
+    fn from_config(config: &WebhookConfig) -> anyhow::Result<Self> {
+        match (&config.template, config.format) {
+            (Some(_), Some(_)) => Err(anyhow::anyhow!(
+                "a webhook takes either a template or a chat format, not both"
+            )),
+            (Some(template), None) => Ok(WebhookBody::Template(Template::parse(
+                template,
+                config.content_type.contains("json"),
+            )?)),
+            (None, Some(format)) => Ok(WebhookBody::Chat(format)),
+            (None, None) => Ok(WebhookBody::Records),
+        }
+    }
+
+    /// Most detections one request may carry, whatever `max_batch` says.
+    fn max_batch(&self) -> usize {
+        match self {
+            WebhookBody::Chat(format) => format.max_batch(),
+            WebhookBody::Template(_) => 1,
+            WebhookBody::Records => usize::MAX,
+        }
+    }
+
+    pub fn render(&self, records: &[DetectionRecord]) -> String {
+        match self {
+            WebhookBody::Records => serde_json::to_string(records).unwrap_or_default(),
+            WebhookBody::Template(template) => records
+                .iter()
+                .map(|record| template.render(record))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            WebhookBody::Chat(format) => format.render(records).to_string(),
+        }
+    }
+}
+
+/// Allows `per_minute` requests in any minute, refilling continuously.
+struct RateLimiter {
+    per_minute: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32, now: Instant) -> Self {
+        let per_minute = f64::from(per_minute.max(1));
+        RateLimiter {
+            per_minute,
+            tokens: per_minute,
+            updated: now,
+        }
+    }
+
+    /// Take a request slot, returning how long to wait before using it.
+    fn take(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_minute / 60.0).min(self.per_minute);
+        self.updated = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens * 60.0 / self.per_minute)
+        }
+    }
+}
+
+enum SendError {
+    /// Worth retrying: the endpoint is down, overloaded or timed out
+    Transient(String),
+    /// The endpoint rejected the request; sending it again will not help
+    Rejected(String),
+}
+
+struct Worker {
+    name: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    content_type: String,
+    batch_interval: Duration,
+    max_batch: usize,
+    max_retries: u32,
+    backoff: Duration,
+    agent: ureq::Agent,
+    body: WebhookBody,
+    limiter: Option<RateLimiter>,
+    spool: Option<PathBuf>,
+    max_spool_bytes: u64,
+}
+
+impl Worker {
+    fn new(name: &str, config: &WebhookConfig, body: WebhookBody) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(config.timeout_seconds.max(1))))
+            .http_status_as_error(false)
+            .build()
+            .into();
+        Worker {
+            name: name.to_string(),
+            url: config.url.clone(),
+            headers: config
+                .headers
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            content_type: config.content_type.clone(),
+            batch_interval: Duration::from_secs(config.batch_seconds),
+            max_batch: config.max_batch.clamp(1, body.max_batch()),
+            max_retries: config.max_retries,
+            backoff: Duration::from_millis(config.retry_backoff_ms),
+            agent,
+            body,
+            limiter: config
+                .rate_limit_per_minute
+                .map(|per_minute| RateLimiter::new(per_minute, Instant::now())),
+            spool: config.spool.clone(),
+            max_spool_bytes: config.max_spool_bytes,
+        }
+    }
+
+    fn run(mut self, receiver: Receiver<DetectionRecord>) {
+        let mut batch = Vec::new();
+        // Set while a batch is collecting or the spool waits for a retry
+        let mut deadline = self.next_replay();
+        loop {
+            let received = match deadline {
+                Some(deadline) => {
+                    receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(record) => {
+                    batch.push(record);
+                    deadline.get_or_insert_with(|| Instant::now() + self.batch_interval);
+                    if batch.len() >= self.max_batch {
+                        self.deliver(std::mem::take(&mut batch));
+                        deadline = self.next_replay();
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.deliver(std::mem::take(&mut batch));
+                    deadline = self.next_replay();
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.deliver(batch);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Send `batch`, after whatever the spool holds so order is kept.
+    fn deliver(&mut self, batch: Vec<DetectionRecord>) {
+        if !self.replay_spool() {
+            if !batch.is_empty() {
+                self.spool_batch(&batch);
+            }
+            return;
+        }
+        for chunk in batch.chunks(self.max_batch) {
+            match self.send_with_retries(chunk) {
+                Ok(()) => {}
+                Err(SendError::Rejected(e)) => eprintln!(
+                    "webhook \"{}\": dropping {} detection(s): {e}",
+                    self.name,
+                    chunk.len()
+                ),
+                Err(SendError::Transient(e)) => {
+                    eprintln!("webhook \"{}\": {e}", self.name);
+                    self.spool_batch(chunk);
+                }
+            }
+        }
+    }
+
+    /// When to try the spool again, if it still holds detections.
+    fn next_replay(&self) -> Option<Instant> {
+        let path = self.spool.as_ref()?;
+        let pending = std::fs::metadata(path).is_ok_and(|m| m.len() > 0);
+        pending.then(|| Instant::now() + self.batch_interval.max(MIN_REPLAY_INTERVAL))
+    }
+
+    /// Send the spooled detections; false if the endpoint is still down or
+    /// the spool cannot be read.
+    fn replay_spool(&mut self) -> bool {
+        let Some(path) = self.spool.clone() else {
+            return true;
+        };
+        let records = match read_spool(&path) {
+            Ok(records) => records,
+            Err(e) => {
+                eprintln!("webhook \"{}\": {e:#}", self.name);
+                return false;
+            }
+        };
+        if records.is_empty() {
+            return true;
+        }
+        let mut sent = 0;
+        for chunk in records.chunks(self.max_batch) {
+            match self.send_with_retries(chunk) {
+                Ok(()) => {}
+                Err(SendError::Rejected(e)) => eprintln!(
+                    "webhook \"{}\": dropping {} spooled detection(s): {e}",
+                    self.name,
+                    chunk.len()
+                ),
+                Err(SendError::Transient(e)) => {
+                    eprintln!("webhook \"{}\": {e}", self.name);
+                    break;
+                }
+            }
+            sent += chunk.len();
+        }
+        if let Err(e) = rewrite_spool(&path, &records[sent..]) {
+            eprintln!("webhook \"{}\": {e:#}", self.name);
+        }
+        sent == records.len()
+    }
+
+    fn spool_batch(&self, batch: &[DetectionRecord]) {
+        let Some(path) = &self.spool else {
+            eprintln!(
+                "webhook \"{}\": dropping {} detection(s); set spool to keep them",
+                self.name,
+                batch.len()
+            );
+            return;
+        };
+        match append_spool(path, batch, self.max_spool_bytes) {
+            Ok(0) => {}
+            Ok(dropped) => eprintln!(
+                "webhook \"{}\": spool {} is full; dropped the {dropped} oldest detection(s)",
+                self.name,
+                path.display()
+            ),
+            Err(e) => eprintln!(
+                "webhook \"{}\": dropping {} detection(s): {e:#}",
+                self.name,
+                batch.len()
+            ),
+        }
+    }
+
+    fn send_with_retries(&mut self, records: &[DetectionRecord]) -> Result<(), SendError> {
+        let body = self.body.render(records);
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match self.send(&body) {
+                Err(SendError::Transient(e)) if attempt < self.max_retries => {
+                    attempt += 1;
+                    eprintln!(
+                        "webhook \"{}\": {e}; retry {attempt} of {} in {}ms",
+                        self.name,
+                        self.max_retries,
+                        backoff.as_millis()
+                    );
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn send(&mut self, body: &str) -> Result<(), SendError> {
+        if let Some(limiter) = &mut self.limiter {
+            std::thread::sleep(limiter.take(Instant::now()));
+        }
+        let mut request = self
+            .agent
+            .post(&self.url)
+            .header("Content-Type", &self.content_type);
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
+        let response = request
+            .send(body)
+            .map_err(|e| SendError::Transient(format!("POST {} failed: {e}", self.url)))?;
+        let status = response.status().as_u16();
+        match status {
+            200..=299 => Ok(()),
+            429 | 500..=599 | 408 => Err(SendError::Transient(format!(
+                "POST {} returned {status}",
+                self.url
+            ))),
+            _ => Err(SendError::Rejected(format!(
+                "POST {} returned {status}",
+                self.url
+            ))),
+        }
+    }
+}
+
+fn read_spool(path: &Path) -> anyhow::Result<Vec<DetectionRecord>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.with_context(|| format!("cannot read {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => eprintln!("Skipping unreadable spool line in {}: {e}", path.display()),
+        }
+    }
+    Ok(records)
+}
+
+/// Append `records` to the spool. When that would grow it past
+/// `max_bytes`, the oldest detections are dropped until the newest fit;
+/// returns how many were dropped.
+fn append_spool(path: &Path, records: &[DetectionRecord], max_bytes: u64) -> anyhow::Result<usize> {
+    let lines = spool_lines(records)?;
+    let added: u64 = lines.iter().map(|line| line.len() as u64).sum();
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size + added <= max_bytes {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        return OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(lines.concat().as_bytes()))
+            .map(|()| 0)
+            .with_context(|| format!("cannot write {}", path.display()));
+    }
+
+    let mut kept = match std::fs::read_to_string(path) {
+        Ok(content) => content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| format!("{line}\n"))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+    };
+    kept.extend(lines);
+    let mut total: u64 = kept.iter().map(|line| line.len() as u64).sum();
+    let mut dropped = 0;
+    while total > max_bytes && dropped < kept.len() {
+        total -= kept[dropped].len() as u64;
+        dropped += 1;
+    }
+    write_spool(path, &kept[dropped..])?;
+    Ok(dropped)
+}
+
+fn rewrite_spool(path: &Path, records: &[DetectionRecord]) -> anyhow::Result<()> {
+    write_spool(path, &spool_lines(records)?)
+}
+
+/// Replace the spool with `lines` through a temporary file, removing it
+/// when there is nothing left.
+fn write_spool(path: &Path, lines: &[String]) -> anyhow::Result<()> {
+    if lines.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("cannot remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, lines.concat())
+        .with_context(|| format!("cannot write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("cannot replace {}", path.display()))
+}
+
+/// One JSON line per record, newline included.
+fn spool_lines(records: &[DetectionRecord]) -> anyhow::Result<Vec<String>> {
+    records
+        .iter()
+        .map(|record| Ok(serde_json::to_string(record)? + "\n"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::console::create_detection;
+    use std::collections::VecDeque;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    /// A local HTTP server that answers with `statuses` in turn (200 once
+    /// they run out) and keeps every request body.
+    fn stand_in(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        let mut statuses = VecDeque::from(statuses);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let body_start = loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(at) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break at + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |value| value.trim().parse().unwrap());
+                while request.len() < body_start + length {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request[body_start..]).into_owned());
+                let status = statuses.pop_front().unwrap_or(200);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                );
+            }
+        });
+        (url, bodies)
+    }
+
+    fn config(url: &str) -> WebhookConfig {
+        let mut config = WebhookConfig::new(url);
+        config.batch_seconds = 60;
+        config.retry_backoff_ms = 1;
+        config
+    }
+
+    fn detection(rule: &str) -> Detection {
+        create_detection("high", Path::new("auth.log"), 3, "login \"root\"", rule)
+    }
+
+    fn rule_ids(body: &str) -> Vec<String> {
+        let records: Vec<DetectionRecord> = serde_json::from_str(body).unwrap();
+        records.into_iter().map(|record| record.rule_id).collect()
+    }
+
+    #[test]
+    fn batches_detections_and_retries_failed_requests() {
+        let (url, bodies) = stand_in(vec![500]);
+        let mut config = config(&url);
+        config.max_batch = 2;
+        let sink = WebhookSink::new("hook", &config).unwrap();
+        for rule in ["a", "b", "c"] {
+            sink.write(&detection(rule)).unwrap();
+        }
+        sink.finish(&RunSummary::default()).unwrap();
+
+        let bodies = bodies.lock().unwrap();
+        let batches: Vec<_> = bodies.iter().map(|body| rule_ids(body)).collect();
+        assert_eq!(batches, vec![vec!["a", "b"], vec!["a", "b"], vec!["c"]]);
+    }
+
+    #[test]
+    fn spools_while_the_endpoint_is_down_and_replays_first() {
+        let scratch = tempfile::tempdir().unwrap();
+        let spool = scratch.path().join("spool.jsonl");
+
+        let (url, bodies) = stand_in(vec![503, 503]);
+        let mut config = config(&url);
+        config.max_retries = 1;
+        config.spool = Some(spool.clone());
+        let sink = WebhookSink::new("hook", &config).unwrap();
+        sink.write(&detection("a")).unwrap();
+        sink.write(&detection("b")).unwrap();
+        sink.finish(&RunSummary::default()).unwrap();
+        assert_eq!(bodies.lock().unwrap().len(), 2);
+        assert_eq!(read_spool(&spool).unwrap().len(), 2);
+
+        let (url, bodies) = stand_in(Vec::new());
+        config.url = url;
+        let sink = WebhookSink::new("hook", &config).unwrap();
+        sink.write(&detection("c")).unwrap();
+        sink.finish(&RunSummary::default()).unwrap();
+        let bodies = bodies.lock().unwrap();
+        let batches: Vec<_> = bodies.iter().map(|body| rule_ids(body)).collect();
+        assert_eq!(batches, vec![vec!["a", "b"], vec!["c"]]);
+        assert!(!spool.exists());
+    }
+
+    #[test]
+    fn replays_the_spool_without_new_detections() {
+        let scratch = tempfile::tempdir().unwrap();
+        let spool = scratch.path().join("spool.jsonl");
+        let record = DetectionRecord::new(&detection("a"), &host_name());
+        append_spool(&spool, &[record], u64::MAX).unwrap();
+
+        let (url, bodies) = stand_in(Vec::new());
+        let mut config = config(&url);
+        config.batch_seconds = 0;
+        config.spool = Some(spool.clone());
+        let sink = WebhookSink::new("hook", &config).unwrap();
+        let started = Instant::now();
+        while bodies.lock().unwrap().is_empty() && started.elapsed() < Duration::from_secs(10) {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        let batches: Vec<_> = bodies
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|body| rule_ids(body))
+            .collect();
+        assert_eq!(batches, vec![vec!["a"]]);
+        sink.finish(&RunSummary::default()).unwrap();
+        assert!(!spool.exists());
+        assert_eq!(bodies.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn full_spool_drops_the_oldest_detections() {
+        let scratch = tempfile::tempdir().unwrap();
+        let spool = scratch.path().join("spool.jsonl");
+        let host = host_name();
+        let records: Vec<_> = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|rule| DetectionRecord::new(&detection(rule), &host))
+            .collect();
+        let line_bytes = spool_lines(&records)
+            .unwrap()
+            .iter()
+            .map(|line| line.len() as u64)
+            .max()
+            .unwrap();
+        let max_bytes = 3 * line_bytes;
+
+        assert_eq!(append_spool(&spool, &records[..2], max_bytes).unwrap(), 0);
+        assert_eq!(append_spool(&spool, &records[2..], max_bytes).unwrap(), 1);
+        let spooled: Vec<_> = read_spool(&spool)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.rule_id)
+            .collect();
+        assert_eq!(spooled, vec!["b", "c", "d"]);
+
+        // A batch larger than the whole spool keeps its newest detections
+        assert_eq!(append_spool(&spool, &records, max_bytes).unwrap(), 4);
+        assert_eq!(read_spool(&spool).unwrap()[0].rule_id, "b");
+    }
+
+    #[test]
+    fn renders_templates_and_drops_rejected_requests() {
+        let (url, bodies) = stand_in(vec![400]);
+        let mut config = config(&url);
+        config.max_batch = 10;
+        config.template =
+            Some(r#"{"text": "{{severity}} {{rule_id}}: {{matched_line}}{{fields.user}}"}"#.into());
+        let sink = WebhookSink::new("hook", &config).unwrap();
+        for rule in ["rejected", "root_login", "sudo"] {
+            sink.write(&detection(rule)).unwrap();
+        }
+        sink.finish(&RunSummary::default()).unwrap();
+
+        // One request per detection; the rejected one is not retried
+        let texts: Vec<_> = bodies
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|body| {
+                let body: serde_json::Value = serde_json::from_str(body).unwrap();
+                body["text"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                "high rejected: login \"root\"",
+                "high root_login: login \"root\"",
+                "high sudo: login \"root\"",
+            ]
+        );
+
+        config.template = Some("{{rule}}".into());
+        let error = WebhookSink::new("hook", &config).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("\"{{rule}}\" is not a detection record field"));
+        config.url = "ftp://example.com".into();
+        assert!(WebhookSink::new("hook", &config).is_err());
+    }
+
+    #[test]
+    fn chat_formats_split_batches_to_fit_one_message() {
+        let (url, bodies) = stand_in(Vec::new());
+        let mut config = config(&url);
+        config.format = Some(ChatFormat::Discord);
+        let sink = WebhookSink::new("hook", &config).unwrap();
+        for rule in ["a", "b", "c", "d", "e", "f", "g"] {
+            sink.write(&detection(rule)).unwrap();
+        }
+        sink.finish(&RunSummary::default()).unwrap();
+
+        let embeds: Vec<usize> = bodies
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|body| {
+                let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+                payload["embeds"].as_array().unwrap().len()
+            })
+            .collect();
+        assert_eq!(embeds, vec![5, 2]);
+
+        config.template = Some("{{rule_id}}".into());
+        assert!(WebhookSink::new("hook", &config).is_err());
+    }
+
+    #[test]
+    fn rate_limiter_spreads_requests_over_the_minute() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(60, start);
+        for _ in 0..60 {
+            assert_eq!(limiter.take(start), Duration::ZERO);
+        }
+        assert_eq!(limiter.take(start), Duration::from_secs(1));
+        let later = start + Duration::from_secs(10);
+        assert_eq!(limiter.take(later), Duration::ZERO);
+    }
+}
@@ -11,21 +11,29 @@ pub async fn watch_file(path: PathBuf, tx: mpsc::Sender<PathBuf>) -> anyhow::Res
 
     println!("Watching file: {}", path.display());
 
-    tokio::spawn(async move {
-        for res in watcher_rx {
-            match res {
-                Ok(event) => {
-                    // We are only interested in file modifications
-                    if event.kind.is_modify() {
-                        if let Err(e) = tx.send(path.clone()).await {
-                            eprintln!("Error sending file path: {e}");
+    // The watcher stops when dropped, so it lives as long as the loop. The
+    // loop blocks on a std channel, so it runs on its own thread.
+    std::thread::Builder::new()
+        .name("watch".to_string())
+        .spawn(move || {
+            let _watcher = watcher;
+            for res in watcher_rx {
+                match res {
+                    Ok(event) => {
+                        // We are only interested in file modifications
+                        if event.kind.is_modify() {
+                            if let Err(e) = tx.blocking_send(path.clone()) {
+                                eprintln!("Error sending file path: {e}");
+                            }
                         }
                     }
+                    Err(e) => {
+                        crate::metrics::global().watcher_error();
+                        eprintln!("watch error: {e:?}");
+                    }
                 }
-                Err(e) => eprintln!("watch error: {e:?}"),
             }
-        }
-    });
+        })?;
 
     Ok(())
 }
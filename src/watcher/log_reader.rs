@@ -1,10 +1,20 @@
 use crate::analyzer::correlation_engine::CorrelationEngine;
 use crate::analyzer::frequency_tracker::FrequencyTracker;
+use crate::analyzer::geoip::GeoIp;
+use crate::analyzer::ioc::IocMatch;
+use crate::analyzer::network::NetworkSets;
+use crate::analyzer::new_values::{NewValueTracker, SeenValue};
 use crate::analyzer::pattern_matcher::PatternMatcher;
-use crate::config::rules::{CorrelatedRule, FrequencyRules};
-use crate::output::console::{create_detection, create_frequency_detection};
+use crate::analyzer::suppression::Suppressor;
+use crate::analyzer::travel::LoginTracker;
+use crate::config::rules::{CorrelatedRule, FrequencyRules, Rules};
+use crate::metrics;
+use crate::output::console::{create_detection, create_frequency_detection, create_ioc_detection};
+use crate::output::sink::Outputs;
 use crate::output::Detection;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader, SeekFrom};
 
@@ -12,156 +22,278 @@ use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader, SeekFrom};
 pub struct ScanState {
     pub frequency_tracker: Option<FrequencyTracker>,
     pub correlation_engine: CorrelationEngine,
+    /// Detections dropped so far, per suppress rule name
+    pub suppressed: HashMap<String, usize>,
+    pub shared: Arc<SharedState>,
 }
 
 impl ScanState {
     pub fn new(
         frequency_rules: &Option<FrequencyRules>,
         correlated_rules: &[CorrelatedRule],
-    ) -> Self {
+        networks: &NetworkSets,
+        shared: &Arc<SharedState>,
+    ) -> anyhow::Result<Self> {
         let frequency_tracker = frequency_rules.as_ref().map(|rules| {
             FrequencyTracker::new(rules.max_same_errors_per_minute, rules.time_window_seconds)
         });
-        ScanState {
+        Ok(ScanState {
             frequency_tracker,
-            correlation_engine: CorrelationEngine::new(correlated_rules.to_vec()),
-        }
+            correlation_engine: CorrelationEngine::new(correlated_rules.to_vec(), networks)?,
+            suppressed: HashMap::new(),
+            shared: Arc::clone(shared),
+        })
+    }
+}
+
+/// Analyzer state learned from every file of a run: login histories and
+/// known values. Each file's `ScanState` holds the same one, so it must only
+/// be updated from `MatchProcessor`, in line order.
+#[derive(Default)]
+pub struct SharedState {
+    pub login_tracker: LoginTracker,
+    pub new_value_tracker: NewValueTracker,
+}
+
+impl SharedState {
+    pub fn new(rules: &Rules, networks: &NetworkSets) -> anyhow::Result<Self> {
+        Ok(SharedState {
+            login_tracker: LoginTracker::new(&rules.travel_rules, &rules.sites, networks)?,
+            new_value_tracker: NewValueTracker::new(&rules.new_value_rules)?,
+        })
+    }
+
+    /// Write every changed history and set of known values to disk.
+    pub fn flush(&self) {
+        self.login_tracker.flush();
+        self.new_value_tracker.flush();
     }
 }
 
 pub async fn read_file_line_by_line(
     file_path: &Path,
     pattern_matcher: &PatternMatcher,
-    output_format: &str,
+    outputs: &Outputs,
     frequency_rules: &Option<FrequencyRules>,
     correlated_rules: &[CorrelatedRule],
-) -> anyhow::Result<(Vec<Detection>, ScanState)> {
-    let mut scan_state = ScanState::new(frequency_rules, correlated_rules);
-    let (_offset, _line_number, detections) = read_file_from_offset(
+    shared: &Arc<SharedState>,
+) -> anyhow::Result<ScanState> {
+    let mut scan_state = ScanState::new(
+        frequency_rules,
+        correlated_rules,
+        pattern_matcher.networks(),
+        shared,
+    )?;
+    read_file_from_offset(
         file_path,
         pattern_matcher,
-        output_format,
+        outputs,
         frequency_rules,
         &mut scan_state,
         0,
         0,
     )
     .await?;
-    Ok((detections, scan_state))
+    Ok(scan_state)
 }
 
 pub async fn read_file_from_offset(
     file_path: &Path,
     pattern_matcher: &PatternMatcher,
-    output_format: &str,
+    outputs: &Outputs,
     frequency_rules: &Option<FrequencyRules>,
     scan_state: &mut ScanState,
     mut offset: u64,
     mut current_line_number: usize,
-) -> anyhow::Result<(u64, usize, Vec<Detection>)> {
+) -> anyhow::Result<(u64, usize)> {
     let mut file = File::open(file_path).await?;
     file.seek(SeekFrom::Start(offset)).await?;
-    let reader = BufReader::new(file);
-    let mut lines = reader.lines();
-
-    let mut json_output_file: Option<std::fs::File> = None;
-    if output_format == "json" || output_format == "both" {
-        let output_path = file_path.with_extension("json");
-        json_output_file = Some(
-            std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&output_path)?,
+    let mut reader = BufReader::new(file);
+
+    eprintln!("Reading file: {}", file_path.display());
+    let mut processor = MatchProcessor::new(file_path, outputs, frequency_rules, pattern_matcher);
+
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        current_line_number += 1;
+        offset += read as u64;
+        metrics::global().lines_read(1, read as u64);
+        let line = decode_line(&buf);
+        processor.process_line(
+            scan_state,
+            current_line_number,
+            &line,
+            pattern_matcher.find_matches(&line),
+            pattern_matcher.find_ioc_matches(&line),
+            pattern_matcher.find_new_values(&line),
         );
     }
 
-    if offset == 0 && (output_format == "json" || output_format == "both") {
-        println!(
-            "Writing JSON output to: {}",
-            file_path.with_extension("json").display()
-        );
+    Ok((offset, current_line_number))
+}
+
+/// One line without its `\n` or `\r\n`. Invalid UTF-8 is replaced rather
+/// than failing the read, and counted as a parse error.
+pub(crate) fn decode_line(bytes: &[u8]) -> std::borrow::Cow<'_, str> {
+    let bytes = match bytes.strip_suffix(b"\n") {
+        Some(bytes) => bytes.strip_suffix(b"\r").unwrap_or(bytes),
+        None => bytes,
+    };
+    let line = String::from_utf8_lossy(bytes);
+    if let std::borrow::Cow::Owned(_) = line {
+        metrics::global().parse_error();
     }
+    line
+}
 
-    println!("Reading file: {}", file_path.display());
+/// Turns the rule matches of one file into detections, enriching them with
+/// GeoIP data and feeding them through suppress rules, the outputs and the
+/// stateful frequency, travel, new value and correlation engines.
+/// Detections of every kind reach the outputs.
+///
+/// Lines must be passed in file order; matching itself is stateless and may
+/// happen elsewhere (see `parallel_scan`). A suppressed match is dropped
+/// before it reaches the frequency tracker or correlation engine.
+pub(crate) struct MatchProcessor<'a> {
+    file_path: &'a Path,
+    outputs: &'a Outputs,
+    frequency_rules: &'a Option<FrequencyRules>,
+    pattern_matcher: &'a PatternMatcher,
+    suppressor: &'a Suppressor,
+    geoip: Option<&'a GeoIp>,
+}
 
-    let mut detections: Vec<Detection> = Vec::new();
+impl<'a> MatchProcessor<'a> {
+    pub(crate) fn new(
+        file_path: &'a Path,
+        outputs: &'a Outputs,
+        frequency_rules: &'a Option<FrequencyRules>,
+        pattern_matcher: &'a PatternMatcher,
+    ) -> Self {
+        MatchProcessor {
+            file_path,
+            outputs,
+            frequency_rules,
+            pattern_matcher,
+            suppressor: pattern_matcher.suppressor(),
+            geoip: pattern_matcher.geoip(),
+        }
+    }
 
-    while let Some(line) = lines.next_line().await? {
-        current_line_number += 1;
-        offset += (line.len() + 1) as u64;
-        if let Some((severity, pattern_name)) = pattern_matcher.check_for_patterns(&line) {
-            let detection = create_detection(
-                severity,
-                file_path,
-                current_line_number,
-                &line,
-                pattern_name,
-            );
-
-            if output_format == "console" || output_format == "both" {
-                detections.push(detection.clone());
-            }
+    pub(crate) fn process_line(
+        &mut self,
+        scan_state: &mut ScanState,
+        line_number: usize,
+        line: &str,
+        matches: Vec<(&str, &str)>,
+        ioc_matches: Vec<IocMatch<'_>>,
+        new_values: Vec<SeenValue<'_>>,
+    ) {
+        let file_path = self.file_path;
+        let pattern_matcher = self.pattern_matcher;
+        let rule_detections = matches.into_iter().map(|(severity, pattern_name)| {
+            let mut detection =
+                create_detection(severity, file_path, line_number, line, pattern_name);
+            pattern_matcher.add_captured_fields(&mut detection);
+            detection
+        });
+        let ioc_detections = ioc_matches
+            .iter()
+            .map(|ioc_match| create_ioc_detection(ioc_match, file_path, line_number, line));
 
-            if (output_format == "json" || output_format == "both") && json_output_file.is_some() {
-                let json_detection = crate::output::json_writer::AnomalyDetection {
-                    timestamp: chrono::Local::now().to_rfc3339(),
-                    severity: severity.to_string(),
-                    rule_name: pattern_name.to_string(),
-                    file_path: file_path.to_string_lossy().to_string(),
-                    line_number: current_line_number,
-                    matched_line: line.clone(),
-                    pattern: pattern_name.to_string(),
-                };
-                crate::output::json_writer::write_json_output(
-                    &json_detection,
-                    json_output_file.as_mut().unwrap(),
-                )?;
-            }
+        for detection in rule_detections.chain(ioc_detections) {
+            self.process_detection(scan_state, detection);
+        }
 
-            if let Some(tracker) = &mut scan_state.frequency_tracker {
-                if let Some(count) = tracker.track_event(pattern_name) {
-                    if output_format == "console" || output_format == "both" {
-                        detections.push(create_frequency_detection(
-                            pattern_name,
-                            count,
-                            frequency_rules.as_ref().unwrap().max_same_errors_per_minute,
-                            frequency_rules.as_ref().unwrap().time_window_seconds,
-                            file_path,
-                            current_line_number,
-                            &line,
-                        ));
-                    }
-                    if (output_format == "json" || output_format == "both")
-                        && json_output_file.is_some()
-                    {
-                        let json_detection = crate::output::json_writer::AnomalyDetection {
-                            timestamp: chrono::Local::now().to_rfc3339(),
-                            severity: "frequency".to_string(),
-                            rule_name: format!("Too many {pattern_name} errors"),
-                            file_path: file_path.to_string_lossy().to_string(),
-                            line_number: current_line_number,
-                            matched_line: line.clone(),
-                            pattern: pattern_name.to_string(),
-                        };
-                        crate::output::json_writer::write_json_output(
-                            &json_detection,
-                            json_output_file.as_mut().unwrap(),
-                        )?;
-                    }
-                }
+        for seen in &new_values {
+            let detection =
+                scan_state
+                    .shared
+                    .new_value_tracker
+                    .observe(seen, file_path, line_number, line);
+            if let Some(detection) = detection {
+                self.emit(scan_state, detection);
             }
+        }
+    }
 
-            if let Some(correlated_detection) =
-                scan_state.correlation_engine.add_detection(detection)
-            {
-                if output_format == "console" || output_format == "both" {
-                    detections.push(correlated_detection);
-                }
+    /// Run one rule or IOC detection through suppression and output, then
+    /// feed it to the frequency, travel and correlation engines.
+    fn process_detection(&mut self, scan_state: &mut ScanState, detection: Detection) {
+        let Some(detection) = self.emit(scan_state, detection) else {
+            return;
+        };
+
+        if let (Some(tracker), Some(frequency_rules)) =
+            (&mut scan_state.frequency_tracker, self.frequency_rules)
+        {
+            if let Some(count) = tracker.track_event(&detection.pattern_name) {
+                let frequency_detection = create_frequency_detection(
+                    &detection.pattern_name,
+                    count,
+                    frequency_rules.max_same_errors_per_minute,
+                    frequency_rules.time_window_seconds,
+                    self.file_path,
+                    detection.line_number,
+                    &detection.matched_line,
+                );
+                self.emit(scan_state, frequency_detection);
             }
         }
+
+        let travel_detections = scan_state
+            .shared
+            .login_tracker
+            .observe(&detection, self.geoip);
+        for travel_detection in travel_detections {
+            self.emit(scan_state, travel_detection);
+        }
+
+        if let Some(correlated_detection) = scan_state.correlation_engine.add_detection(detection) {
+            self.emit(scan_state, correlated_detection);
+        }
+    }
+
+    /// Enrich `detection` and, unless a suppress rule drops it, send it to
+    /// the outputs. Returns the kept detection for the engines that build
+    /// on it. A failing output does not stop the scan (see
+    /// `Outputs::write`).
+    fn emit(&mut self, scan_state: &mut ScanState, mut detection: Detection) -> Option<Detection> {
+        self.enrich(&mut detection);
+        if self.is_suppressed(scan_state, &detection) {
+            return None;
+        }
+        metrics::global().detection(&detection.pattern_name, detection.severity);
+        self.outputs.write(&detection);
+        Some(detection)
     }
 
-    Ok((offset, current_line_number, detections))
+    /// Attach `geo.*` fields when GeoIP databases are configured.
+    fn enrich(&self, detection: &mut Detection) {
+        if let Some(geoip) = self.geoip {
+            geoip.enrich(detection);
+        }
+    }
+
+    /// Check `detection` against the suppress rules, counting it if dropped.
+    fn is_suppressed(&self, scan_state: &mut ScanState, detection: &Detection) -> bool {
+        match self.suppressor.suppressed_by(detection) {
+            Some(rule_name) => {
+                metrics::global().suppressed(rule_name);
+                *scan_state
+                    .suppressed
+                    .entry(rule_name.to_string())
+                    .or_default() += 1;
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 /// Find all .log files in a directory
@@ -1,3 +1,4 @@
 // src/watcher/mod.rs
 pub mod file_watcher;
 pub mod log_reader;
+pub mod parallel_scan;
@@ -0,0 +1,542 @@
+use crate::analyzer::ioc::IocMatch;
+use crate::analyzer::new_values::SeenValue;
+use crate::analyzer::pattern_matcher::PatternMatcher;
+use crate::config::rules::{CorrelatedRule, FrequencyRules};
+use crate::metrics;
+use crate::output::sink::Outputs;
+use crate::watcher::log_reader::{decode_line, MatchProcessor, ScanState, SharedState};
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+
+/// Files larger than this are split into several chunks so one big file
+/// can keep more than one worker busy.
+pub const DEFAULT_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Chunks each worker may match ahead of the replay.
+const CHUNKS_AHEAD_PER_WORKER: usize = 2;
+
+/// How a multi-file scan is spread across threads.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanOptions {
+    /// Upper bound on matching threads.
+    pub workers: usize,
+    /// Target chunk size in bytes; chunks always end on a line boundary.
+    pub chunk_size: u64,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// Result of scanning one file, ready to continue in follow mode.
+pub struct FileScan {
+    pub path: PathBuf,
+    /// Byte offset just past the last line read.
+    pub offset: u64,
+    /// Number of the last line read.
+    pub line_number: usize,
+    pub scan_state: ScanState,
+}
+
+/// A line-aligned byte range of one file, the unit of work for a worker.
+struct Chunk {
+    file_index: usize,
+    range: Range<u64>,
+}
+
+/// A line with at least one rule or IOC match or new value rule value,
+/// numbered within its chunk.
+struct MatchedLine<'m> {
+    index: usize,
+    text: String,
+    matches: Vec<(&'m str, &'m str)>,
+    ioc_matches: Vec<IocMatch<'m>>,
+    new_values: Vec<SeenValue<'m>>,
+}
+
+struct ChunkMatches<'m> {
+    line_count: usize,
+    lines: Vec<MatchedLine<'m>>,
+}
+
+/// Scan `files` from the start using a bounded pool of worker threads.
+///
+/// Matching is stateless, so workers run `PatternMatcher` over line-aligned
+/// chunks in parallel. Each chunk is replayed through its file's frequency
+/// tracker and correlation engine as soon as the chunks before it are done,
+/// so stateful detections come out exactly as a line-by-line read would
+/// produce them, reaching `outputs` file by file. Workers stay at most
+/// `CHUNKS_AHEAD_PER_WORKER` chunks each ahead of the replay, which bounds
+/// the matches held in memory whatever the size of the scan.
+/// Results are returned in the order of `files`.
+pub fn scan_files(
+    files: &[PathBuf],
+    pattern_matcher: &PatternMatcher,
+    outputs: &Outputs,
+    frequency_rules: &Option<FrequencyRules>,
+    correlated_rules: &[CorrelatedRule],
+    shared: &Arc<SharedState>,
+    options: ScanOptions,
+) -> anyhow::Result<Vec<FileScan>> {
+    let mut chunks = Vec::new();
+    for (file_index, path) in files.iter().enumerate() {
+        for range in line_aligned_chunks(path, options.chunk_size)
+            .with_context(|| format!("Failed to split {:?} into chunks", path))?
+        {
+            chunks.push(Chunk { file_index, range });
+        }
+    }
+
+    let workers = options.workers.clamp(1, chunks.len().max(1));
+    let window = Window::new(workers * CHUNKS_AHEAD_PER_WORKER);
+    let next_chunk = AtomicUsize::new(0);
+    let (sender, results) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        let (chunks, window, next_chunk) = (&chunks, &window, &next_chunk);
+        for _ in 0..workers {
+            let sender = sender.clone();
+            scope.spawn(move || {
+                run_worker(files, chunks, next_chunk, window, pattern_matcher, sender)
+            });
+        }
+        drop(sender);
+        // Releases waiting workers however the replay ends
+        let _close = CloseOnDrop(window);
+
+        let mut waiting = HashMap::new();
+        let mut next = 0;
+        let mut scans: Vec<FileScan> = Vec::with_capacity(files.len());
+        for (file_index, path) in files.iter().enumerate() {
+            let mut scan_state = ScanState::new(
+                frequency_rules,
+                correlated_rules,
+                pattern_matcher.networks(),
+                shared,
+            )?;
+            eprintln!("Reading file: {}", path.display());
+            let mut processor =
+                MatchProcessor::new(path, outputs, frequency_rules, pattern_matcher);
+            let mut offset = 0;
+            let mut line_number = 0;
+
+            while let Some(chunk) = chunks.get(next).filter(|c| c.file_index == file_index) {
+                let chunk_matches = loop {
+                    if let Some(result) = waiting.remove(&next) {
+                        break result;
+                    }
+                    let (index, result) = results
+                        .recv()
+                        .context("a scan worker stopped before finishing its chunk")?;
+                    waiting.insert(index, result);
+                }?;
+                for matched in chunk_matches.lines {
+                    processor.process_line(
+                        &mut scan_state,
+                        line_number + matched.index + 1,
+                        &matched.text,
+                        matched.matches,
+                        matched.ioc_matches,
+                        matched.new_values,
+                    );
+                }
+                offset = chunk.range.end;
+                line_number += chunk_matches.line_count;
+                next += 1;
+                window.advance();
+            }
+
+            scans.push(FileScan {
+                path: path.clone(),
+                offset,
+                line_number,
+                scan_state,
+            });
+        }
+
+        Ok(scans)
+    })
+}
+
+/// How far the workers may run ahead of the replay.
+struct Window {
+    /// Chunks that may be matched but not yet replayed
+    size: usize,
+    /// Chunks replayed so far; None once the replay has ended
+    replayed: Mutex<Option<usize>>,
+    changed: Condvar,
+}
+
+impl Window {
+    fn new(size: usize) -> Self {
+        Window {
+            size,
+            replayed: Mutex::new(Some(0)),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Block until chunk `index` is inside the window. False if the replay
+    /// has ended and the chunk is no longer needed.
+    fn wait_for(&self, index: usize) -> bool {
+        let mut replayed = self.replayed.lock().unwrap();
+        loop {
+            match *replayed {
+                None => return false,
+                Some(done) if index < done + self.size => return true,
+                Some(_) => replayed = self.changed.wait(replayed).unwrap(),
+            }
+        }
+    }
+
+    fn advance(&self) {
+        if let Some(done) = self.replayed.lock().unwrap().as_mut() {
+            *done += 1;
+        }
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        *self.replayed.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        self.changed.notify_all();
+    }
+}
+
+struct CloseOnDrop<'w>(&'w Window);
+
+impl Drop for CloseOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Take chunks off the shared queue in order, each once it fits in the
+/// window, until the queue is empty or the replay has ended.
+fn run_worker<'m>(
+    files: &[PathBuf],
+    chunks: &[Chunk],
+    next_chunk: &AtomicUsize,
+    window: &Window,
+    pattern_matcher: &'m PatternMatcher,
+    results: mpsc::Sender<(usize, anyhow::Result<ChunkMatches<'m>>)>,
+) {
+    loop {
+        let index = next_chunk.fetch_add(1, Ordering::Relaxed);
+        let Some(chunk) = chunks.get(index) else {
+            return;
+        };
+        if !window.wait_for(index) {
+            return;
+        }
+        let path = &files[chunk.file_index];
+        let result = match_chunk(path, chunk.range.clone(), pattern_matcher)
+            .with_context(|| format!("Failed to scan {:?}", path));
+        if results.send((index, result)).is_err() {
+            return;
+        }
+    }
+}
+
+fn match_chunk<'m>(
+    path: &Path,
+    range: Range<u64>,
+    pattern_matcher: &'m PatternMatcher,
+) -> anyhow::Result<ChunkMatches<'m>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(range.start))?;
+    let mut text = Vec::new();
+    file.take(range.end - range.start).read_to_end(&mut text)?;
+
+    let mut line_count = 0;
+    let mut lines = Vec::new();
+    for (index, line) in text.split_inclusive(|&byte| byte == b'\n').enumerate() {
+        line_count += 1;
+        let line = decode_line(line);
+        let line = line.as_ref();
+        let matches = pattern_matcher.find_matches(line);
+        let ioc_matches = pattern_matcher.find_ioc_matches(line);
+        let new_values = pattern_matcher.find_new_values(line);
+        if !matches.is_empty() || !ioc_matches.is_empty() || !new_values.is_empty() {
+            lines.push(MatchedLine {
+                index,
+                text: line.to_string(),
+                matches,
+                ioc_matches,
+                new_values,
+            });
+        }
+    }
+
+    metrics::global().lines_read(line_count as u64, text.len() as u64);
+    Ok(ChunkMatches { line_count, lines })
+}
+
+/// Split a file into byte ranges of roughly `chunk_size`, each ending just
+/// after a newline (or at end of file).
+fn line_aligned_chunks(path: &Path, chunk_size: u64) -> anyhow::Result<Vec<Range<u64>>> {
+    let len = std::fs::metadata(path)?.len();
+    let chunk_size = chunk_size.max(1);
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut rest_of_line = Vec::new();
+
+    while start < len {
+        let target = start + chunk_size;
+        if target >= len {
+            ranges.push(start..len);
+            break;
+        }
+        reader.seek(SeekFrom::Start(target - 1))?;
+        rest_of_line.clear();
+        let read = reader.read_until(b'\n', &mut rest_of_line)? as u64;
+        let end = (target - 1 + read).min(len);
+        ranges.push(start..end);
+        start = end;
+    }
+
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::rules::{PatternConfig, RegexRule, Rules, TriggerRule};
+    use crate::output::sink::{OutputSink, SinkFilter};
+    use crate::output::{Detection, DetectionKind};
+    use crate::watcher::log_reader::{read_file_from_offset, read_file_line_by_line};
+
+    /// Keeps every detection it receives.
+    struct Collect(Arc<Mutex<Vec<Detection>>>);
+
+    impl OutputSink for Collect {
+        fn write(&self, detection: &Detection) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(detection.clone());
+            Ok(())
+        }
+    }
+
+    fn collecting_outputs() -> (Outputs, Arc<Mutex<Vec<Detection>>>) {
+        let detections = Arc::new(Mutex::new(Vec::new()));
+        let mut outputs = Outputs::default();
+        outputs.add(
+            "collect",
+            SinkFilter::default(),
+            Box::new(Collect(detections.clone())),
+        );
+        (outputs, detections)
+    }
+
+    #[test]
+    fn chunks_end_on_line_boundaries() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let path = dir.join("a.log");
+        std::fs::write(&path, "aaaa\nbb\ncccccc\nd").unwrap();
+
+        let ranges = line_aligned_chunks(&path, 3).unwrap();
+        assert_eq!(ranges, vec![0..5, 5..8, 8..15, 15..16]);
+        assert_eq!(line_aligned_chunks(&path, 100).unwrap(), vec![0..16]);
+
+        std::fs::write(&path, "").unwrap();
+        assert!(line_aligned_chunks(&path, 3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn workers_wait_for_the_replay_to_catch_up() {
+        let window = Window::new(2);
+        assert!(window.wait_for(0));
+        assert!(window.wait_for(1));
+
+        std::thread::scope(|scope| {
+            let ahead = scope.spawn(|| window.wait_for(2));
+            let abandoned = scope.spawn(|| window.wait_for(4));
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!ahead.is_finished());
+            window.advance();
+            assert!(ahead.join().unwrap());
+            window.close();
+            assert!(!abandoned.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn matches_sequential_scan_across_chunks_and_workers() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let rules = Rules {
+            patterns: PatternConfig {
+                error_patterns: vec!["ERROR".into()],
+                ..Default::default()
+            },
+            regex_rules: vec![
+                RegexRule {
+                    name: "auth_failure".to_string(),
+                    pattern: "Failed password".to_string(),
+                    severity: "high".to_string(),
+                    ..Default::default()
+                },
+                RegexRule {
+                    name: "login".to_string(),
+                    pattern: "Accepted password".to_string(),
+                    severity: "info".to_string(),
+                    ..Default::default()
+                },
+            ],
+            frequency_rules: Some(FrequencyRules {
+                max_same_errors_per_minute: 3,
+                time_window_seconds: 60,
+            }),
+            correlated_rules: vec![CorrelatedRule {
+                name: "brute_force".to_string(),
+                severity: "critical".to_string(),
+                time_window_seconds: 60,
+                trigger_on_rule: TriggerRule {
+                    name: "auth_failure".to_string(),
+                    count: 3,
+                },
+                followed_by: "login".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let matcher = PatternMatcher::new(&rules).unwrap();
+
+        let mut files = Vec::new();
+        for file in 0..3 {
+            let mut log = String::new();
+            for i in 0..200 {
+                log.push_str(&match i % 7 {
+                    0 | 2 | 4 => format!("sshd[{file}]: Failed password for user{i}\n"),
+                    5 => format!("sshd[{file}]: Accepted password for user{i}\n"),
+                    6 => format!("app[{file}]: ERROR request {i} failed\n"),
+                    _ => format!("app[{file}]: request {i} ok\n"),
+                });
+            }
+            let path = dir.join(format!("{file}.log"));
+            std::fs::write(&path, log).unwrap();
+            files.push(path);
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (outputs, expected) = collecting_outputs();
+        for path in &files {
+            runtime
+                .block_on(read_file_line_by_line(
+                    path,
+                    &matcher,
+                    &outputs,
+                    &rules.frequency_rules,
+                    &rules.correlated_rules,
+                    &Arc::default(),
+                ))
+                .unwrap();
+        }
+        let expected = expected.lock().unwrap().clone();
+        assert!(expected
+            .iter()
+            .any(|d| d.kind == DetectionKind::Correlation));
+
+        let (outputs, detections) = collecting_outputs();
+        let scans = scan_files(
+            &files,
+            &matcher,
+            &outputs,
+            &rules.frequency_rules,
+            &rules.correlated_rules,
+            &Arc::default(),
+            ScanOptions {
+                workers: 4,
+                chunk_size: 256,
+            },
+        )
+        .unwrap();
+
+        let summarize = |detections: &[Detection]| -> Vec<(String, usize, String, String)> {
+            detections
+                .iter()
+                .map(|d| {
+                    (
+                        d.file_path.clone(),
+                        d.line_number,
+                        d.pattern_name.clone(),
+                        d.matched_line.clone(),
+                    )
+                })
+                .collect()
+        };
+        for (scan, path) in scans.iter().zip(&files) {
+            assert_eq!(&scan.path, path);
+            assert_eq!(scan.line_number, 200);
+            assert_eq!(scan.offset, std::fs::metadata(path).unwrap().len());
+        }
+        assert_eq!(summarize(&detections.lock().unwrap()), summarize(&expected));
+    }
+
+    #[test]
+    fn reads_crlf_and_invalid_utf8_lines_like_the_follow_reader() {
+        let scratch = tempfile::tempdir().unwrap();
+        let dir = scratch.path();
+        let path = dir.join("mixed.log");
+        std::fs::write(&path, b"ok\r\nFailed password \xff root\r\nlast").unwrap();
+        let rules = Rules {
+            regex_rules: vec![RegexRule {
+                name: "auth_failure".to_string(),
+                pattern: "Failed password .* root$".to_string(),
+                severity: "high".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let matcher = PatternMatcher::new(&rules).unwrap();
+
+        let (outputs, followed) = collecting_outputs();
+        let mut state = ScanState::new(&None, &[], matcher.networks(), &Arc::default()).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (offset, lines) = runtime
+            .block_on(read_file_from_offset(
+                &path, &matcher, &outputs, &None, &mut state, 0, 0,
+            ))
+            .unwrap();
+        assert_eq!(offset, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(lines, 3);
+
+        let (outputs, scanned) = collecting_outputs();
+        let scans = scan_files(
+            &[path],
+            &matcher,
+            &outputs,
+            &None,
+            &[],
+            &Arc::default(),
+            ScanOptions {
+                workers: 2,
+                chunk_size: 8,
+            },
+        )
+        .unwrap();
+        assert_eq!((scans[0].offset, scans[0].line_number), (offset, lines));
+
+        for detections in [followed, scanned] {
+            let detections = detections.lock().unwrap();
+            assert_eq!(detections.len(), 1);
+            assert_eq!(detections[0].line_number, 2);
+            assert_eq!(detections[0].matched_line, "Failed password \u{fffd} root");
+        }
+    }
+}